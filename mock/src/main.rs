@@ -4,18 +4,18 @@ extern crate wasmer;
 extern crate wasmtime_provider;
 
 extern crate base64;
+extern crate mockhost;
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
-extern crate json_dotpath;
-extern crate snailquote;
 use std::sync::{Arc};
 
-use elvwasm::ErrorKinds;
+use elvwasm::{ErrorKinds, Request};
+use mockhost::{MockFabric, QFile, Version};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
-use json_dotpath::DotPaths;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use wasmer::{imports, Store, Universal};
 use wasmer_compiler_cranelift::Cranelift;
@@ -25,16 +25,49 @@ use wapc::WapcHost;
 
 use serde_derive::{Deserialize, Serialize};
 
-static mut QFAB: MockFabric = MockFabric{
-    fab : None
-};
-
+/// The scenario a run starts from: the fabric's content and the call made to the bitcode, along with
+/// what the bitcode reads from its `fis` stream and a fixed SystemTime for reproducible output
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RootMockFabric {
   pub library:Library,
   pub call:serde_json::Value,
+  #[serde(default)]
+  pub fis: Option<Content>,
+  #[serde(default)]
+  pub time: Option<u64>,
+  #[serde(skip)]
+  dir: PathBuf,
+}
+
+/// Bytes given inline as `{"text" : ...}` or `{"base64" : ...}`, or as a `{"file" : ...}` relative to the
+/// scenario file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Content {
+  Text(String),
+  Base64(String),
+  File(PathBuf),
+}
+
+impl Content {
+    fn bytes(&self, dir: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Content::Text(t) => Ok(t.as_bytes().to_vec()),
+            Content::Base64(b) => Ok(base64::decode(b)?),
+            Content::File(f) => Ok(std::fs::read(dir.join(f))?),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileContent {
+  #[serde(flatten)]
+  pub content: Content,
+  #[serde(default)]
+  pub mime: String,
 }
 
+/// A content object with its finalized version and, when write_token is set, a write token open on it
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Object {
   pub hash: String,
@@ -42,8 +75,15 @@ pub struct Object {
   pub qlib_id: String,
   #[serde(rename = "type")]
   pub qtype: String,
+  #[serde(default)]
   pub write_token: String,
-  pub meta : serde_json::Map<String, serde_json::Value>
+  pub meta : serde_json::Map<String, serde_json::Value>,
+  /// files by path
+  #[serde(default)]
+  pub files: BTreeMap<String, FileContent>,
+  /// parts by part hash
+  #[serde(default)]
+  pub parts: BTreeMap<String, Content>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -52,128 +92,127 @@ pub struct Library {
   pub objects: std::vec::Vec<Object>,
 }
 
-#[derive(Serialize, Deserialize,  Clone, Debug)]
-pub struct MockFabric{
-    fab : Option<RootMockFabric>
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct JPCRequest {
-  pub jpc: String,
-  pub params: serde_json::Map<String, serde_json::Value>
+impl Object {
+    fn to_mock(&self, qlib_id: &str, dir: &Path) -> Result<mockhost::Object, Box<dyn std::error::Error + Send + Sync>> {
+        if self.hash.is_empty() || self.hash == self.write_token {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "object {} needs a hash distinct from its write token",
+                self.id
+            ))));
+        }
+        let mut version = Version {
+            hash: self.hash.clone(),
+            meta: serde_json::Value::Object(self.meta.clone()),
+            ..Default::default()
+        };
+        for (path, f) in &self.files {
+            let data = f.content.bytes(dir)?;
+            version.files.insert(path.clone(), QFile { data, mime: f.mime.clone() });
+        }
+        for (qphash, p) in &self.parts {
+            version.parts.insert(qphash.clone(), p.bytes(dir)?);
+        }
+        let mut drafts = BTreeMap::new();
+        if !self.write_token.is_empty() {
+            drafts.insert(
+                self.write_token.clone(),
+                Version {
+                    hash: self.write_token.clone(),
+                    ..version.clone()
+                },
+            );
+        }
+        let qlib_id = match self.qlib_id.as_str() {
+            "" => qlib_id,
+            q => q,
+        };
+        Ok(mockhost::Object {
+            id: self.id.clone(),
+            qlib_id: qlib_id.to_string(),
+            qtype: self.qtype.clone(),
+            versions: vec![version],
+            drafts,
+        })
+    }
 }
 
-impl MockFabric{
-    pub fn init(& mut self, path_to_json:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+impl RootMockFabric {
+    pub fn load(path_to_json: &str) -> Result<RootMockFabric, Box<dyn std::error::Error + Send + Sync>> {
         let file = File::open(path_to_json)?;
         let reader = BufReader::new(file);
-
-        // Read the JSON contents of the file as an instance of `User`.
-        let json_rep:RootMockFabric = serde_json::from_reader(reader)?;
-        self.fab = Some(json_rep);
-        return Ok("DONE".as_bytes().to_vec())
+        let mut root: RootMockFabric = serde_json::from_reader(reader)?;
+        root.dir = Path::new(path_to_json).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        Ok(root)
     }
-    pub fn write_stream(&self, _json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in WriteStream");
-        Ok("Not Implemented".as_bytes().to_vec())
-    }
-    pub fn sqmd_delete(&self, json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in SQMD delete");
-        let j:JPCRequest = serde_json::from_str(json_rep)?;
-        let path = j.params["path"].to_string();
-        if  !path.is_empty(){
-            let mut fab = self.fab.clone().unwrap();
-            let p = &snailquote::unescape(&path).unwrap();
-            let pp:String = p.chars().map(|x| match x {
-                '/' => '.',
-                _ => x
-            }).collect();
-            fab.library.objects[0].meta.dot_remove(&pp[1..])?;//{
-            return Ok("DONE".as_bytes().to_vec())
-        }else{
-            println!("failed to find path argument");
+
+    /// fabric seeds a mock fabric with the scenario's objects, calls resolve against the one the call's
+    /// qinfo names
+    pub fn fabric(&self) -> Result<MockFabric, Box<dyn std::error::Error + Send + Sync>> {
+        let mut fab = MockFabric::new();
+        fab.add_library(&self.library.id);
+        for o in &self.library.objects {
+            fab.add_object(o.to_mock(&self.library.id, &self.dir)?);
         }
-        Ok("FAILED".as_bytes().to_vec())
-    }
-    pub fn sqmd_set(&self, json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in SQMD set");
-        let j:JPCRequest = serde_json::from_str(json_rep)?;
-        let path = j.params["path"].to_string();
-        let meta = j.params["meta"].to_string();
-        if !path.is_empty(){
-            let mut fab = self.fab.clone().unwrap();
-            let p = &snailquote::unescape(&path).unwrap();
-            let pp:String = p.chars().map(|x| match x {
-                '/' => '.',
-                _ => x
-            }).collect();
-            fab.library.objects[0].meta.dot_set(&pp[1..], meta)?;
-            return Ok("DONE".as_bytes().to_vec())
-
-        }else{
-            println!("failed to find path argument");
+        if let Some(fis) = &self.fis {
+            fab.set_input(&fis.bytes(&self.dir)?);
         }
-        Ok("FAILED".as_bytes().to_vec())
-    }
-    pub fn sqmd_get(&self, json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in SQMD get");
-        let j:JPCRequest = serde_json::from_str(json_rep)?;
-        let path = j.params["path"].to_string();
-        if !path.is_empty(){
-            let fab = self.fab.clone().unwrap();
-            let p = &snailquote::unescape(&path).unwrap();
-            let pp:String = p.chars().map(|x| match x {
-                '/' => '.',
-                _ => x
-            }).collect();
-            let gotten:Option<serde_json::Value> = fab.library.objects[0].meta.dot_get(&pp[1..])?;
-            let ret = gotten.unwrap();
-            println!("sqmd_get returning = {}", ret);
-            return Ok(ret.to_string().as_bytes().to_vec())
-        }else{
-            println!("failed to find path argument");
+        if let Some(time) = self.time {
+            fab.set_time(time);
+        }
+        let request: Request = serde_json::from_value(self.call.clone())?;
+        let qhot = request.q_info.qhot();
+        if fab.content(&qhot).is_none() && fab.content(&request.q_info.id).is_none() {
+            println!("warning: the call's qinfo names no object of the scenario");
         }
-        Ok("FAILED".as_bytes().to_vec())
+        fab.set_current(&request.q_info);
+        fab.stub("ProxyHttp", |_| {
+            let to_encode = r#"{"url" : {"type" : "application/json"}} "#.as_bytes();
+            Ok(serde_json::json!(base64::encode(to_encode)))
+        });
+        Ok(fab)
     }
-    pub fn proxy_http(&self, _json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in ProxyHttp");
-        let to_encode = r#"{"url" : {"type" : "application/json"}} "#.as_bytes();
-        let enc = base64::encode(to_encode);
-        Ok(format!(r#"{{"result": "{}"}}"#, enc).as_bytes().to_vec())
+
+    /// print_state prints the versions and open write tokens of the scenario's objects
+    pub fn print_state(&self) {
+        mockhost::with_fabric(|fab| {
+            for o in &self.library.objects {
+                if let Some(obj) = fab.object(&o.id) {
+                    for v in obj.versions.iter().chain(obj.drafts.values()) {
+                        println!("{} {} meta = {}", obj.id, v.hash, v.meta);
+                    }
+                }
+            }
+        })
     }
-    pub fn callback(&self, _json_rep:&str) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        println!("in callback");
-        let to_encode = r#"{"url" : {"type" : "application/json"}} "#.as_bytes();
-        let enc = base64::encode(to_encode);
-        Ok(format!(r#"{{"result": "{}"}}"#, enc).as_bytes().to_vec())
+}
+
+/// check_output writes what the bitcode wrote to `fos` to `output` and compares it with `expected`
+pub fn check_output(output: Option<&Path>, expected: Option<&Path>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let fos = mockhost::with_fabric(|fab| fab.output().to_vec());
+    println!("fos captured {} bytes", fos.len());
+    if let Some(path) = output {
+        std::fs::write(path, &fos)?;
     }
-    pub fn host_callback(i_cb:u64, id:&str, context:&str, method:&str, pkg:&[u8])-> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
-        let s_pkg = std::str::from_utf8(pkg)?;
-        println!("In host callback, values i_cb = {} id = {} method = {} context = {}, pkg = {}", i_cb, id,method,context, s_pkg);
-        match method {
-            "SQMDGet" =>{
-               unsafe{ QFAB.sqmd_get(s_pkg) }
-            }
-            "SQMDSet" =>{
-                unsafe{ QFAB.sqmd_set(s_pkg) }
-             }
-            "SQMDDelete" =>{
-                unsafe{ QFAB.sqmd_delete(s_pkg) }
-             }
-            "Write" => {
-                unsafe{ QFAB.write_stream(s_pkg) }
-            }
-            "Callback" => {
-                unsafe{ QFAB.callback(s_pkg) }
-            }
-            "ProxyHttp" => {
-                unsafe{ QFAB.proxy_http(s_pkg) }
-            }
-            _ => {
-                Err(Box::new(ErrorKinds::NotExist("Method not handled")))
-            }
+    if let Some(path) = expected {
+        let want = std::fs::read(path)?;
+        if want != fos {
+            let at = want.iter().zip(fos.iter()).take_while(|(a, b)| a == b).count();
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "fos differs from {} at byte {at}, got {} bytes expected {}",
+                path.display(),
+                fos.len(),
+                want.len()
+            ))));
         }
+        println!("fos matches {}", path.display());
     }
+    Ok(())
+}
+
+pub fn host_callback(i_cb:u64, id:&str, context:&str, method:&str, pkg:&[u8])-> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>{
+    println!("In host callback, values i_cb = {} id = {} method = {} context = {}, pkg = {}", i_cb, id, method, context, String::from_utf8_lossy(pkg));
+    mockhost::with_fabric(|fab| fab.host_call(id, context, method, pkg))
+        .map_err(|e| Box::new(ErrorKinds::Other(e)) as Box<dyn std::error::Error + Send + Sync>)
 }
 
 struct WasmerHolder{
@@ -210,12 +249,21 @@ struct Opt {
     #[structopt(parse(from_os_str))]
     fabric: PathBuf,
 
+    /// Write the captured fos stream to this file
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Fail unless the captured fos stream matches this file
+    #[structopt(short, long, parse(from_os_str))]
+    expected: Option<PathBuf>,
+
 }
 
 pub fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("In main");
     let opt = Opt::from_args();
-    unsafe{QFAB.init(&opt.fabric.into_os_string().into_string().unwrap())?;}
+    let root = RootMockFabric::load(&opt.fabric.into_os_string().into_string().unwrap())?;
+    mockhost::install(root.fabric()?);
     let module_wat = std::fs::read(&opt.input.into_os_string().into_string().unwrap())?;
     let h;
     if opt.mode == "wasmer"{
@@ -226,15 +274,17 @@ pub fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let import_object = imports! {};
         let instance = wasmer::Instance::new(&wasmer_mod, &import_object)?;
         let wasm_holder = WasmerHolder{_instance:instance};
-        let host = wapc::WapcHost::new(Box::new(wasm_holder), MockFabric::host_callback)?;
+        let host = wapc::WapcHost::new(Box::new(wasm_holder), host_callback)?;
         h = Some(host);
     }else{
         let engine = WasmtimeEngineProvider::new(&module_wat, None);
-        let host = WapcHost::new(Box::new(engine), MockFabric::host_callback)?;
+        let host = WapcHost::new(Box::new(engine), host_callback)?;
         h = Some(host)
     }
 
-    h.unwrap().call("_JPC", &serde_json::to_vec(unsafe{&QFAB.fab.clone().unwrap().call}).unwrap())?;
-    Ok(())
+    let res = h.unwrap().call("_JPC", &serde_json::to_vec(&root.call)?)?;
+    println!("result = {}", String::from_utf8_lossy(&res));
+    root.print_state();
+    check_output(opt.output.as_deref(), opt.expected.as_deref())
 }
 
@@ -0,0 +1,1101 @@
+//! In-memory content fabric <br>
+//! [MockFabric] keeps libraries, content objects with their finalized versions and open write tokens,
+//! metadata, files, parts, bitcode streams and state stores in memory and answers the host calls a
+//! [elvwasm::BitcodeContext] makes.  Calls are resolved against the content of the request being run (its
+//! [QInfo]) unless they name another one.  Operations the fabric does not emulate, such as the search or
+//! http extensions, can be answered by a [Stub].
+//! ```rust
+//! use mockhost::MockFabric;
+//! use serde_json::json;
+//! let mut fab = MockFabric::new();
+//! let qinfo = fab.create_content("ilib1", "hq__type", json!({"title" : "old man"}));
+//! let draft = fab.edit(&qinfo.id).unwrap();
+//! fab.add_file(&draft.write_token, "/assets/a.txt", b"hello", "text/plain").unwrap();
+//! let done = fab.finalize(&draft.write_token).unwrap();
+//! assert_eq!(fab.file(&done.hash, "/assets/a.txt").unwrap().data, b"hello");
+//! assert_eq!(fab.versions(&qinfo.id).len(), 2);
+//! ```
+
+extern crate md5;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+
+use elvwasm::{make_json_error, ErrorKinds, QInfo, QPart, QRef, SizeStats, Q};
+
+use md5::Md5;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// the stream a handler reads its request body from
+pub const INPUT_STREAM: &str = "fis";
+/// the stream a handler writes its response body to
+pub const OUTPUT_STREAM: &str = "fos";
+
+/// Stub answers a host operation in place of the fabric, receiving the call's parameters
+pub type Stub = Box<dyn FnMut(&Value) -> Result<Value, ErrorKinds>>;
+
+/// A file of a content version
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QFile {
+    pub data: Vec<u8>,
+    pub mime: String,
+}
+
+/// The state of a content object at one version, or in an open write token
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Version {
+    /// the version hash, or the write token of a draft
+    pub hash: String,
+    pub meta: Value,
+    pub files: BTreeMap<String, QFile>,
+    pub parts: BTreeMap<String, Vec<u8>>,
+}
+
+impl Version {
+    fn new(hash: &str, meta: Value) -> Version {
+        Version {
+            hash: hash.to_string(),
+            meta,
+            ..Default::default()
+        }
+    }
+}
+
+/// A content object with its finalized versions, oldest first, and open write tokens
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    pub id: String,
+    pub qlib_id: String,
+    pub qtype: String,
+    pub versions: Vec<Version>,
+    pub drafts: BTreeMap<String, Version>,
+}
+
+/// A bitcode stream, file streams also carry the name of their fabric file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stream {
+    pub data: Vec<u8>,
+    /// read position of Read and Reader
+    pub pos: usize,
+    pub file_name: Option<String>,
+    pub closed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub level: String,
+    pub msg: String,
+}
+
+/// A host call made through the fabric, stream reads and writes included
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostCall {
+    pub ns: String,
+    pub op: String,
+    pub params: Value,
+}
+
+// where a hash or write token resolved to
+enum Slot {
+    Version(usize),
+    Draft(String),
+}
+
+/// MockFabric is an in-memory content fabric, see the [module documentation](self)
+pub struct MockFabric {
+    libraries: BTreeSet<String>,
+    objects: BTreeMap<String, Object>,
+    streams: BTreeMap<String, Stream>,
+    qss: BTreeMap<String, BTreeMap<String, String>>,
+    stubs: HashMap<String, Stub>,
+    logs: Vec<LogEntry>,
+    callbacks: Vec<Value>,
+    calls: Vec<HostCall>,
+    current: QInfo,
+    time: u64,
+    next: u64,
+}
+
+impl Default for MockFabric {
+    fn default() -> Self {
+        MockFabric::new()
+    }
+}
+
+impl MockFabric {
+    /// new creates an empty fabric holding only the `fis` and `fos` streams
+    pub fn new() -> MockFabric {
+        let mut streams = BTreeMap::new();
+        streams.insert(INPUT_STREAM.to_string(), Stream::default());
+        streams.insert(OUTPUT_STREAM.to_string(), Stream::default());
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        MockFabric {
+            libraries: BTreeSet::new(),
+            objects: BTreeMap::new(),
+            streams,
+            qss: BTreeMap::new(),
+            stubs: HashMap::new(),
+            logs: vec![],
+            callbacks: vec![],
+            calls: vec![],
+            current: QInfo::default(),
+            time,
+            next: 0,
+        }
+    }
+
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next += 1;
+        format!("{prefix}{:04}", self.next)
+    }
+
+    // SEEDING
+
+    pub fn add_library(&mut self, qlib_id: &str) -> &mut Self {
+        self.libraries.insert(qlib_id.to_string());
+        self
+    }
+
+    /// add_object adds a content object under its own id, with a first version when it has none
+    pub fn add_object(&mut self, mut object: Object) -> QInfo {
+        self.libraries.insert(object.qlib_id.clone());
+        if object.versions.is_empty() {
+            let hash = self.next_id("hq__");
+            object.versions.push(Version::new(&hash, json!({})));
+        }
+        let qinfo = q_info(&object, object.versions.last().map(|v| v.hash.as_str()));
+        self.objects.insert(object.id.clone(), object);
+        qinfo
+    }
+
+    /// create_content creates a content object with one finalized version holding `meta`
+    pub fn create_content(&mut self, qlib_id: &str, qtype: &str, meta: Value) -> QInfo {
+        let hash = self.next_id("hq__");
+        let object = Object {
+            id: self.next_id("iq__"),
+            qlib_id: qlib_id.to_string(),
+            qtype: qtype.to_string(),
+            versions: vec![Version::new(&hash, meta)],
+            drafts: BTreeMap::new(),
+        };
+        self.add_object(object)
+    }
+
+    /// edit opens a write token on the latest version of a content object
+    pub fn edit(&mut self, qid: &str) -> Result<QInfo, ErrorKinds> {
+        let token = self.next_id("tqw__");
+        let object = self
+            .objects
+            .get_mut(qid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("content {qid} not found")))?;
+        let mut draft = object.versions.last().cloned().unwrap_or_default();
+        draft.hash = token.clone();
+        object.drafts.insert(token.clone(), draft);
+        let mut qinfo = q_info(object, object.versions.last().map(|v| v.hash.as_str()));
+        qinfo.write_token = token;
+        Ok(qinfo)
+    }
+
+    /// finalize turns a write token into a new version of its content, the write token is closed
+    pub fn finalize(&mut self, qwtoken: &str) -> Result<QInfo, ErrorKinds> {
+        let hash = self.next_id("hq__");
+        let (qid, slot) = self.find(qwtoken)?;
+        let token = match slot {
+            Slot::Draft(t) => t,
+            Slot::Version(_) => {
+                return Err(ErrorKinds::Finalized(format!(
+                    "{qwtoken} is not a write token"
+                )))
+            }
+        };
+        let object = self
+            .objects
+            .get_mut(&qid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("content {qid} not found")))?;
+        let mut version = object.drafts.remove(&token).unwrap_or_default();
+        version.hash = hash.clone();
+        object.versions.push(version);
+        Ok(q_info(object, Some(&hash)))
+    }
+
+    /// add_file stores a file in the version or write token
+    pub fn add_file(
+        &mut self,
+        hash_or_token: &str,
+        path: &str,
+        data: &[u8],
+        mime: &str,
+    ) -> Result<(), ErrorKinds> {
+        self.version_mut(hash_or_token)?.files.insert(
+            path.to_string(),
+            QFile {
+                data: data.to_vec(),
+                mime: mime.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// add_part stores a part in the version or write token, returning its hash
+    pub fn add_part(&mut self, hash_or_token: &str, data: &[u8]) -> Result<String, ErrorKinds> {
+        let qphash = self.next_id("hqp_");
+        self.version_mut(hash_or_token)?
+            .parts
+            .insert(qphash.clone(), data.to_vec());
+        Ok(qphash)
+    }
+
+    /// set_input replaces the contents of the `fis` stream
+    pub fn set_input(&mut self, data: &[u8]) -> &mut Self {
+        self.streams.insert(
+            INPUT_STREAM.to_string(),
+            Stream {
+                data: data.to_vec(),
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// set_time fixes the time returned to SystemTime, in milliseconds
+    pub fn set_time(&mut self, time: u64) -> &mut Self {
+        self.time = time;
+        self
+    }
+
+    /// stub answers the operation `op` with `f` instead of the fabric
+    pub fn stub<F>(&mut self, op: &str, f: F) -> &mut Self
+    where
+        F: FnMut(&Value) -> Result<Value, ErrorKinds> + 'static,
+    {
+        self.stubs.insert(op.to_string(), Box::new(f));
+        self
+    }
+
+    /// set_current makes `qinfo` the content that calls are resolved against
+    pub fn set_current(&mut self, qinfo: &QInfo) -> &mut Self {
+        self.current = qinfo.clone();
+        self
+    }
+
+    // INSPECTION
+
+    pub fn libraries(&self) -> Vec<&str> {
+        self.libraries.iter().map(|l| l.as_str()).collect()
+    }
+
+    pub fn object(&self, qid: &str) -> Option<&Object> {
+        self.objects.get(qid)
+    }
+
+    /// versions lists the finalized versions of a content object, oldest first
+    pub fn versions(&self, qid: &str) -> &[Version] {
+        match self.objects.get(qid) {
+            Some(o) => &o.versions,
+            None => &[],
+        }
+    }
+
+    /// content finds a version by hash or write token, a content id names its latest version
+    pub fn content(&self, hash_or_token: &str) -> Option<&Version> {
+        let (qid, slot) = self.find(hash_or_token).ok()?;
+        let object = self.objects.get(&qid)?;
+        match slot {
+            Slot::Version(i) => object.versions.get(i),
+            Slot::Draft(t) => object.drafts.get(&t),
+        }
+    }
+
+    /// meta returns the metadata at `path` of a version or write token
+    pub fn meta(&self, hash_or_token: &str, path: &str) -> Option<&Value> {
+        meta_get(&self.content(hash_or_token)?.meta, path)
+    }
+
+    pub fn file(&self, hash_or_token: &str, path: &str) -> Option<&QFile> {
+        self.content(hash_or_token)?.files.get(path)
+    }
+
+    pub fn part(&self, hash_or_token: &str, qphash: &str) -> Option<&[u8]> {
+        self.content(hash_or_token)?
+            .parts
+            .get(qphash)
+            .map(|p| p.as_slice())
+    }
+
+    pub fn stream(&self, sid: &str) -> Option<&Stream> {
+        self.streams.get(sid)
+    }
+
+    /// output returns what was written to the `fos` stream
+    pub fn output(&self) -> &[u8] {
+        match self.streams.get(OUTPUT_STREAM) {
+            Some(s) => &s.data,
+            None => &[],
+        }
+    }
+
+    pub fn qss(&self, qssid: &str) -> Option<&BTreeMap<String, String>> {
+        self.qss.get(qssid)
+    }
+
+    pub fn logs(&self) -> &[LogEntry] {
+        &self.logs
+    }
+
+    /// callbacks returns the http part of every Callback, the response headers a handler sent
+    pub fn callbacks(&self) -> &[Value] {
+        &self.callbacks
+    }
+
+    pub fn calls(&self) -> &[HostCall] {
+        &self.calls
+    }
+
+    // HOST CALLS
+
+    /// host_call answers a call made through the wapc `__host_call`.  Fabric errors come back as a json
+    /// error response, as from the fabric; a failed stream operation or an operation that is neither
+    /// emulated nor stubbed is a host error.
+    pub fn host_call(
+        &mut self,
+        binding: &str,
+        ns: &str,
+        op: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        if let "Write" | "Read" | "Reader" = op {
+            return self.stream_call(ns, op, msg).map_err(|e| e.to_string());
+        }
+        let envelope: Value =
+            serde_json::from_slice(msg).map_err(|e| format!("{op} bad parameters: {e}"))?;
+        // calls made through call_function are wrapped along with the method name
+        let params = match (envelope.get("method"), envelope.get("params")) {
+            (Some(_), Some(p)) => p.clone(),
+            _ => envelope,
+        };
+        self.calls.push(HostCall {
+            ns: ns.to_string(),
+            op: op.to_string(),
+            params: params.clone(),
+        });
+        let res = match self.stubs.get_mut(op) {
+            Some(stub) => stub(&params),
+            None => self.dispatch(op, &params),
+        };
+        match res {
+            Ok(v) => serde_json::to_vec(&json!({ "jpc" : "1.0", "id" : binding, "result" : v }))
+                .map_err(|e| e.to_string()),
+            Err(ErrorKinds::NotImplemented(m)) => Err(ErrorKinds::NotImplemented(m).to_string()),
+            Err(e) => make_json_error(e, binding).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn stream_call(&mut self, sid: &str, op: &str, msg: &[u8]) -> Result<Vec<u8>, ErrorKinds> {
+        let params = match op {
+            "Write" => json!({ "len": msg.len() }),
+            _ => serde_json::from_slice(msg).map_err(|e| ErrorKinds::Invalid(e.to_string()))?,
+        };
+        self.calls.push(HostCall {
+            ns: sid.to_string(),
+            op: op.to_string(),
+            params: params.clone(),
+        });
+        let stream = self
+            .streams
+            .get_mut(sid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("stream {sid} not found")))?;
+        if stream.closed {
+            return Err(ErrorKinds::IO(format!("stream {sid} is closed")));
+        }
+        match op {
+            "Write" => {
+                stream.data.extend_from_slice(msg);
+                Ok(serde_json::to_vec(&json!({ "written": msg.len() }))
+                    .map_err(|e| ErrorKinds::Other(e.to_string()))?)
+            }
+            _ => {
+                let len = params["len"].as_u64().unwrap_or_default() as usize;
+                let end = std::cmp::min(stream.pos + len, stream.data.len());
+                let chunk = stream.data[stream.pos..end].to_vec();
+                stream.pos = end;
+                Ok(chunk)
+            }
+        }
+    }
+
+    fn dispatch(&mut self, op: &str, p: &Value) -> Result<Value, ErrorKinds> {
+        match op {
+            "Log" => {
+                self.logs.push(LogEntry {
+                    level: str_param(p, "level").to_string(),
+                    msg: str_param(p, "msg").to_string(),
+                });
+                Ok(json!({}))
+            }
+            "Callback" => {
+                self.callbacks.push(p["http"].clone());
+                Ok(json!({}))
+            }
+            "SystemTime" => Ok(json!({ "time": self.time })),
+            "NewStream" => Ok(json!({ "stream_id": self.new_stream(false) })),
+            "NewFileStream" => {
+                let sid = self.new_stream(true);
+                let file_name = self.streams[&sid].file_name.clone();
+                Ok(json!({ "stream_id": sid, "file_name": file_name }))
+            }
+            "CloseStream" => {
+                let stream = self.stream_mut(str_param(p, "stream_id"))?;
+                stream.closed = true;
+                Ok(json!(hex_digest(Sha256::digest(&stream.data).as_slice())))
+            }
+            "FileStreamSize" => {
+                let name = str_param(p, "file_name");
+                match self
+                    .streams
+                    .values()
+                    .find(|s| s.file_name.as_deref() == Some(name))
+                {
+                    Some(s) => Ok(json!({ "file_size": s.data.len() })),
+                    None => Err(ErrorKinds::NotExist(format!("file {name} not found"))),
+                }
+            }
+            "FileToStream" => {
+                let name = str_param(p, "path");
+                let data = match self
+                    .streams
+                    .values()
+                    .find(|s| s.file_name.as_deref() == Some(name))
+                {
+                    Some(s) => s.data.clone(),
+                    None => return Err(ErrorKinds::NotExist(format!("file {name} not found"))),
+                };
+                self.stream_mut(str_param(p, "stream_id"))?
+                    .data
+                    .extend_from_slice(&data);
+                Ok(json!({ "written": data.len() }))
+            }
+            "QCreateContent" => {
+                let qlib_id = self.current.qlib_id.clone();
+                let qid = self.next_id("iq__");
+                let token = self.next_id("tqw__");
+                let mut drafts = BTreeMap::new();
+                drafts.insert(token.clone(), Version::new(&token, p["meta"].clone()));
+                self.libraries.insert(qlib_id.clone());
+                self.objects.insert(
+                    qid.clone(),
+                    Object {
+                        id: qid.clone(),
+                        qlib_id,
+                        qtype: str_param(p, "qtype").to_string(),
+                        versions: vec![],
+                        drafts,
+                    },
+                );
+                Ok(json!({ "qid": qid, "qwtoken": token }))
+            }
+            "QModifyContent" => {
+                let qid = self.current.id.clone();
+                let qinfo = self.edit(&qid)?;
+                Ok(json!({ "qwtoken": qinfo.write_token }))
+            }
+            "QFinalizeContent" => {
+                let qinfo = match p.get("qwtoken") {
+                    Some(t) => self.finalize(t.as_str().unwrap_or_default())?,
+                    // committing an already finalized version leaves it as it is
+                    None => {
+                        let qhash = str_param(p, "qhash");
+                        let (qid, _) = self.find(qhash)?;
+                        QInfo {
+                            id: qid,
+                            hash: qhash.to_string(),
+                            ..Default::default()
+                        }
+                    }
+                };
+                Ok(json!({ "qid": qinfo.id, "qhash": qinfo.hash }))
+            }
+            "QListContent" => {
+                let qlib_id = self.current.qlib_id.clone();
+                self.list_content(&qlib_id)
+            }
+            "QListContentFor" => self.list_content(str_param(p, "external_lib")),
+            "QGetVersions" => {
+                let qid = str_param(p, "qid");
+                let object = self
+                    .objects
+                    .get(qid)
+                    .ok_or_else(|| ErrorKinds::NotExist(format!("content {qid} not found")))?;
+                let versions: Vec<Q> = object
+                    .versions
+                    .iter()
+                    .rev()
+                    .map(|v| q_of(object, v, p["with_details"].as_bool().unwrap_or_default()))
+                    .collect();
+                to_value(&QRef {
+                    id: qid.to_string(),
+                    versions,
+                })
+            }
+            "QPartList" => {
+                let key = self.target(p, "object_id_or_hash");
+                let (qid, _) = self.find(&key)?;
+                let version = self.content_of(&key)?;
+                let object = &self.objects[&qid];
+                let parts: Vec<QPart> = version
+                    .parts
+                    .iter()
+                    .map(|(hash, data)| part_of(version, hash, data))
+                    .collect();
+                Ok(
+                    json!({ "part_list": { "content": q_of(object, version, false), "parts": parts } }),
+                )
+            }
+            "QWritePartToStream" => {
+                let key = self.target(p, "qihot");
+                let qphash = str_param(p, "qphash");
+                let part = self
+                    .content_of(&key)?
+                    .parts
+                    .get(qphash)
+                    .ok_or_else(|| ErrorKinds::NotExist(format!("part {qphash} not found")))?;
+                let off = std::cmp::min(
+                    p["off"].as_i64().unwrap_or_default().max(0) as usize,
+                    part.len(),
+                );
+                let end = match p["len"].as_i64() {
+                    Some(l) if l >= 0 => std::cmp::min(off + l as usize, part.len()),
+                    _ => part.len(),
+                };
+                let data = part[off..end].to_vec();
+                self.stream_mut(str_param(p, "stream_id"))?
+                    .data
+                    .extend_from_slice(&data);
+                Ok(json!({ "written": data.len() }))
+            }
+            "QFileToStream" => {
+                let key = match p.get("hash_or_token") {
+                    Some(_) => self.target(p, "hash_or_token"),
+                    None => self.target(p, "qihot"),
+                };
+                let path = str_param(p, "path");
+                let file = self
+                    .content_of(&key)?
+                    .files
+                    .get(path)
+                    .cloned()
+                    .ok_or_else(|| ErrorKinds::NotExist(format!("file {path} not found")))?;
+                self.stream_mut(str_param(p, "stream_id"))?
+                    .data
+                    .extend_from_slice(&file.data);
+                Ok(json!({ "written": file.data.len(), "mime_type": file.mime }))
+            }
+            "QCreatePartFromStream" => {
+                let data = self.stream_mut(str_param(p, "stream_id"))?.data.clone();
+                let key = self.target(p, "qwtoken");
+                self.draft_mut(&key)?;
+                let qphash = self.add_part(&key, &data)?;
+                Ok(json!({ "qphash": qphash, "size": data.len() }))
+            }
+            "QCreateFileFromStream" => {
+                let data = self.stream_mut(str_param(p, "stream_id"))?.data.clone();
+                let key = self.target(p, "qwtoken");
+                let path = str_param(p, "path");
+                self.draft_mut(&key)?;
+                self.add_file(&key, path, &data, str_param(p, "mime"))?;
+                let (qid, _) = self.find(&key)?;
+                let version = self.content_of(&key)?;
+                let part = part_of(version, path, &data);
+                Ok(json!({ "content": q_of(&self.objects[&qid], version, false), "part": part }))
+            }
+            "QCheckSumPart" => {
+                let key = self.current_key();
+                let qphash = str_param(p, "qphash");
+                let part = self
+                    .content_of(&key)?
+                    .parts
+                    .get(qphash)
+                    .ok_or_else(|| ErrorKinds::NotExist(format!("part {qphash} not found")))?;
+                Ok(json!({ "checksum": checksum(str_param(p, "method"), part)? }))
+            }
+            "QCheckSumFile" => {
+                let key = self.current_key();
+                let path = str_param(p, "file_path");
+                let file = self
+                    .content_of(&key)?
+                    .files
+                    .get(path)
+                    .ok_or_else(|| ErrorKinds::NotExist(format!("file {path} not found")))?;
+                Ok(json!({ "checksum": checksum(str_param(p, "method"), &file.data)? }))
+            }
+            "SQMDGet" | "SQMDGetJSONResolve" => {
+                let key = self.current_key();
+                self.meta_at(&key, str_param(p, "path"))
+            }
+            "SQMDGetExternal" => {
+                let qhash = str_param(p, "qhash");
+                let qlibid = str_param(p, "qlibid");
+                match self.find(qhash) {
+                    Ok((qid, _)) if self.objects[&qid].qlib_id == qlibid => {
+                        self.meta_at(qhash, str_param(p, "path"))
+                    }
+                    _ => Err(ErrorKinds::NotExist(format!(
+                        "content {qhash} not found in {qlibid}"
+                    ))),
+                }
+            }
+            "SQMDSet" => {
+                let key = self.current_key();
+                meta_set(
+                    &mut self.draft_mut(&key)?.meta,
+                    str_param(p, "path"),
+                    p["meta"].clone(),
+                );
+                Ok(json!({}))
+            }
+            "SQMDMerge" => {
+                let key = self.current_key();
+                // the value to merge comes as a json string
+                let val = match &p["meta"] {
+                    Value::String(s) => serde_json::from_str(s).unwrap_or_else(|_| json!(s)),
+                    v => v.clone(),
+                };
+                let meta = &mut self.draft_mut(&key)?.meta;
+                match meta_get_mut(meta, str_param(p, "path")) {
+                    Some(dst) => meta_merge(dst, val),
+                    None => meta_set(meta, str_param(p, "path"), val),
+                }
+                Ok(json!({}))
+            }
+            "SQMDDelete" => {
+                let key = self.current_key();
+                let path = str_param(p, "path");
+                if !meta_delete(&mut self.draft_mut(&key)?.meta, path) {
+                    return Err(ErrorKinds::NotExist(format!("metadata {path} not found")));
+                }
+                Ok(json!({}))
+            }
+            "SQMDClear" => {
+                let key = self.current_key();
+                meta_set(
+                    &mut self.draft_mut(&key)?.meta,
+                    str_param(p, "path"),
+                    json!({}),
+                );
+                Ok(json!({}))
+            }
+            "QCreateQStateStore" => {
+                let qssid = self.next_id("qss_");
+                self.qss.insert(qssid.clone(), BTreeMap::new());
+                Ok(json!(qssid))
+            }
+            "QSSSet" => {
+                self.state_store(str_param(p, "qssid"))?.insert(
+                    str_param(p, "key").to_string(),
+                    str_param(p, "val").to_string(),
+                );
+                Ok(json!({}))
+            }
+            "QSSGet" => {
+                let key = str_param(p, "key");
+                Ok(json!(self.state_store(str_param(p, "qssid"))?.get(key)))
+            }
+            "QSSDelete" => {
+                let key = str_param(p, "key");
+                self.state_store(str_param(p, "qssid"))?.remove(key);
+                Ok(json!({}))
+            }
+            _ => Err(ErrorKinds::NotImplemented(format!(
+                "{op} is not emulated by the mock fabric, stub it"
+            ))),
+        }
+    }
+
+    fn new_stream(&mut self, file: bool) -> String {
+        let sid = self.next_id("sid_");
+        let file_name = file.then(|| format!("/tmp/{sid}"));
+        self.streams.insert(
+            sid.clone(),
+            Stream {
+                file_name,
+                ..Default::default()
+            },
+        );
+        sid
+    }
+
+    fn stream_mut(&mut self, sid: &str) -> Result<&mut Stream, ErrorKinds> {
+        self.streams
+            .get_mut(sid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("stream {sid} not found")))
+    }
+
+    fn state_store(&mut self, qssid: &str) -> Result<&mut BTreeMap<String, String>, ErrorKinds> {
+        self.qss
+            .get_mut(qssid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("state store {qssid} not found")))
+    }
+
+    fn list_content(&self, qlib_id: &str) -> Result<Value, ErrorKinds> {
+        if !self.libraries.contains(qlib_id) {
+            return Err(ErrorKinds::NotExist(format!("library {qlib_id} not found")));
+        }
+        let contents: Vec<QRef> = self
+            .objects
+            .values()
+            .filter(|o| o.qlib_id == qlib_id)
+            .map(|o| QRef {
+                id: o.id.clone(),
+                versions: o.versions.iter().rev().map(|v| q_of(o, v, false)).collect(),
+            })
+            .collect();
+        Ok(json!({ "filter": "", "contents": to_value(&contents)?, "errors": [] }))
+    }
+
+    fn meta_at(&self, hash_or_token: &str, path: &str) -> Result<Value, ErrorKinds> {
+        match meta_get(&self.content_of(hash_or_token)?.meta, path) {
+            Some(v) => Ok(v.clone()),
+            None => Err(ErrorKinds::NotExist(format!("metadata {path} not found"))),
+        }
+    }
+
+    // the content a call works on when it names none: the request's write token, hash or id
+    fn current_key(&self) -> String {
+        match self.current.qhot() {
+            k if k.is_empty() => self.current.id.clone(),
+            k => k,
+        }
+    }
+
+    fn target(&self, p: &Value, name: &str) -> String {
+        match str_param(p, name) {
+            "" => self.current_key(),
+            k => k.to_string(),
+        }
+    }
+
+    fn find(&self, key: &str) -> Result<(String, Slot), ErrorKinds> {
+        for (qid, o) in &self.objects {
+            if o.drafts.contains_key(key) {
+                return Ok((qid.clone(), Slot::Draft(key.to_string())));
+            }
+            if let Some(i) = o.versions.iter().position(|v| v.hash == key) {
+                return Ok((qid.clone(), Slot::Version(i)));
+            }
+            if qid == key && !o.versions.is_empty() {
+                return Ok((qid.clone(), Slot::Version(o.versions.len() - 1)));
+            }
+        }
+        Err(ErrorKinds::NotExist(format!("content {key} not found")))
+    }
+
+    fn content_of(&self, key: &str) -> Result<&Version, ErrorKinds> {
+        self.content(key)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("content {key} not found")))
+    }
+
+    fn version_mut(&mut self, key: &str) -> Result<&mut Version, ErrorKinds> {
+        let (qid, slot) = self.find(key)?;
+        let object = self
+            .objects
+            .get_mut(&qid)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("content {qid} not found")))?;
+        match slot {
+            Slot::Version(i) => object.versions.get_mut(i),
+            Slot::Draft(t) => object.drafts.get_mut(&t),
+        }
+        .ok_or_else(|| ErrorKinds::NotExist(format!("content {key} not found")))
+    }
+
+    // mutations from a bitcode need a write token
+    fn draft_mut(&mut self, key: &str) -> Result<&mut Version, ErrorKinds> {
+        match self.find(key)? {
+            (_, Slot::Draft(_)) => self.version_mut(key),
+            (_, Slot::Version(_)) => Err(ErrorKinds::Finalized(format!(
+                "content {key} is finalized, a write token is required"
+            ))),
+        }
+    }
+}
+
+fn q_info(object: &Object, hash: Option<&str>) -> QInfo {
+    QInfo {
+        hash: hash.unwrap_or_default().to_string(),
+        id: object.id.clone(),
+        qlib_id: object.qlib_id.clone(),
+        qtype: object.qtype.clone(),
+        write_token: String::new(),
+    }
+}
+
+fn q_of(object: &Object, version: &Version, with_meta: bool) -> Q {
+    let size: usize = version.parts.values().map(|p| p.len()).sum();
+    let is_draft = object.drafts.contains_key(&version.hash);
+    Q {
+        id: object.id.clone(),
+        hash: if is_draft {
+            String::new()
+        } else {
+            version.hash.clone()
+        },
+        write_token: if is_draft {
+            version.hash.clone()
+        } else {
+            String::new()
+        },
+        q_type: object.qtype.clone(),
+        qlib_id: object.qlib_id.clone(),
+        meta: if with_meta {
+            version.meta.clone()
+        } else {
+            Value::Null
+        },
+        size_stats: SizeStats {
+            parts: version.parts.len() as i32,
+            size: format!("{size} B"),
+            size_bytes: size as i64,
+        },
+    }
+}
+
+fn part_of(version: &Version, hash: &str, data: &[u8]) -> QPart {
+    QPart {
+        write_token: version.hash.clone(),
+        hash: hash.to_string(),
+        size: data.len() as i64,
+    }
+}
+
+fn to_value<T: serde::Serialize>(t: &T) -> Result<Value, ErrorKinds> {
+    serde_json::to_value(t).map_err(|e| ErrorKinds::Other(e.to_string()))
+}
+
+fn str_param<'v>(p: &'v Value, name: &str) -> &'v str {
+    p[name].as_str().unwrap_or_default()
+}
+
+fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn checksum(method: &str, data: &[u8]) -> Result<String, ErrorKinds> {
+    match method.to_lowercase().as_str() {
+        "md5" => Ok(hex_digest(Md5::digest(data).as_slice())),
+        "sha256" | "sha-256" => Ok(hex_digest(Sha256::digest(data).as_slice())),
+        _ => Err(ErrorKinds::Invalid(format!(
+            "unsupported checksum method {method}"
+        ))),
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// meta_get returns the value at a slash separated metadata path, `/` being the whole of it
+pub fn meta_get<'v>(meta: &'v Value, path: &str) -> Option<&'v Value> {
+    segments(path).into_iter().try_fold(meta, |v, s| match v {
+        Value::Object(m) => m.get(s),
+        Value::Array(a) => s.parse::<usize>().ok().and_then(|i| a.get(i)),
+        _ => None,
+    })
+}
+
+fn meta_get_mut<'v>(meta: &'v mut Value, path: &str) -> Option<&'v mut Value> {
+    segments(path).into_iter().try_fold(meta, |v, s| match v {
+        Value::Object(m) => m.get_mut(s),
+        Value::Array(a) => s.parse::<usize>().ok().and_then(move |i| a.get_mut(i)),
+        _ => None,
+    })
+}
+
+/// meta_set sets the value at a metadata path, creating the objects leading to it
+pub fn meta_set(meta: &mut Value, path: &str, val: Value) {
+    let mut cur = meta;
+    for s in segments(path) {
+        if !cur.is_object() {
+            *cur = Value::Object(Map::new());
+        }
+        cur = match cur {
+            Value::Object(m) => m.entry(s.to_string()).or_insert(Value::Null),
+            _ => unreachable!(),
+        };
+    }
+    *cur = val;
+}
+
+/// meta_delete removes the value at a metadata path, false when there was none
+pub fn meta_delete(meta: &mut Value, path: &str) -> bool {
+    let segs = segments(path);
+    let (last, parent) = match segs.split_last() {
+        Some((last, parent)) => (last, parent.join("/")),
+        None => {
+            *meta = json!({});
+            return true;
+        }
+    };
+    match meta_get_mut(meta, &parent) {
+        Some(Value::Object(m)) => m.remove(*last).is_some(),
+        _ => false,
+    }
+}
+
+fn meta_merge(dst: &mut Value, src: Value) {
+    match (dst, src) {
+        (Value::Object(d), Value::Object(s)) => {
+            for (k, v) in s {
+                meta_merge(d.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (d, s) => *d = s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(fab: &mut MockFabric, op: &str, params: Value) -> Value {
+        let msg = json!({ "jpc" : "1.0", "id" : "id1", "module" : "core", "method" : op, "params" : params });
+        let res = fab
+            .host_call("id1", "core", op, &serde_json::to_vec(&msg).unwrap())
+            .unwrap();
+        serde_json::from_slice(&res).unwrap()
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({"a" : {"b" : 1}}));
+        fab.set_current(&qinfo);
+        assert_eq!(
+            call(&mut fab, "SQMDGet", json!({"path" : "/a/b"}))["result"],
+            1
+        );
+        assert!(call(&mut fab, "SQMDGet", json!({"path" : "/x"}))
+            .get("error")
+            .is_some());
+        // a finalized version cannot be changed
+        let res = call(&mut fab, "SQMDSet", json!({"path" : "/a/c", "meta" : 2}));
+        assert!(res.get("error").is_some());
+
+        let draft = fab.edit(&qinfo.id).unwrap();
+        fab.set_current(&draft);
+        call(
+            &mut fab,
+            "SQMDSet",
+            json!({"path" : "/a/c", "meta" : {"d" : [1, 2]}}),
+        );
+        call(
+            &mut fab,
+            "SQMDMerge",
+            json!({"path" : "/a", "meta" : "{\"e\" : true}"}),
+        );
+        call(&mut fab, "SQMDDelete", json!({"path" : "/a/b"}));
+        assert_eq!(
+            fab.meta(&draft.write_token, "/a"),
+            Some(&json!({"c" : {"d" : [1, 2]}, "e" : true}))
+        );
+        assert_eq!(fab.meta(&draft.write_token, "/a/c/d/1"), Some(&json!(2)));
+        assert_eq!(fab.meta(&qinfo.hash, "/a"), Some(&json!({"b" : 1})));
+
+        let res = call(
+            &mut fab,
+            "QFinalizeContent",
+            json!({"qwtoken" : draft.write_token}),
+        );
+        let qhash = res["result"]["qhash"].as_str().unwrap().to_string();
+        assert_ne!(qhash, qinfo.hash);
+        assert_eq!(fab.meta(&qinfo.id, "/a/e"), Some(&json!(true)));
+        assert!(fab.content(&draft.write_token).is_none());
+        let versions = call(&mut fab, "QGetVersions", json!({"qid" : qinfo.id}));
+        assert_eq!(versions["result"]["versions"][0]["hash"], json!(qhash));
+    }
+
+    #[test]
+    fn test_streams_and_parts() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        fab.set_current(&draft);
+        let sid = call(&mut fab, "NewStream", json!({}))["result"]["stream_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let res = fab.host_call("id1", &sid, "Write", b"0123456789").unwrap();
+        assert_eq!(res, br#"{"written":10}"#.to_vec());
+        let res = call(
+            &mut fab,
+            "QCreatePartFromStream",
+            json!({"qwtoken" : draft.write_token, "stream_id" : sid}),
+        );
+        let qphash = res["result"]["qphash"].as_str().unwrap().to_string();
+        assert_eq!(
+            fab.part(&draft.write_token, &qphash),
+            Some(&b"0123456789"[..])
+        );
+
+        let out = call(&mut fab, "NewStream", json!({}))["result"]["stream_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let res = call(
+            &mut fab,
+            "QWritePartToStream",
+            json!({"stream_id" : out, "qphash" : qphash, "qihot" : "", "off" : 2, "len" : 5}),
+        );
+        assert_eq!(res["result"]["written"], 5);
+        let read = fab
+            .host_call("id1", &out, "Reader", br#"{"len" : 3}"#)
+            .unwrap();
+        assert_eq!(read, b"234".to_vec());
+        let read = fab
+            .host_call("id1", &out, "Reader", br#"{"len" : 3}"#)
+            .unwrap();
+        assert_eq!(read, b"56".to_vec());
+        assert!(fab
+            .host_call("id1", "sid_none", "Reader", br#"{"len" : 3}"#)
+            .is_err());
+        assert!(fab
+            .host_call("id1", "search", "SearchIndex", b"{}")
+            .is_err());
+
+        fab.host_call("id1", OUTPUT_STREAM, "Write", b"done")
+            .unwrap();
+        assert_eq!(fab.output(), b"done");
+    }
+
+    #[test]
+    fn test_stub_and_state_store() {
+        let mut fab = MockFabric::new();
+        fab.stub("ProxyHttp", |p| Ok(json!({ "echo": p["request"].clone() })));
+        assert_eq!(
+            call(&mut fab, "ProxyHttp", json!({"request" : "x"}))["result"]["echo"],
+            "x"
+        );
+        let qssid = call(&mut fab, "QCreateQStateStore", json!({}))["result"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        call(
+            &mut fab,
+            "QSSSet",
+            json!({"qssid" : qssid, "key" : "k", "val" : "v"}),
+        );
+        assert_eq!(
+            call(&mut fab, "QSSGet", json!({"qssid" : qssid, "key" : "k"}))["result"],
+            "v"
+        );
+        assert_eq!(
+            call(&mut fab, "QSSGet", json!({"qssid" : qssid, "key" : "z"}))["result"],
+            Value::Null
+        );
+        assert_eq!(fab.calls().len(), 5);
+    }
+}
@@ -0,0 +1,1417 @@
+//! mockhost is a native host for unit testing bitcode handlers. <br>
+//! It implements the host side of the wapc abi (`__host_call`, `__host_response`, ...) over an in-memory
+//! [MockFabric], so a handler built natively runs its [elvwasm::BitcodeContext] calls against the mock as
+//! it would against the content fabric.  A test seeds a fabric, [install]s it, runs the handler directly
+//! with [run_handler] or through [elvwasm::jpc] with [run_jpc], and inspects the fabric afterwards with
+//! [with_fabric]. <br>
+//! The fabric is kept per thread, tests running in parallel each see their own.  Crates using mockhost get
+//! elvwasm with its `mock-host` feature, which leaves the host exports to this crate.
+//! ```rust
+//! use elvwasm::BitcodeContext;
+//! use serde_json::json;
+//!
+//! fn do_title(bcc: &mut BitcodeContext) -> wapc_guest::CallResult {
+//!     let title = bcc.sqmd_get_json("/public/name")?;
+//!     bcc.callback(200, "application/json", title.len())?;
+//!     bcc.write_stream("fos", &title)?;
+//!     bcc.make_success_json(&json!({}))
+//! }
+//!
+//! let mut fab = mockhost::MockFabric::new();
+//! let qinfo = fab.create_content("ilib1", "hq__type", json!({"public" : {"name" : "old man"}}));
+//! mockhost::install(fab);
+//! let res = mockhost::run_handler(do_title, mockhost::request("title", "/title", &qinfo));
+//! assert!(res.is_ok());
+//! assert_eq!(mockhost::with_fabric(|f| f.output().to_vec()), b"\"old man\"");
+//! ```
+
+extern crate elvwasm;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate wapc_guest as guest;
+
+pub mod fabric;
+
+pub use self::fabric::*;
+
+use elvwasm::{BitcodeContext, HttpParams, JpcParams, QInfo, Request};
+use guest::CallResult;
+
+use std::cell::RefCell;
+
+thread_local! {
+    static FABRIC: RefCell<MockFabric> = RefCell::new(MockFabric::new());
+    static RESPONSE: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static ERROR: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// install makes `fabric` the fabric of the current thread, returning the one it replaces
+pub fn install(fabric: MockFabric) -> MockFabric {
+    FABRIC.with(|f| f.replace(fabric))
+}
+
+/// with_fabric runs `f` on the fabric of the current thread
+pub fn with_fabric<R, F: FnOnce(&mut MockFabric) -> R>(f: F) -> R {
+    FABRIC.with(|fab| f(&mut fab.borrow_mut()))
+}
+
+/// request builds a GET request for `method` on the content described by `qinfo`
+pub fn request(method: &str, path: &str, qinfo: &QInfo) -> Request {
+    Request {
+        id: format!("mock_{method}"),
+        jpc: "1.0".to_string(),
+        method: method.to_string(),
+        params: JpcParams {
+            http: HttpParams {
+                path: path.to_string(),
+                verb: "GET".to_string(),
+                ..Default::default()
+            },
+        },
+        q_info: qinfo.clone(),
+    }
+}
+
+/// run_handler runs a handler on `request`, its host calls resolved against the request's content
+pub fn run_handler(handler: fn(&mut BitcodeContext) -> CallResult, request: Request) -> CallResult {
+    with_fabric(|f| {
+        f.set_current(&request.q_info);
+    });
+    handler(&mut BitcodeContext::new(request))
+}
+
+/// run_jpc dispatches `request` to the handler registered for its method, as the fabric's call to `_JPC`
+pub fn run_jpc(request: &Request) -> CallResult {
+    with_fabric(|f| {
+        f.set_current(&request.q_info);
+    });
+    elvwasm::jpc(&serde_json::to_vec(request)?)
+}
+
+// the wapc host functions the guest imports; __console_log stays with elvwasm
+mod exports {
+    use super::{with_fabric, ERROR, RESPONSE};
+
+    fn raw_str<'a>(ptr: *const u8, len: usize) -> &'a str {
+        unsafe { std::str::from_utf8(std::slice::from_raw_parts(ptr, len)).unwrap_or_default() }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __host_call(
+        bd_ptr: *const u8,
+        bd_len: usize,
+        ns_ptr: *const u8,
+        ns_len: usize,
+        op_ptr: *const u8,
+        op_len: usize,
+        ptr: *const u8,
+        len: usize,
+    ) -> usize {
+        let msg = unsafe { std::slice::from_raw_parts(ptr, len) };
+        let res = with_fabric(|f| {
+            f.host_call(
+                raw_str(bd_ptr, bd_len),
+                raw_str(ns_ptr, ns_len),
+                raw_str(op_ptr, op_len),
+                msg,
+            )
+        });
+        match res {
+            Ok(r) => {
+                RESPONSE.with(|resp| *resp.borrow_mut() = r);
+                1
+            }
+            Err(e) => {
+                ERROR.with(|err| *err.borrow_mut() = e.into_bytes());
+                0
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __host_response(ptr: *mut u8) {
+        RESPONSE.with(|resp| {
+            let r = resp.borrow();
+            unsafe { std::ptr::copy_nonoverlapping(r.as_ptr(), ptr, r.len()) }
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __host_response_len() -> usize {
+        RESPONSE.with(|resp| resp.borrow().len())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __host_error(ptr: *mut u8) {
+        ERROR.with(|err| {
+            let e = err.borrow();
+            unsafe { std::ptr::copy_nonoverlapping(e.as_ptr(), ptr, e.len()) }
+        })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __host_error_len() -> usize {
+        ERROR.with(|err| err.borrow().len())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __guest_response(ptr: *const u8, len: usize) {
+        println!("guest response : {}", raw_str(ptr, len));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __guest_error(ptr: *const u8, len: usize) {
+        println!("guest error : {}", raw_str(ptr, len));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn __guest_request(_op_ptr: *const u8, _ptr: *const u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elvwasm::{register_handler, ErrorKinds, QPartList, WriteResult};
+    use serde_json::{json, Value};
+
+    fn do_copy(bcc: &mut BitcodeContext) -> CallResult {
+        let qwt = bcc.request.q_info.write_token.clone();
+        let mut fis = bcc.stream_reader("fis");
+        bcc.q_upload_reader(&qwt, &mut fis, "/copy.txt", &elvwasm::UploadOptions::new())?;
+        bcc.sqmd_set_json("/copied", &json!({"path" : "/copy.txt"}))?;
+        let res = bcc.q_finalize_content(&qwt)?;
+        let wr: WriteResult = bcc.write_stream("fos", &res).try_into()?;
+        bcc.make_success_json(&json!({ "written": wr.written }))
+    }
+
+    #[test]
+    fn test_run_handler() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        fab.set_input(b"some file contents");
+        install(fab);
+        let res = run_handler(do_copy, request("copy", "/copy", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert!(res["result"]["written"].as_u64().unwrap() > 0);
+        with_fabric(|f| {
+            let out: Value = serde_json::from_slice(f.output()).unwrap();
+            let qhash = out["qhash"].as_str().unwrap();
+            assert_eq!(f.versions(&qinfo.id).len(), 2);
+            assert_eq!(
+                f.file(qhash, "/copy.txt").unwrap().data,
+                b"some file contents"
+            );
+            assert_eq!(f.meta(qhash, "/copied/path"), Some(&json!("/copy.txt")));
+        });
+    }
+
+    fn do_checked_copy(bcc: &mut BitcodeContext) -> CallResult {
+        let qwt = bcc.request.q_info.write_token.clone();
+        let mut fis = bcc.stream_reader("fis");
+        let opts = elvwasm::UploadOptions::new()
+            .chunk_size(4)
+            .checksum("MD5", "5eb63bbbe01eeed093cb22bb8f5acdc3");
+        bcc.q_upload_reader(&qwt, &mut fis, "/copy.txt", &opts)
+    }
+
+    #[test]
+    fn test_upload_checksum() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        fab.set_input(b"hello world");
+        install(fab);
+        assert!(run_handler(do_checked_copy, request("copy", "/copy", &draft)).is_ok());
+        with_fabric(|f| {
+            assert!(f.file(&draft.write_token, "/copy.txt").is_some());
+            f.set_input(b"hello there");
+        });
+        let draft = with_fabric(|f| f.edit(&qinfo.id).unwrap());
+        assert!(run_handler(do_checked_copy, request("copy", "/copy", &draft)).is_err());
+        with_fabric(|f| assert!(f.file(&draft.write_token, "/copy.txt").is_none()));
+    }
+
+    fn do_state(bcc: &mut BitcodeContext) -> CallResult {
+        let store = elvwasm::StateStore::from_meta(bcc, "/state_store/qssid")?.namespace("jobs");
+        assert_eq!(store.put("job1", &json!({"state" : "running"}))?, 1);
+        assert_eq!(store.put_if_version("job1", 0, &json!({}))?, None);
+        assert_eq!(
+            store.put_if_version("job1", 1, &json!({"state" : "done"}))?,
+            Some(2)
+        );
+        assert_eq!(store.increment("visits", 2)?, 2);
+        assert_eq!(store.increment("visits", 3)?, 5);
+        assert!(store.put(".keys", &json!([])).is_err());
+        // a value stored without the envelope exists, at version 1
+        bcc.qss_set(store.qssid(), "jobs/legacy", "\"raw\"")?;
+        assert_eq!(store.version("legacy")?, 1);
+        assert_eq!(store.put_if_version("legacy", 0, &json!("new"))?, None);
+        assert_eq!(store.get::<String>("legacy")?.as_deref(), Some("raw"));
+        assert!(store.get::<Value>("missing")?.is_none());
+        assert!(store.delete("visits")?);
+        let jobs: Vec<(String, Value)> = store.list("")?;
+        bcc.make_success_json(&json!({ "jobs": jobs }))
+    }
+
+    #[test]
+    fn test_state_store() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        install(fab);
+        let res = run_handler(do_state, request("state", "/state", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["jobs"], json!([["job1", {"state" : "done"}]]));
+        with_fabric(|f| {
+            let qssid = f.meta(&draft.write_token, "/state_store/qssid").unwrap();
+            assert_eq!(f.qss(qssid.as_str().unwrap()).unwrap().len(), 3);
+        });
+    }
+
+    fn do_cache(bcc: &mut BitcodeContext) -> CallResult {
+        let store = elvwasm::StateStore::from_meta(bcc, "/state_store/qssid")?;
+        let policy = elvwasm::CachePolicy::default().ttl(1000).max_entries(20);
+        let cache = elvwasm::BitcodeCache::new(&store, policy);
+        for i in 0..30 {
+            with_fabric(|f| {
+                f.set_time(10_000 + i as u64);
+            });
+            cache.put("hq__1", &json!({ "i": i }), &i)?;
+        }
+        let hit: Option<i32> = cache.get("hq__1", &json!({"i" : 29}))?;
+        let evicted: Option<i32> = cache.get("hq__1", &json!({"i" : 0}))?;
+        bcc.make_success_json(&json!({ "hit": hit, "evicted": evicted }))
+    }
+
+    fn do_cache_get(bcc: &mut BitcodeContext) -> CallResult {
+        let store = elvwasm::StateStore::from_meta(bcc, "/state_store/qssid")?;
+        let cache = elvwasm::BitcodeCache::new(&store, elvwasm::CachePolicy::default().ttl(1000));
+        let hit: Option<i32> = cache.get("hq__1", &json!({"i" : 29}))?;
+        bcc.make_success_json(&json!({ "hit": hit }))
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        install(fab);
+        let res = run_handler(do_cache, request("cache", "/cache", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"], json!({"hit" : 29, "evicted" : null}));
+        with_fabric(|f| {
+            let qssid = f.meta(&draft.write_token, "/state_store/qssid").unwrap();
+            // a slack of two sweeps back to 19 entries before the 22nd is added, 30 puts leave 20 and the
+            // key index
+            assert_eq!(f.qss(qssid.as_str().unwrap()).unwrap().len(), 21);
+            f.set_time(11_028);
+        });
+        let res = run_handler(do_cache_get, request("cache", "/cache", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["hit"], 29);
+        with_fabric(|f| {
+            f.set_time(11_029);
+        });
+        let res = run_handler(do_cache_get, request("cache", "/cache", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["hit"], Value::Null);
+    }
+
+    fn do_cache_limits(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::{BitcodeCache, CachePolicy};
+        let store = elvwasm::StateStore::from_meta(bcc, "/state_store/qssid")?;
+        let entries = store.namespace(elvwasm::CACHE_NAMESPACE);
+        // a slack of one, the 11th entry sweeps the oldest
+        let cache = BitcodeCache::new(&store, CachePolicy::default().max_entries(10));
+        for i in 0..15 {
+            with_fabric(|f| {
+                f.set_time(20_000 + i as u64);
+            });
+            assert!(cache.put("hq__1", &json!({ "i": i }), &i)?);
+            assert_eq!(entries.list_keys("")?.len(), std::cmp::min(i + 1, 10));
+        }
+        assert_eq!(cache.get::<i32>("hq__1", &json!({"i" : 4}))?, None);
+        assert_eq!(cache.get::<i32>("hq__1", &json!({"i" : 5}))?, Some(5));
+        // rewriting an entry does not sweep
+        cache.put("hq__1", &json!({"i" : 5}), &50)?;
+        assert_eq!(cache.get::<i32>("hq__1", &json!({"i" : 6}))?, Some(6));
+        assert_eq!(cache.invalidate_content("hq__1")?, 10);
+
+        let cache = BitcodeCache::new(&store, CachePolicy::default().max_entry_bytes(8));
+        assert!(!cache.put("hq__1", &json!({"i" : 1}), &"more than eight bytes")?);
+        assert_eq!(cache.get::<String>("hq__1", &json!({"i" : 1}))?, None);
+        assert!(cache.put("hq__1", &json!({"i" : 1}), &"short")?);
+
+        // another parameter set stored under the same key is a miss, and replaced by put
+        let params = json!({"i" : 2});
+        let key = BitcodeCache::cache_key("hq__1", &params);
+        entries.put(
+            &key,
+            &json!({"created" : 0, "params" : "{\"i\":3}", "value" : 3}),
+        )?;
+        assert_eq!(cache.get::<i32>("hq__1", &params)?, None);
+        let mut computed = 0;
+        let v: i32 = cache.get_or_compute("hq__1", &params, || {
+            computed += 1;
+            Ok(2)
+        })?;
+        let again: i32 = cache.get_or_compute("hq__1", &params, || {
+            computed += 1;
+            Ok(-1)
+        })?;
+        assert_eq!((v, again, computed), (2, 2, 1));
+
+        // an expired entry is dropped when read, and swept before live ones when full
+        let cache = BitcodeCache::new(&store, CachePolicy::default().ttl(100).max_entries(3));
+        cache.invalidate_content("hq__1")?;
+        for (i, t) in [(0, 30_000), (1, 30_150), (2, 30_160)] {
+            with_fabric(|f| {
+                f.set_time(t);
+            });
+            cache.put("hq__2", &json!({ "i": i }), &i)?;
+        }
+        cache.put("hq__2", &json!({"i" : 3}), &3)?;
+        assert_eq!(entries.list_keys("hq__2/")?.len(), 3);
+        assert_eq!(cache.get::<i32>("hq__2", &json!({"i" : 1}))?, Some(1));
+        with_fabric(|f| {
+            f.set_time(30_250);
+        });
+        assert_eq!(cache.get::<i32>("hq__2", &json!({"i" : 1}))?, None);
+        assert_eq!(entries.list_keys("hq__2/")?.len(), 2);
+        bcc.make_success_json(&json!({}))
+    }
+
+    #[test]
+    fn test_cache_limits() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        install(fab);
+        run_handler(do_cache_limits, request("cache", "/cache", &draft)).unwrap();
+    }
+
+    struct Count;
+
+    impl elvwasm::LroJob for Count {
+        const JOB_TYPE: &'static str = "count";
+        type Args = Vec<String>;
+        type Output = usize;
+        fn run(
+            ctx: &elvwasm::LroContext,
+            args: Vec<String>,
+        ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+            ctx.report_progress(0.5, &args[0])?;
+            Ok(args.len())
+        }
+    }
+
+    fn do_lro_start(bcc: &mut BitcodeContext) -> CallResult {
+        let status = bcc.lro_start::<Count>(&vec!["a".to_string(), "b".to_string()])?;
+        bcc.make_success_json(&serde_json::to_value(status)?)
+    }
+
+    fn do_lro_run(bcc: &mut BitcodeContext) -> CallResult {
+        bcc.lro_run::<Count>()
+    }
+
+    fn job_request(method: &str, qinfo: &QInfo, job_id: &str) -> Request {
+        let mut req = request(method, &format!("/{method}"), qinfo);
+        req.params
+            .http
+            .query
+            .insert("job_id".to_string(), vec![job_id.to_string()]);
+        req
+    }
+
+    #[test]
+    fn test_lro() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        fab.stub("StartBitcodeLRO", |_| Ok(json!({"lro_handle" : "lro_1"})));
+        install(fab);
+        // a version without a write token cannot record the job store
+        assert!(run_handler(do_lro_start, request("start", "/start", &qinfo)).is_err());
+        let res = run_handler(do_lro_start, request("start", "/start", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        let job_id = res["result"]["job_id"].as_str().unwrap().to_string();
+        assert!(job_id.starts_with("count_1@qss_"));
+        let finalized = with_fabric(|f| f.finalize(&draft.write_token).unwrap());
+
+        run_handler(do_lro_run, job_request("count", &finalized, &job_id)).unwrap();
+        // the job is found from the version predating the store as well
+        let res = run_handler(
+            elvwasm::lro_status_handler,
+            job_request("status", &qinfo, &job_id),
+        )
+        .unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["state"], "succeeded");
+        assert_eq!(res["result"]["result"], 2);
+        assert_eq!(res["result"]["lro_handle"], "lro_1");
+
+        // a job cancelled while queued is not run
+        let res = run_handler(do_lro_start, request("start", "/start", &finalized)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        let queued = res["result"]["job_id"].as_str().unwrap().to_string();
+        assert!(queued.starts_with("count_2@qss_"));
+        let res = run_handler(
+            elvwasm::lro_cancel_handler,
+            job_request("cancel", &finalized, &queued),
+        )
+        .unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["state"], "pending");
+        let res = run_handler(do_lro_run, job_request("count", &finalized, &queued)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["state"], "cancelled");
+        assert_eq!(res["result"]["result"], Value::Null);
+        assert_eq!(res["result"]["message"], "cancelled before it started");
+
+        let missing = job_id.replace("count_1", "count_9");
+        let res = run_handler(
+            elvwasm::lro_cancel_handler,
+            job_request("cancel", &finalized, &missing),
+        )
+        .unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(
+            res["error"]["desc"]["NotExist"],
+            format!("lro job {missing} not found")
+        );
+        let res = run_handler(
+            elvwasm::lro_status_handler,
+            job_request("status", &finalized, "count_1"),
+        )
+        .unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert!(res["error"]["desc"]["BadHttpParams"].is_string());
+    }
+
+    fn do_external(bcc: &mut BitcodeContext) -> CallResult {
+        let sid = match bcc.request.params.http.path.as_str() {
+            "/missing" => "sid_none".to_string(),
+            _ => {
+                let stream: elvwasm::NewStreamResult = bcc.new_stream().try_into()?;
+                bcc.write_stream(&stream.stream_id, b"streamed body")?;
+                stream.stream_id
+            }
+        };
+        let ext = elvwasm::ExternalBitcode::new(bcc, "hq__ext", "builtin");
+        let resp = ext.post("transform", "/transform", json!(sid))?;
+        bcc.make_success_json(&json!({ "body": String::from_utf8(resp.body)? }))
+    }
+
+    #[test]
+    fn test_external_stream() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        fab.stub("CallExternalBitcode", |p| {
+            Ok(json!({"body" : p["params"]["http"]["body"], "status" : 200}))
+        });
+        install(fab);
+        let res = run_handler(do_external, request("ext", "/ext", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["body"], "streamed body");
+        // a failed read is an error rather than an empty body
+        assert!(run_handler(do_external, request("ext", "/missing", &qinfo)).is_err());
+        with_fabric(|f| {
+            f.stub("CallExternalBitcode", |_| {
+                Err(ErrorKinds::NotExist("no bitcode in hq__ext".to_string()))
+            });
+        });
+        let err = run_handler(do_external, request("ext", "/ext", &qinfo))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("NotExist") && err.contains("no bitcode in hq__ext"),
+            "{err}"
+        );
+    }
+
+    fn do_resilient(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::{Attempt, HttpClient, HttpRequest, ResiliencePolicies, ResilientClient};
+        let store = elvwasm::StateStore::from_meta(bcc, "/state_store/qssid")?;
+        let policies: ResiliencePolicies = serde_json::from_value(json!({
+            "default" : {"breaker" : {"failure_threshold" : 3, "reset_ms" : 10000}},
+            "targets" : {"slow.example.com" : {"retry" : {"initial_backoff_ms" : 500}}}
+        }))?;
+        let client = ResilientClient::new(HttpClient::proxy(bcc), &store, policies);
+        // two 503s retried at once
+        let resp = client.send(&HttpRequest::get("https://api.example.com/items"))?;
+        // a backoff is handed back rather than waited out
+        let retry_after =
+            match client.attempt(&HttpRequest::get("https://slow.example.com/x"), 1)? {
+                Attempt::Retry { retry_after_ms, .. } => retry_after_ms,
+                Attempt::Done(_) => 0,
+            };
+        // the last attempt's response, whose failure opens the circuit for the next call
+        let down = client.send(&HttpRequest::get("https://down.example.com/x"))?;
+        let open = client.send(&HttpRequest::get("https://down.example.com/x"));
+        bcc.make_success_json(&json!({
+            "status": resp.status,
+            "retry_after": retry_after,
+            "down": down.status,
+            "open": open.map_err(|e| e.to_string()).err(),
+        }))
+    }
+
+    #[test]
+    fn test_resilient_client() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        let draft = fab.edit(&qinfo.id).unwrap();
+        let mut api_calls = 0;
+        fab.stub("ProxyHttp", move |p| {
+            let url = p["request"]["url"].as_str().unwrap_or_default();
+            if url.contains("api.example.com") {
+                api_calls += 1;
+                if api_calls < 3 {
+                    return Ok(json!({"status" : 503}));
+                }
+                return Ok(json!({"status" : 200, "body" : ""}));
+            }
+            Ok(json!({"status" : 503}))
+        });
+        install(fab);
+        let res = run_handler(do_resilient, request("r", "/r", &draft)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["status"], 200);
+        assert_eq!(res["result"]["retry_after"], 500);
+        assert_eq!(res["result"]["down"], 503);
+        assert!(res["result"]["open"]
+            .as_str()
+            .unwrap()
+            .starts_with("CircuitOpen"));
+        with_fabric(|f| {
+            let proxied = f.calls().iter().filter(|c| c.op == "ProxyHttp").count();
+            assert_eq!(proxied, 7);
+        });
+    }
+
+    // an id and a hash of it in the fabric's encoding: 32 digest bytes, the size as a varint and the id bytes
+    fn fabric_ids(n: u8) -> (String, String) {
+        fn base58(data: &[u8]) -> String {
+            const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+            let mut digits: Vec<u8> = Vec::new();
+            for &b in data {
+                let mut carry = b as u32;
+                for d in digits.iter_mut() {
+                    carry += (*d as u32) << 8;
+                    *d = (carry % 58) as u8;
+                    carry /= 58;
+                }
+                while carry > 0 {
+                    digits.push((carry % 58) as u8);
+                    carry /= 58;
+                }
+            }
+            digits
+                .iter()
+                .rev()
+                .map(|&d| ALPHABET[d as usize] as char)
+                .collect()
+        }
+        let id = [n, 7, 9, 11];
+        let mut raw = vec![n; 32];
+        raw.push(100);
+        raw.extend(id);
+        (
+            format!("iq__{}", base58(&id)),
+            format!("hq__{}", base58(&raw)),
+        )
+    }
+
+    fn linked_object(fab: &mut MockFabric, n: u8, qlib_id: &str, links: &[&str]) -> String {
+        let (id, hash) = fabric_ids(n);
+        let links: Vec<Value> = links
+            .iter()
+            .map(|h| json!({ "/": format!("/qfab/{h}/meta/public") }))
+            .collect();
+        fab.add_object(Object {
+            id,
+            qlib_id: qlib_id.to_string(),
+            versions: vec![Version {
+                hash: hash.clone(),
+                meta: json!({"public" : {"title" : format!("object {n}"), "links" : links}}),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        hash
+    }
+
+    fn do_crawl(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::search::*;
+        let config = IndexerConfig {
+            indexer_type: "metadata-text".to_string(),
+            document: FabricDocument {
+                prefix: "/public".to_string(),
+            },
+            fields: vec![],
+            fabric: FabricConfig {
+                policy: FabricPolicy {
+                    paths: vec!["/public/items".to_string()],
+                    max_depth: Some(2),
+                },
+                root: RootConfig {
+                    content: bcc.request.q_info.id.clone(),
+                    library: bcc.request.q_info.qlib_id.clone(),
+                },
+            },
+        };
+        let crawled = Crawler::new(bcc, "").crawl(&config)?;
+        bcc.make_success_json(&serde_json::to_value(crawled)?)
+    }
+
+    #[test]
+    fn test_crawl() {
+        let mut fab = MockFabric::new();
+        // y is reached through x before its direct link from the root is seen, z links back to y
+        let (_, y) = fabric_ids(2);
+        let (_, z) = fabric_ids(3);
+        let x = linked_object(&mut fab, 1, "ilib1", &[&y]);
+        linked_object(&mut fab, 2, "ilib2", &[&z]);
+        linked_object(&mut fab, 3, "ilib2", &[&y]);
+        let links: Vec<Value> = [&x, &y]
+            .iter()
+            .map(|h| json!({ "/": format!("/qfab/{h}/meta/public") }))
+            .collect();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({"public" : {"items" : links}}));
+        install(fab);
+        let res = run_handler(do_crawl, request("crawl", "/crawl", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        let reg = &res["result"]["mdregistry"];
+        assert_eq!(reg[&y]["depth"], 1);
+        assert_eq!(reg[&y]["qlib_id"], "ilib2");
+        assert_eq!(
+            reg[&y]["links"],
+            json!(["/public/items[1]", "/public/links[0]"])
+        );
+        assert_eq!(reg[&z]["depth"], 2);
+        assert_eq!(reg[&z]["meta"]["title"], "object 3");
+        let stats = &res["result"]["stats"];
+        assert_eq!(stats["objects"], 4);
+        assert_eq!(stats["cycles"], 1);
+        assert_eq!(stats["errors"], 0);
+    }
+
+    // answers the search extension operations of building, restoring, writing, archiving and opening a
+    // searcher on an index, the index parts archived being numbered; searches and terms are left to the tests
+    fn stub_search(fab: &mut MockFabric) {
+        let ids = std::rc::Rc::new(std::cell::Cell::new(0));
+        let next = move || {
+            ids.set(ids.get() + 1);
+            ids.get()
+        };
+        for op in [
+            "BuilderAddTextField",
+            "BuilderAddU64Field",
+            "BuilderAddI64Field",
+            "BuilderAddF64Field",
+            "BuilderAddDateField",
+            "BuilderAddBoolField",
+            "BuilderAddFacetField",
+            "BuilderAddBytesField",
+            "BuilderAddJsonField",
+        ] {
+            let next = next.clone();
+            fab.stub(op, move |_| {
+                Ok(json!({"http" : {"body" : {"field" : next()}}}))
+            });
+        }
+        let docs = next.clone();
+        fab.stub("DocumentCreate", move |_| {
+            Ok(json!({"http" : {"body" : {"document-create-id" : docs()}}}))
+        });
+        for op in ["NewIndexBuilder", "RestoreIndexFromPart"] {
+            fab.stub(op, |_| {
+                Ok(json!({"http" : {"body" : {"dir" : "/tmp/index"}}}))
+            });
+        }
+        fab.stub("ArchiveIndexToPart", move |_| {
+            Ok(json!({"http" : {"body" : {"part_hash" : format!("hqp_index{}", next())}}}))
+        });
+        for op in [
+            "BuilderRegisterTokenizer",
+            "BuilderBuild",
+            "BuilderCreateIndex",
+            "DocumentCreateIndex",
+            "IndexCreateWriter",
+            "IndexWriterAddDocument",
+            "IndexWriterCommit",
+            "IndexWriterDeleteTerm",
+            "IndexWriterRollback",
+            "DocumentAddText",
+            "DocumentAddU64",
+            "DocumentAddI64",
+            "DocumentAddF64",
+            "DocumentAddDate",
+            "DocumentAddBool",
+            "DocumentAddFacet",
+            "DocumentAddBytes",
+            "DocumentAddJson",
+            "IndexReaderBuilderCreate",
+            "IndexReaderSearcher",
+            "QueryParserForIndex",
+            "QueryParserParseQuery",
+            "QueryParserParseJsonQuery",
+        ] {
+            fab.stub(op, |_| Ok(json!({"http" : {"body" : {}}})));
+        }
+    }
+
+    // an object of the index tests, with a title and a two dimensional embedding
+    fn document_object(fab: &mut MockFabric, n: u8, embedding: [f32; 2]) -> String {
+        let (id, hash) = fabric_ids(n);
+        fab.add_object(Object {
+            id,
+            qlib_id: "ilib1".to_string(),
+            versions: vec![Version {
+                hash: hash.clone(),
+                meta: json!({"public" : {"title" : format!("object {n}"), "embedding" : embedding}}),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        hash
+    }
+
+    fn items(hashes: &[&String]) -> Value {
+        let links: Vec<Value> = hashes
+            .iter()
+            .map(|h| json!({ "/": format!("/qfab/{h}/meta/public") }))
+            .collect();
+        json!({"public" : {"items" : links}})
+    }
+
+    // a root content linking to objects 1 and 2 and a content indexing it, configured with a suggested title
+    // and an embedding field, returning the root and a write token on the index content
+    fn index_fixture(fab: &mut MockFabric) -> (QInfo, QInfo) {
+        let one = document_object(fab, 1, [1.0, 1.0]);
+        let two = document_object(fab, 2, [2.0, 1.0]);
+        let root = fab.create_content("ilib1", "hq__root", items(&[&one, &two]));
+        let config = json!({
+            "indexer" : {
+                "type" : "metadata-text",
+                "arguments" : {
+                    "document" : {"prefix" : "/public"},
+                    "fields" : {
+                        "title" : {"type" : "text", "options" : {"suggest" : true}, "paths" : ["title"]},
+                        "embedding" : {
+                            "type" : "vector",
+                            "options" : {"dimensions" : 2},
+                            "paths" : ["embedding"],
+                        },
+                    },
+                },
+            },
+            "fabric" : {
+                "root" : {"content" : root.id, "library" : root.qlib_id},
+                "policy" : {"paths" : ["/public/items"]},
+            },
+        });
+        let index = fab.create_content(
+            "ilib1",
+            "hq__index",
+            json!({"indexer" : {"config" : config}}),
+        );
+        let draft = fab.edit(&index.id).unwrap();
+        stub_search(fab);
+        (root, draft)
+    }
+
+    fn do_update(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::search::*;
+        let config = IndexerConfig::from_meta(bcc)?;
+        let update = update_index(bcc, &config)?;
+        bcc.make_success_json(&update)
+    }
+
+    fn update(draft: &QInfo) -> Value {
+        let res = run_handler(do_update, request("update", "/update", draft)).unwrap();
+        serde_json::from_slice::<Value>(&res).unwrap()["result"].clone()
+    }
+
+    #[test]
+    fn test_index_update() {
+        let mut fab = MockFabric::new();
+        let (root, draft) = index_fixture(&mut fab);
+        install(fab);
+        let first = update(&draft);
+        assert_eq!(first["status"], "updated");
+        assert_eq!(first["plan"]["added"].as_array().unwrap().len(), 2);
+        let state = with_fabric(|f| f.meta(&draft.write_token, "/indexer/state").cloned()).unwrap();
+        let vector_part = state["current"]["vector_parts"]["embedding"].clone();
+        assert!(vector_part.is_string());
+        let indexed = with_fabric(|f| f.finalize(&draft.write_token)).unwrap();
+
+        // the root did not move: nothing is crawled, restored or written
+        let draft = with_fabric(|f| f.edit(&indexed.id)).unwrap();
+        let calls = with_fabric(|f| f.calls().len());
+        let second = update(&draft);
+        assert_eq!(second["status"], "unchanged");
+        assert_eq!(second["part_hash"], first["part_hash"]);
+        with_fabric(|f| {
+            for c in &f.calls()[calls..] {
+                assert!(![
+                    "SQMDGetExternal",
+                    "RestoreIndexFromPart",
+                    "QCreatePartFromStream"
+                ]
+                .contains(&c.op.as_str()));
+            }
+            assert!(f.meta(&draft.write_token, "/indexer/state").unwrap() == &state);
+        });
+
+        // object 1 is dropped and 3 added, while the embedding of 2 changes under the same hash: the vector
+        // index of the current part is updated by the plan, keeping the vector 2 was indexed with
+        let (one, two) = (fabric_ids(1), fabric_ids(2));
+        with_fabric(|f| {
+            let three = document_object(f, 3, [3.0, 1.0]);
+            let mut root_object = f.object(&root.id).unwrap().clone();
+            root_object.versions.push(Version {
+                hash: "hq__root2".to_string(),
+                meta: items(&[&two.1, &three]),
+                ..Default::default()
+            });
+            f.add_object(root_object);
+            let mut object = f.object(&two.0).unwrap().clone();
+            object.versions[0].meta["public"]["embedding"] = json!([0.0, 1.0]);
+            f.add_object(object);
+        });
+        let calls = with_fabric(|f| f.calls().len());
+        let third = update(&draft);
+        assert_eq!(third["status"], "updated");
+        assert_eq!(third["plan"]["added"], json!([fabric_ids(3).0]));
+        assert_eq!(third["plan"]["removed"], json!([one.0]));
+        assert_eq!(third["plan"]["unchanged"], 1);
+        with_fabric(|f| {
+            assert!(f.calls()[calls..]
+                .iter()
+                .any(|c| c.op == "QWritePartToStream" && c.params["qphash"] == vector_part));
+            let state = f.meta(&draft.write_token, "/indexer/state").unwrap();
+            let part = state["current"]["vector_parts"]["embedding"]
+                .as_str()
+                .unwrap();
+            let index =
+                elvwasm::search::VectorIndex::from_bytes(f.part(&draft.write_token, part).unwrap())
+                    .unwrap();
+            assert_eq!(index.len(), 2);
+            let hits = index.search(&[2.0, 1.0], 1, None).unwrap();
+            assert_eq!(hits[0].id, two.0);
+            assert!((hits[0].score - 1.0).abs() < 1e-5);
+        });
+    }
+
+    // the root and index content of [index_fixture], installed once the index is updated and finalized
+    fn indexed() -> (QInfo, QInfo) {
+        let mut fab = MockFabric::new();
+        let (root, draft) = index_fixture(&mut fab);
+        install(fab);
+        update(&draft);
+        (
+            root,
+            with_fabric(|f| f.finalize(&draft.write_token)).unwrap(),
+        )
+    }
+
+    fn query_request(method: &str, qinfo: &QInfo, query: &[(&str, &str)]) -> Request {
+        let mut req = request(method, &format!("/{method}"), qinfo);
+        req.params.http.query = query
+            .iter()
+            .map(|(k, v)| (k.to_string(), vec![v.to_string()]))
+            .collect();
+        req
+    }
+
+    // the result of a handler's success reply
+    fn handler_result(res: CallResult) -> Value {
+        let res: Value = serde_json::from_slice(&res.unwrap()).unwrap();
+        assert_eq!(res["result"]["body"], "SUCCESS", "{res}");
+        res["result"]["result"].clone()
+    }
+
+    // the message of a handler's error, either replied or returned
+    fn handler_error(res: CallResult) -> String {
+        match res {
+            Ok(r) => serde_json::from_slice::<Value>(&r).unwrap()["error"]["desc"].to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_search_handlers() {
+        use elvwasm::search::handlers::*;
+        let (root, qinfo) = indexed();
+        with_fabric(|f| {
+            f.stub("QueryParserSearch", |_| {
+                Ok(json!({"http" : {"body" : {"total_hits" : 3, "hits" : [
+                    {"score" : 2.0, "doc" : {"uid" : ["iq__a"], "title" : ["object 1"]}},
+                    {"score" : 1.0, "doc" : {"uid" : ["iq__b"], "title" : ["object 2"]}},
+                    {"score" : 0.5, "doc" : {"uid" : ["iq__c"], "title" : ["object 3"]}},
+                ]}}}))
+            });
+        });
+        let page = handler_result(run_handler(
+            search,
+            query_request("search", &qinfo, &[("query", "object"), ("limit", "2")]),
+        ));
+        assert_eq!(page["total_hits"], 3);
+        assert_eq!(page["hits"].as_array().unwrap().len(), 2);
+        let err = handler_error(run_handler(
+            search,
+            query_request("search", &qinfo, &[("limit", "ten")]),
+        ));
+        assert!(err.contains("limit must be a number, got ten"), "{err}");
+
+        let err = handler_error(run_handler(
+            federated_search,
+            query_request("federated_search", &qinfo, &[("shards", "hq__abc")]),
+        ));
+        assert!(
+            err.contains("shard hq__abc is not library:content_hash"),
+            "{err}"
+        );
+        let shard = format!("{}:{}", root.qlib_id, root.hash);
+        let err = handler_error(run_handler(
+            federated_search,
+            query_request("federated_search", &qinfo, &[("shards", &shard)]),
+        ));
+        assert!(err.contains("has not been indexed"), "{err}");
+
+        let err = handler_error(run_handler(suggest, query_request("suggest", &qinfo, &[])));
+        assert!(err.contains("prefix is required"), "{err}");
+        let err = handler_error(run_handler(
+            suggest,
+            query_request("suggest", &qinfo, &[("prefix", "ob"), ("limit", "-1")]),
+        ));
+        assert!(err.contains("limit must be a number, got -1"), "{err}");
+
+        let err = handler_error(run_handler(
+            vector_search,
+            query_request("vector_search", &qinfo, &[]),
+        ));
+        assert!(
+            err.contains("vector must be a JSON array of numbers"),
+            "{err}"
+        );
+        let err = handler_error(run_handler(
+            vector_search,
+            query_request(
+                "vector_search",
+                &qinfo,
+                &[("vector", "[2, 1]"), ("k", "all")],
+            ),
+        ));
+        assert!(err.contains("k must be a number, got all"), "{err}");
+        let found = handler_result(run_handler(
+            vector_search,
+            query_request("vector_search", &qinfo, &[("vector", "[2, 1]"), ("k", "1")]),
+        ));
+        assert_eq!(found["field"], "embedding");
+        assert_eq!(found["hits"][0]["id"], fabric_ids(2).0);
+
+        // a crawl from scratch and a rollback to the part it replaced
+        let draft = with_fabric(|f| f.edit(&qinfo.id)).unwrap();
+        let crawled = handler_result(run_handler(crawl, request("crawl", "/crawl", &draft)));
+        assert_eq!(crawled["status"], "updated");
+        let updated = handler_result(run_handler(update, request("update", "/update", &draft)));
+        assert_eq!(updated["status"], "unchanged");
+        let rolled = handler_result(run_handler(
+            rollback,
+            request("rollback", "/rollback", &draft),
+        ));
+        assert_eq!(rolled["dropped"], crawled["part_hash"]);
+        let err = handler_error(run_handler(
+            rollback,
+            request("rollback", "/rollback", &draft),
+        ));
+        assert!(err.contains("no previous index part"), "{err}");
+    }
+
+    fn do_schema(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::search::*;
+        let mut builder = SchemaBuilder::new(bcc)
+            .text("title", TextOptions::text().stored())
+            .u64("year", NumericOptions::indexed().stored().fast())
+            .facet("genre", true);
+        if bcc.request.method == "duplicate" {
+            builder = builder.bool("year", NumericOptions::indexed());
+        }
+        let schema = builder.build()?;
+        let ids: Vec<Option<u64>> = ["title", "year", "genre"]
+            .iter()
+            .map(|f| schema.field(f))
+            .collect();
+        bcc.make_success_json(&json!(ids))
+    }
+
+    #[test]
+    fn test_schema_build() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        stub_search(&mut fab);
+        install(fab);
+        let res = run_handler(do_schema, request("schema", "/schema", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"], json!([1, 2, 3]));
+        with_fabric(|f| {
+            let ops: Vec<&str> = f.calls().iter().map(|c| c.op.as_str()).collect();
+            assert_eq!(
+                ops,
+                vec![
+                    "BuilderAddTextField",
+                    "BuilderAddU64Field",
+                    "BuilderAddFacetField",
+                    "BuilderBuild"
+                ]
+            );
+            assert_eq!(
+                f.calls()[1].params,
+                json!({"name" : "year", "indexed" : true, "stored" : true, "fast" : true})
+            );
+            assert_eq!(
+                f.calls()[2].params,
+                json!({"name" : "genre", "stored" : true})
+            );
+        });
+
+        // a field declared twice fails the build before the schema is built
+        let calls = with_fabric(|f| f.calls().len());
+        let err = run_handler(do_schema, request("duplicate", "/schema", &qinfo)).unwrap_err();
+        assert!(
+            err.to_string().contains("field year declared twice"),
+            "{err}"
+        );
+        with_fabric(|f| {
+            assert!(f.calls()[calls..].iter().all(|c| c.op != "BuilderBuild"));
+        });
+    }
+
+    fn do_search(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::search::*;
+        let schema = SchemaBuilder::new(bcc)
+            .text("title", TextOptions::text().stored())
+            .text("body", TextOptions::text().stored())
+            .build()?;
+        let snippets: &[&str] = match bcc.request.method.as_str() {
+            "unknown" => &["summary"],
+            _ => &["body"],
+        };
+        let req = SearchRequest::new("sea")
+            .offset(1)
+            .limit(2)
+            .snippets(snippets);
+        let results = Searcher::new(bcc, &schema)?.search(&req)?;
+        bcc.make_success_json(&json!(results))
+    }
+
+    #[test]
+    fn test_searcher() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        stub_search(&mut fab);
+        fab.stub("QueryParserSearch", |_| {
+            Ok(json!({"http" : {"body" : {"total_hits" : 7, "hits" : [
+                {"score" : 3.0, "doc" : {"title" : ["first"], "body" : ["the sea"]}},
+                {"score" : 2.0, "doc" : {"title" : ["second"], "body" : ["a sea"]},
+                    "snippets" : {"body" : "a <b>sea</b> from the host"}},
+                {"score" : 1.0, "doc" : {"title" : ["third"], "body" : ["old man and the sea"]},
+                    "address" : {"segment_ord" : 0, "doc_id" : 9}},
+            ]}}}))
+        });
+        install(fab);
+        let res = run_handler(do_search, request("search", "/search", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        let page = &res["result"];
+        assert_eq!(page["total_hits"], 7);
+        assert_eq!(
+            (page["offset"].clone(), page["limit"].clone()),
+            (json!(1), json!(2))
+        );
+        let hits = page["hits"].as_array().unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0]["fields"]["title"], json!(["second"]));
+        assert_eq!(hits[0]["snippets"]["body"], "a <b>sea</b> from the host");
+        assert_eq!(hits[1]["snippets"]["body"], "old man and the <b>sea</b>");
+        assert_eq!(hits[1]["address"]["doc_id"], 9);
+        with_fabric(|f| {
+            let call = |op: &str| {
+                f.calls()
+                    .iter()
+                    .find(|c| c.op == op)
+                    .unwrap()
+                    .params
+                    .clone()
+            };
+            assert_eq!(
+                call("QueryParserForIndex")["fields"],
+                json!(["title", "body"])
+            );
+            assert_eq!(call("QueryParserParseQuery"), json!({"query" : "sea"}));
+            let search = call("QueryParserSearch");
+            assert_eq!(search["top_limit"], 3);
+            assert_eq!(search["snippets"], json!(["body"]));
+        });
+
+        // snippets of a field missing from the schema fail before the host is asked
+        let calls = with_fabric(|f| f.calls().len());
+        let err = run_handler(do_search, request("unknown", "/search", &qinfo)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("field summary is not in the schema"),
+            "{err}"
+        );
+        with_fabric(|f| {
+            assert!(f.calls()[calls..]
+                .iter()
+                .all(|c| c.op != "QueryParserSearch"));
+        });
+    }
+
+    fn structured_query(fuzzy: bool) -> elvwasm::search::Query {
+        use elvwasm::search::Query;
+        if fuzzy {
+            Query::boolean()
+                .must(Query::fuzzy("title", "saa", 1))
+                .should(Query::term("body", "sea"))
+        } else {
+            Query::boolean()
+                .must(Query::term("title", "sea"))
+                .must_not(Query::phrase("body", "old man"))
+        }
+    }
+
+    fn do_query(bcc: &mut BitcodeContext) -> CallResult {
+        use elvwasm::search::*;
+        let schema = SchemaBuilder::new(bcc)
+            .text("title", TextOptions::text().stored())
+            .text("body", TextOptions::text().stored())
+            .build()?;
+        let q = structured_query(bcc.request.method == "fuzzy");
+        let req = SearchRequest::with_query(&q).snippets(&["body"]);
+        let results = Searcher::new(bcc, &schema)?.search(&req)?;
+        bcc.make_success_json(&json!(results))
+    }
+
+    #[test]
+    fn test_structured_query() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        stub_search(&mut fab);
+        fab.stub("QueryParserSearch", |_| {
+            Ok(json!({"http" : {"body" : {"total_hits" : 1, "hits" : [
+                {"score" : 1.0, "doc" : {"title" : ["sea"], "body" : ["the sea"]}},
+            ]}}}))
+        });
+        install(fab);
+
+        // a query the parser syntax expresses is compiled to it
+        let plain = structured_query(false);
+        let res = run_handler(do_query, request("plain", "/search", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(
+            res["result"]["hits"][0]["snippets"]["body"],
+            "the <b>sea</b>"
+        );
+        with_fabric(|f| {
+            let ops: Vec<&str> = f.calls().iter().map(|c| c.op.as_str()).collect();
+            assert!(!ops.contains(&"QueryParserParseJsonQuery"));
+            let parsed = f
+                .calls()
+                .iter()
+                .find(|c| c.op == "QueryParserParseQuery")
+                .unwrap();
+            assert_eq!(parsed.params["query"], plain.to_query_string());
+        });
+
+        // fuzzy terms go to the host as a structured query
+        let fuzzy = structured_query(true);
+        let calls = with_fabric(|f| f.calls().len());
+        let res = run_handler(do_query, request("fuzzy", "/search", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["total_hits"], 1);
+        with_fabric(|f| {
+            let calls = &f.calls()[calls..];
+            assert!(calls.iter().all(|c| c.op != "QueryParserParseQuery"));
+            let parsed = calls
+                .iter()
+                .find(|c| c.op == "QueryParserParseJsonQuery")
+                .unwrap();
+            assert_eq!(parsed.params["query"], fuzzy.to_json());
+        });
+    }
+
+    #[test]
+    fn test_suggest() {
+        use elvwasm::search::{completion_field, handlers::suggest, suggest_field};
+        let (_, qinfo) = indexed();
+        let completion = completion_field("title");
+        with_fabric(|f| {
+            f.stub("QueryParserSearch", move |_| {
+                let hits: Vec<Value> = ["Object 1", "Object 2", "Object 1"]
+                    .iter()
+                    .map(|t| json!({"score" : 1.0, "doc" : {completion.as_str() : [t]}}))
+                    .collect();
+                Ok(json!({"http" : {"body" : {"total_hits" : 3, "hits" : hits}}}))
+            });
+            f.stub("IndexReaderTerms", |p| {
+                assert_eq!(p["fuzzy"]["term"], "objct");
+                Ok(json!({"http" : {"body" : {"terms" : [["object", 2], ["objects", 1]]}}}))
+            });
+        });
+        let found = handler_result(run_handler(
+            suggest,
+            query_request("suggest", &qinfo, &[("prefix", "Objct"), ("limit", "1")]),
+        ));
+        assert_eq!(found["field"], "title");
+        assert_eq!(
+            found["completions"],
+            json!([{"text" : "Object 1", "count" : 2}])
+        );
+        assert_eq!(found["did_you_mean"], "object");
+        with_fabric(|f| {
+            let parsed = f
+                .calls()
+                .iter()
+                .find(|c| c.op == "QueryParserParseQuery")
+                .unwrap();
+            let query = parsed.params["query"].as_str().unwrap();
+            assert!(
+                query.starts_with(&format!("{}:", suggest_field("title"))),
+                "{query}"
+            );
+        });
+
+        // a field without suggestions is refused before the index is searched
+        let calls = with_fabric(|f| f.calls().len());
+        let err = handler_error(run_handler(
+            suggest,
+            query_request(
+                "suggest",
+                &qinfo,
+                &[("prefix", "ob"), ("field", "embedding")],
+            ),
+        ));
+        assert!(
+            err.contains("field embedding is not configured for suggestions"),
+            "{err}"
+        );
+        with_fabric(|f| {
+            assert!(f.calls()[calls..]
+                .iter()
+                .all(|c| c.op != "QueryParserSearch"));
+        });
+    }
+
+    #[test]
+    fn test_federated_search() {
+        use elvwasm::search::handlers::federated_search;
+        use std::{cell::RefCell, rc::Rc};
+        let mut fab = MockFabric::new();
+        let (_, first) = index_fixture(&mut fab);
+        let (_, second) = index_fixture(&mut fab);
+        install(fab);
+        update(&first);
+        update(&second);
+        let (first, second) = with_fabric(|f| {
+            (
+                f.finalize(&first.write_token).unwrap(),
+                f.finalize(&second.write_token).unwrap(),
+            )
+        });
+        let part_of = |q: &QInfo| {
+            with_fabric(|f| f.meta(&q.hash, "/indexer/state/current/part_hash").cloned()).unwrap()
+        };
+        let (first_part, second_part) = (part_of(&first), part_of(&second));
+        assert_ne!(first_part, second_part);
+
+        // each shard answers from the part restored last, both finding b
+        let restored = Rc::new(RefCell::new(Value::Null));
+        with_fabric(|f| {
+            let last = restored.clone();
+            f.stub("RestoreIndexFromPart", move |p| {
+                *last.borrow_mut() = p["part-hash"].clone();
+                Ok(json!({"http" : {"body" : {"dir" : "/tmp/index"}}}))
+            });
+            let first_part = first_part.clone();
+            f.stub("QueryParserSearch", move |_| {
+                let hits = if *restored.borrow() == first_part {
+                    json!([
+                        {"score" : 3.0, "doc" : {"uid" : ["a"]}},
+                        {"score" : 1.0, "doc" : {"uid" : ["b"]}},
+                    ])
+                } else {
+                    json!([
+                        {"score" : 2.0, "doc" : {"uid" : ["b"]}},
+                        {"score" : 0.5, "doc" : {"uid" : ["c"]}},
+                    ])
+                };
+                Ok(json!({"http" : {"body" : {"total_hits" : 2, "hits" : hits}}}))
+            });
+        });
+        let shards = format!("{}:{}", second.qlib_id, second.hash);
+        let page = handler_result(run_handler(
+            federated_search,
+            query_request(
+                "federated_search",
+                &first,
+                &[("query", "object"), ("limit", "2"), ("shards", &shards)],
+            ),
+        ));
+        assert_eq!(page["total_hits"], 3);
+        let hits: Vec<(Value, Value)> = page["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| (h["fields"]["uid"][0].clone(), h["part"].clone()))
+            .collect();
+        assert_eq!(
+            hits,
+            vec![
+                (json!("a"), first_part.clone()),
+                (json!("b"), second_part.clone())
+            ]
+        );
+        with_fabric(|f| {
+            let restores: Vec<&Value> = f
+                .calls()
+                .iter()
+                .filter(|c| c.op == "RestoreIndexFromPart")
+                .map(|c| &c.params)
+                .collect();
+            assert_eq!(restores.len(), 2);
+            assert_eq!(restores[1]["content-hash"], second.hash);
+            assert_eq!(restores[1]["part-hash"], second_part);
+            for c in f.calls().iter().filter(|c| c.op == "QueryParserSearch") {
+                assert_eq!(c.params["top_limit"], 2);
+            }
+        });
+    }
+
+    fn do_parts(bcc: &mut BitcodeContext) -> CallResult {
+        let pl: QPartList = bcc
+            .q_part_list(bcc.request.q_info.hash.clone())
+            .try_into()?;
+        match pl.part_list.parts.first() {
+            Some(p) => bcc.make_success_json(&json!({ "size": p.size })),
+            None => bcc.make_error_with_kind(ErrorKinds::NotExist("no parts".to_string())),
+        }
+    }
+
+    #[test]
+    fn test_run_jpc() {
+        let mut fab = MockFabric::new();
+        let qinfo = fab.create_content("ilib1", "hq__type", json!({}));
+        fab.add_part(&qinfo.hash, b"12345").unwrap();
+        install(fab);
+        register_handler("parts", do_parts);
+        let res = run_jpc(&request("parts", "/parts", &qinfo)).unwrap();
+        let res: Value = serde_json::from_slice(&res).unwrap();
+        assert_eq!(res["result"]["size"], 5);
+        with_fabric(|f| assert_eq!(f.calls()[0].op, "QPartList"));
+    }
+}
@@ -8,8 +8,8 @@ extern crate scopeguard;
 use base64::{engine::general_purpose, Engine as _};
 use elvwasm::BitcodeContext;
 use elvwasm::{
-    implement_bitcode_module, jpc, register_handler, CreatePartResult, ErrorKinds,
-    ExternalCallResult, FinalizeCallResult, NewStreamResult,
+    implement_bitcode_module, jpc, register_handler, CreatePartResult, ErrorKinds, ExternalBitcode,
+    ExternalCallResult, ExternalRequest, FinalizeCallResult, NewStreamResult,
 };
 use serde_json::json;
 use std::convert::TryInto;
@@ -111,31 +111,15 @@ fn do_external(bcc: &mut BitcodeContext) -> CallResult {
         .get("tar_hash")
         .ok_or(ErrorKinds::Invalid("tar_hash not present".to_string()))?[0];
     bcc.log_info(&format!("img_hash ={img_hash:?} tar_hash = {tar_hash:?}"))?;
-    let params = json!({
-        "http" : {
-            "verb" : "GET",
-            "headers": {
-                "Content-type": [
-                    "application/json"
-                ]
-            },
-            "path" : "/image/default/files/assets/birds.jpg",
-            "query" : {
-                "height" : ["200"],
-            },
-        },
-    });
-    let exr: ExternalCallResult = bcc
-        .call_external_bitcode("image", &params, img_obj, "builtin")
-        .try_into()?;
-    bcc.log_info("here")?;
-    let imgbits = &general_purpose::STANDARD.decode(&exr.fout)?;
-    console_log(&format!(
-        "imgbits decoded size = {} fout size = {}",
-        imgbits.len(),
-        exr.fout.len()
-    ));
-    bcc.log_debug(&format!("fout {}", &exr.fout))?;
+    let image = ExternalBitcode::new(bcc, img_obj, "builtin");
+    let img_resp = image.call(
+        "image",
+        &ExternalRequest::get("/image/default/files/assets/birds.jpg")
+            .header("Content-type", "application/json")
+            .query("height", "200"),
+    )?;
+    let imgbits = &img_resp.body;
+    console_log(&format!("imgbits decoded size = {}", imgbits.len()));
     let stream_img: NewStreamResult = bcc.new_stream().try_into()?;
     defer! {
         bcc.log_debug(&format!("Closing part stream {}", &stream_img.stream_id)).unwrap_or_default();
@@ -152,29 +136,15 @@ fn do_external(bcc: &mut BitcodeContext) -> CallResult {
     let fc: FinalizeCallResult = bcc
         .q_finalize_content(&bcc.request.q_info.write_token)
         .try_into()?;
-    let tar_params = json!({
-        "http" : {
-            "verb" : "some",
-            "headers": {
-                "Content-type": [
-                    "application/json"
-                ]
-            },
-            "path" : "/tar",
-            "query" : {
-                "object_id_or_hash" : [fc.qhash],
-            },
-        },
-    });
-    let exr_tar: ExternalCallResult = bcc
-        .call_external_bitcode("tar", &tar_params, &fc.qhash, tar_hash)
-        .try_into()?;
-    let tarbits = &general_purpose::STANDARD.decode(&exr_tar.fout)?;
-    bcc.log_info(&format!(
-        "fout size = {} tar_ bit len = {}",
-        &exr_tar.fout.len(),
-        tarbits.len()
-    ))?;
+    let tar = ExternalBitcode::new(bcc, &fc.qhash, tar_hash);
+    let tar_resp = tar.call(
+        "tar",
+        &ExternalRequest::new("some", "/tar")
+            .header("Content-type", "application/json")
+            .query("object_id_or_hash", &fc.qhash),
+    )?;
+    let tarbits = &tar_resp.body;
+    bcc.log_info(&format!("tar bit len = {}", tarbits.len()))?;
     bcc.callback(200, "application/zip", tarbits.len())?;
     bcc.write_stream("fos", tarbits)?;
     bcc.make_success_json(&json!({}))
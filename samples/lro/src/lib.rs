@@ -1,34 +1,68 @@
 extern crate elvwasm;
 extern crate serde;
+extern crate serde_derive;
 extern crate serde_json;
 
 use std::convert::TryInto;
 
-use elvwasm::{implement_bitcode_module, jpc, register_handler, LROResult, ModifyResult};
+use elvwasm::{
+    implement_bitcode_module, jpc, lro_cancel_handler, lro_status_handler, register_handler,
+    LroContext, LroJob, ModifyResult,
+};
+use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 
-implement_bitcode_module!("lro", do_lro, "callback", do_lro_callback);
+implement_bitcode_module!(
+    "lro",
+    do_lro,
+    "callback",
+    do_lro_callback,
+    "status",
+    lro_status_handler,
+    "cancel",
+    lro_cancel_handler
+);
+
+#[derive(Serialize, Deserialize)]
+struct ModifyArgs {
+    arg1: String,
+}
+
+struct ModifyJob;
+
+impl LroJob for ModifyJob {
+    const JOB_TYPE: &'static str = "callback";
+    type Args = ModifyArgs;
+    type Output = String;
+
+    fn run(
+        ctx: &LroContext,
+        args: ModifyArgs,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let bcc = ctx.bcc();
+        bcc.log_info(&format!("IN CALLBACK arg1 = {}", args.arg1))?;
+        ctx.check_cancelled()?;
+        let mr: ModifyResult = bcc.q_modify_content().try_into()?;
+        bcc.log_info(&format!("write token = {}", mr.qwtoken))?;
+        ctx.report_progress(1.0, "content modified")?;
+        Ok(mr.qwtoken)
+    }
+}
 
 #[no_mangle]
 fn do_lro(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
-    let http_p = &bcc.request.params.http;
-    let _qp = &http_p.query;
-    let bhandle = bcc.start_bitcode_lro("", "callback", &json!({"arg1" : "test"}))?;
-    let bhandle: LROResult = serde_json::from_slice(&bhandle)?;
+    let status = bcc.lro_start::<ModifyJob>(&ModifyArgs {
+        arg1: "test".to_string(),
+    })?;
     bcc.make_success_json(&json!(
     {
         "headers" : "application/json",
-        "body" : bhandle,
+        "body" : status,
         "result" : "complete",
     }))
 }
 
 #[no_mangle]
 fn do_lro_callback(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
-    let http_p = &bcc.request.params.http;
-    let _qp = &http_p.query;
-    bcc.log_info("IN CALLBACK!!!!!!!")?;
-    let mr: ModifyResult = bcc.q_modify_content().try_into()?;
-    bcc.log_info(&format!("write token = {}", mr.qwtoken))?;
-    bcc.make_success_json(&json!({}))
+    bcc.lro_run::<ModifyJob>()
 }
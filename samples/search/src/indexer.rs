@@ -1,167 +1,4 @@
-#![allow(dead_code, clippy::box_collection)]
-
-use crate::{crawler, utils::extract_body};
-
-use elvwasm::{BitcodeContext, ErrorKinds};
-use serde_json::{json, Value};
-use std::{collections::HashMap, error::Error};
-
-pub struct Indexer {
-    pub filepath: String,
-    pub fields: Vec<crawler::FieldConfig>,
-}
-
-impl Indexer {
-    pub fn new(
-        bcc: &mut BitcodeContext,
-        filepath: String,
-        fields: Vec<crawler::FieldConfig>,
-    ) -> Result<Indexer, Box<dyn Error + Send + Sync>> {
-        // Read request
-        let http_p = &bcc.request.params.http;
-        let query_params = &http_p.query;
-        bcc.log_debug(&format!(
-            "In create_index hash={} headers={:#?} query params={query_params:#?}",
-            &bcc.request.q_info.hash, &http_p.headers
-        ))?;
-        let _id = &bcc.request.id;
-
-        // Create index in directory
-        let mut input_data = json!({
-            "directory": "index" //TODO is this correct directory?
-        });
-        bcc.log_debug("before BUILDER")?;
-        bcc.new_index_builder(input_data)?;
-        bcc.log_debug("NEW INDEX BUILDER")?;
-
-        // Add fields to schema builder
-        for field_config in &fields {
-            Indexer::add_field_to_schema(bcc, field_config)?;
-        }
-
-        // Build index
-        input_data = json!({});
-        bcc.builder_build(Some(input_data))?;
-
-        Ok(Indexer { filepath, fields })
-    }
-
-    fn add_field_to_schema(
-        bcc: &BitcodeContext,
-        field_config: &crawler::FieldConfig,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        //TODO: Add support for other fields.
-        let input_data;
-        match field_config.field_type.as_str() {
-            "text" => {
-                input_data = json!({
-                    "name": field_config.name,
-                    "type": 1_u8, //FIXME this should be a TextOption
-                    "stored": true,
-                });
-                let field_title_vec = bcc.builder_add_text_field(Some(input_data))?;
-                let ft_json: serde_json::Value = serde_json::from_slice(&field_title_vec)?;
-                match extract_body(ft_json) {
-                    Some(o) => o.get("field").unwrap().as_u64(),
-                    None => {
-                        return Err(Box::new(ErrorKinds::BadHttpParams(
-                            "could not find key document-create-id".to_string(),
-                        )))
-                    }
-                };
-                bcc.log_debug("ADDED TEXT FIELD.")?;
-            }
-            "string" => {
-                input_data = json!({
-                    "name": field_config.name,
-                    "type": 1_u8, //FIXME this should be a TextOption. What is the right number here?
-                    "stored": true,
-                });
-                let field_title_vec = bcc.builder_add_text_field(Some(input_data))?;
-                let ft_json: serde_json::Value = serde_json::from_slice(&field_title_vec)?;
-                match extract_body(ft_json) {
-                    Some(o) => o.get("field").unwrap().as_u64(),
-                    None => {
-                        return Err(Box::new(ErrorKinds::BadHttpParams(
-                            "could not find key document-create-id".to_string(),
-                        )))
-                    }
-                };
-                bcc.log_debug("ADDED STRING FIELD.")?;
-            }
-            _ => panic!("unknown field type"),
-        }
-        Ok(())
-    }
-}
-
-struct Writer<'a> {
-    bcc: &'a BitcodeContext,
-    fields: HashMap<String, crawler::FieldConfig>,
-}
-
-impl<'a> Writer<'a> {
-    pub fn new(
-        bcc: &'a BitcodeContext,
-        fields: HashMap<String, crawler::FieldConfig>,
-    ) -> Writer<'a> {
-        Writer { bcc, fields }
-    }
-
-    pub fn index(
-        &self,
-        uid: &String,
-        data: &Value,
-        fields: &Value,
-    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-        assert!(fields.is_object());
-
-        let response_vec = self.bcc.document_create(None)?;
-        let response_val: serde_json::Value = serde_json::from_slice(&response_vec)?;
-        let doc_id = match extract_body(response_val) {
-            Some(o) => o.get("document-create-id").unwrap().as_u64(),
-            None => {
-                return self.bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                    "could not find key document-create-id".to_string(),
-                ))
-            }
-        }
-        .unwrap();
-        self.document_add_field("uid", "text", &json!(uid), doc_id)?;
-        self.document_add_field("data", "text", data, doc_id)?;
-
-        for field in fields.as_object().unwrap() {
-            let field_type = &self.fields.get(field.0).unwrap().field_type;
-            self.document_add_field(field.0, field_type, field.1, doc_id)?;
-        }
-        Ok(Vec::new())
-    }
-
-    fn document_add_field(
-        &self,
-        field_name: &str,
-        field_type: &str,
-        field_content: &Value,
-        doc_id: u64,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        match field_type {
-            "text" | "string" => {
-                let input = json!({
-                    "field": field_name,
-                    "value": field_content.as_str(),
-                    "doc": doc_id
-                });
-                self.bcc.document_add_text(Some(input))?;
-            }
-            _ => {
-                return Err(Box::new(ErrorKinds::Invalid(
-                    "invalid field type encountered".to_string(),
-                )))
-            }
-        }
-        Ok(())
-    }
-}
+pub use elvwasm::search::{DocumentWriter, Indexer, DATA_FIELD, UID_FIELD};
 
 // The following are mearly intended to verify internal consistency.  There are no actual calls made
 // but the tests verify that the json parsing of the http message is correct
@@ -182,9 +19,10 @@ mod tests {
     extern crate tempdir;
     use base64::engine::general_purpose;
     use base64::Engine;
-    use elvwasm::Request;
+    use elvwasm::{BitcodeContext, Request};
     use json_dotpath::DotPaths;
     use std::collections::hash_map::RandomState;
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::BufReader;
     use std::sync::Arc;
@@ -194,6 +32,7 @@ mod tests {
     use tantivy_jpc::tests::{FakeContext, TestDocument};
 
     use serde_derive::{Deserialize, Serialize};
+    use serde_json::{json, Value};
 
     pub static mut QFAB: MockFabric = MockFabric {
         fab: None,
@@ -641,9 +480,8 @@ mod tests {
         let index_object_meta: Value = serde_json::from_str(INDEX_CONFIG)
             .expect("Could not read index object into json value.");
         let config_value: &Value = &index_object_meta["indexer"]["config"];
-        let indexer_config: crawler::IndexerConfig =
-            crawler::IndexerConfig::parse_index_config(config_value)
-                .expect("Could not parse indexer config.");
+        let indexer_config = elvwasm::search::IndexerConfig::parse_index_config(config_value)
+            .expect("Could not parse indexer config.");
         let new_id = "id123".to_string();
         let req = &Request {
             id: new_id.clone(),
@@ -672,13 +510,9 @@ mod tests {
                 write_token: "tqw_5555".to_string(),
             },
         };
-        let mut bcc = BitcodeContext::new(req.clone());
-        let idx = Indexer::new(
-            &mut bcc,
-            indexer_config.document.prefix.as_ref().to_string(),
-            indexer_config.fields.clone(),
-        )
-        .expect("failed to create index");
+        let bcc = BitcodeContext::new(req.clone());
+        let idx =
+            Indexer::new(&bcc, indexer_config.fields.clone()).expect("failed to create index");
         assert_eq!(&idx.fields.len(), &indexer_config.fields.len());
     }
 }
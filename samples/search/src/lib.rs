@@ -1,7 +1,6 @@
 mod old_man;
 
 pub mod crawler;
-pub mod graph;
 pub mod indexer;
 pub mod searcher;
 pub mod utils;
@@ -9,13 +8,17 @@ pub mod utils;
 extern crate elvwasm;
 extern crate serde_json;
 
-use crawler::FieldConfig;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 
 use crate::old_man::S_OLD_MAN;
+use elvwasm::search::handlers::{
+    crawl as index_crawl, federated_search as index_federated_search, rollback as index_rollback,
+    search as index_search, suggest as index_suggest, update as index_update,
+    vector_search as index_vector_search,
+};
+use elvwasm::search::{IndexWriter, SchemaBuilder, SearchRequest, Searcher, TextOptions};
 use elvwasm::ErrorKinds;
-use indexer::Indexer;
 use snailquote::unescape;
 
 use elvwasm::{implement_bitcode_module, jpc, register_handler};
@@ -30,7 +33,19 @@ implement_bitcode_module!(
     "search_update",
     do_search_update,
     "search_update_new",
-    do_search_update_new,
+    index_update,
+    "search_rollback",
+    index_rollback,
+    "index_crawl",
+    index_crawl,
+    "index_search",
+    index_search,
+    "index_federated_search",
+    index_federated_search,
+    "index_suggest",
+    index_suggest,
+    "index_vector_search",
+    index_vector_search,
     "search",
     do_search
 );
@@ -120,53 +135,22 @@ fn do_crawl(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
             ))
         }
     };
-    let ft_json: serde_json::Value = serde_json::from_slice(&bcc.builder_add_text_field(Some(
-        json!({ "field_name": "title", "type": 2_u8, "stored": true}),
-    ))?)?;
-    let field_title = match extract_body(ft_json) {
-        Some(o) => o.get("field").unwrap().as_u64(),
-        None => {
-            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                "could not find key document-create-id".to_string(),
-            ))
-        }
-    };
-    let fb_json: serde_json::Value = serde_json::from_slice(&bcc.builder_add_text_field(Some(
-        json!({ "field_name": "body", "type": 2_u8 , "stored": true}),
-    ))?)?;
-    let field_body = match extract_body(fb_json) {
-        Some(o) => o.get("field").unwrap().as_u64(),
-        None => {
-            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                "could not find key document-create-id".to_string(),
-            ))
-        }
-    };
-    bcc.builder_build(None)?;
-    let doc_old_man: serde_json::Value = serde_json::from_slice(&bcc.document_create(None)?)?;
-    console_log(&format!("obj_old = {:?}", &doc_old_man));
-    let o_doc_id = match extract_body(doc_old_man) {
-        Some(o) => o.get("document-create-id").unwrap().as_u64(),
-        None => {
-            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                "could not find key document-create-id".to_string(),
-            ))
-        }
-    };
-    let doc_id = o_doc_id.unwrap();
+    let schema = SchemaBuilder::new(bcc)
+        .text("title", TextOptions::text().stored())
+        .text("body", TextOptions::text().stored())
+        .build()?;
+    let mut writer = IndexWriter::new(bcc, &schema)?;
+    let committed = writer.transaction(|w| {
+        let mut doc = w.document()?;
+        doc.add_text("title", "The Old Man and the Sea")?
+            .add_text("body", S_OLD_MAN)?;
+        w.add_document(doc)
+    })?;
     bcc.log_info(&format!(
-        "doc_id={doc_id}, field_title = {}, field_body={}",
-        field_title.unwrap(),
-        field_body.unwrap()
-    ))?;
-    bcc.document_add_text(Some(json!({ "field": field_title.unwrap(), "value": "The Old Man and the Sea", "doc_id": doc_id})))?;
-    bcc.document_add_text(Some(
-        json!({ "field": field_body.unwrap(), "value": S_OLD_MAN, "doc_id": doc_id}),
+        "committed {committed} documents, field_title = {:?}, field_body={:?}",
+        schema.field("title"),
+        schema.field("body")
     ))?;
-    bcc.document_create_index(None)?;
-    bcc.index_create_writer(None)?;
-    bcc.index_add_document(Some(json!({ "document_id": doc_id })))?;
-    bcc.index_writer_commit(None)?;
     let part_u8 = bcc.archive_index_to_part(&dir)?;
     let part_hash: serde_json::Value = serde_json::from_slice(&part_u8)?;
     let b = extract_body(part_hash.clone());
@@ -233,35 +217,24 @@ fn do_search(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
     let content_hash = &qp["content-hash"][0];
 
     bcc.restore_index_from_part(content_hash, part_hash)?;
-    let ft_json: serde_json::Value = serde_json::from_slice(&bcc.builder_add_text_field(Some(
-        json!({ "field_name": "title", "type": 2_u8, "stored": true}),
-    ))?)?;
-    let _field_title = match extract_body(ft_json) {
-        Some(o) => o.get("field").unwrap().as_u64(),
-        None => {
-            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                "could not find key document-create-id".to_string(),
-            ))
-        }
-    };
-    let fb_json: serde_json::Value = serde_json::from_slice(&bcc.builder_add_text_field(Some(
-        json!({ "field_name": "body", "type": 2_u8 , "stored": true}),
-    ))?)?;
-    let _field_body = match extract_body(fb_json) {
-        Some(o) => o.get("field").unwrap().as_u64(),
-        None => {
-            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
-                "could not find key document-create-id".to_string(),
-            ))
-        }
-    };
-    bcc.builder_build(None)?;
-    bcc.builder_create_index(None)?;
-    bcc.index_reader_builder_create(None)?;
-    bcc.index_reader_searcher(None)?;
-    bcc.query_parser_for_index(Some(json!({"fields" : ["title", "body"]})))?;
-    bcc.query_parser_parse_query("Sea")?;
-    let res = bcc.query_parser_search(None)?;
+    let schema = SchemaBuilder::new(bcc)
+        .text("title", TextOptions::text().stored())
+        .text("body", TextOptions::text().stored())
+        .build()?;
+    // `query` in the query parser syntax or `q`, a structured Query, searching title and body by default
+    let req = SearchRequest::from_query(qp)?;
+    if req.query.is_empty() {
+        return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
+            "one of query or q is required".to_string(),
+        ));
+    }
+    let results = Searcher::new(bcc, &schema)?.search(&req)?;
+    bcc.log_info(&format!(
+        "found {} hits, returning {}",
+        results.total_hits,
+        results.hits.len()
+    ))?;
+    let res = serde_json::to_vec(&results)?;
     bcc.callback(200, "application/json", res.len())?;
     bcc.write_stream("fos", &res)?;
     bcc.make_success_json(&json!(
@@ -272,34 +245,6 @@ fn do_search(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
     }))
 }
 
-fn do_search_update_new(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
-    let res = bcc.sqmd_get_json("/indexer/arguments/fields")?;
-    let fields: Map<String, Value> = serde_json::from_slice(&res)?;
-    let mut idx_fields = Vec::<crawler::FieldConfig>::new();
-    for (field, val) in fields.into_iter() {
-        let fc_cur = FieldConfig {
-            name: field,
-            options: serde_json::from_value(val["options"].clone()).unwrap(),
-            field_type: serde_json::from_value(val["field_type"].clone()).unwrap(),
-            paths: serde_json::from_value(val["paths"].clone()).unwrap(),
-        };
-
-        idx_fields.push(fc_cur);
-    }
-    let _idx = Indexer::new(bcc, "idx".to_string(), idx_fields)?;
-
-    let res_config = bcc.sqmd_get_json("/indexer/config")?;
-    let fields_config: Value = serde_json::from_slice(&res_config)?;
-    let _indexer_config = crawler::IndexerConfig::parse_index_config(&fields_config)?;
-
-    bcc.make_success_json(&json!(
-    {
-        "headers" : "application/json",
-        "body" : "SUCCESS",
-        "result" : {"status" : "update complete"},
-    }))
-}
-
 fn do_search_update(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
     let http_p = &bcc.request.params.http;
     let _qp = &http_p.query;
@@ -1,40 +1,40 @@
+use elvwasm::search::{self, Schema, SchemaBuilder, SearchRequest, SearchResults, TextOptions};
 use elvwasm::BitcodeContext;
+use serde_json::json;
+use std::error::Error;
 use wapc_guest::CallResult;
 
 pub fn content_query(bcc: &mut BitcodeContext) -> CallResult {
-    let searcher = Searcher { bcc };
     let http_p = &bcc.request.params.http;
     let qp = &http_p.query;
     bcc.log_debug(&format!(
         "In content_query hash={} headers={:#?} query params={qp:#?}",
         &bcc.request.q_info.hash, &http_p.headers
     ))?;
-    searcher.query(qp["query"][0].as_str())?;
-    Ok(Vec::new())
+    let req = SearchRequest::from_query(qp)?;
+    let schema = SchemaBuilder::new(bcc)
+        .text("title", TextOptions::text().stored())
+        .text("body", TextOptions::text().stored())
+        .build()?;
+    let searcher = Searcher {
+        bcc,
+        schema: &schema,
+    };
+    let results = searcher.query(&req)?;
+    bcc.make_success_json(&json!(results))
 }
 
 struct Searcher<'a> {
     bcc: &'a BitcodeContext,
+    schema: &'a Schema,
 }
 
 impl Searcher<'_> {
-    fn query(&self, query_str: &str) -> CallResult {
+    fn query(&self, req: &SearchRequest) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
         // let hash_part_id_vec = self
         //     .bcc
         //     .sqmd_get_json(&format!("indexer/part/{}", part_name))?;
         // let hash_part_id = serde_json::from_slice(&hash_part_id_vec)?;
-        self.bcc.index_reader_builder_create(None)?;
-
-        self.bcc.reader_builder_query_parser_create(None)?;
-
-        self.bcc.query_parser_for_index(Some(serde_json::from_str(
-            r#"{ "fields" : ["title", "body"] } }"#,
-        )?))?;
-
-        self.bcc.query_parser_parse_query(query_str)?;
-
-        let _search_results = self.bcc.query_parser_search(None);
-
-        Ok(Vec::new())
+        search::Searcher::new(self.bcc, self.schema)?.search(req)
     }
 }
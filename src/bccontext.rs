@@ -5,13 +5,12 @@ extern crate thiserror;
 extern crate wapc_guest as guest;
 
 use crate::{get_cargo_version, make_json_error, ErrorKinds};
-use crate::{FileStream, NewStreamResult, Request, Response};
+use crate::{NewStreamResult, Request, Response, UploadOptions};
 
 use serde_json::json;
 
 use std::fmt::Debug;
 
-use std::collections::HashMap;
 use std::str;
 
 use guest::prelude::*;
@@ -468,7 +467,7 @@ impl<'a> BitcodeContext {
     /// * `qwt` : a fabric write token
     /// *  `input_data` : a slice of u8 data
     /// *  `path` : fabric file location
-    /// *  `mime` : MIME type to store the data as (eg gif)
+    /// *  `mime` : MIME type to store the data as (eg gif), detected from the data and path if empty
     ///
     pub fn q_upload_file(
         &'a mut self,
@@ -477,24 +476,11 @@ impl<'a> BitcodeContext {
         path: &str,
         mime: &str,
     ) -> CallResult {
-        let sid = self.new_file_stream()?;
-        let new_stream: FileStream = serde_json::from_slice(&sid)?;
-        defer! {
-          let _ = self.close_stream(new_stream.stream_id.clone());
+        let mut opts = UploadOptions::new();
+        if !mime.is_empty() {
+            opts = opts.mime(mime);
         }
-        let ret_s = self.write_stream(new_stream.clone().stream_id.as_str(), input_data)?;
-        let written_map: HashMap<String, String> = serde_json::from_slice(&ret_s)?;
-        let i: i32 = written_map["written"].parse()?;
-        let j = json!({
-          "qwtoken" : qwt,
-          "stream_id": new_stream.stream_id,
-          "path":path,
-          "mime":mime,
-          "size": i,
-        });
-
-        let method = "QCreateFileFromStream";
-        self.call_function(method, j, "core")
+        self.q_upload_reader(qwt, &mut &input_data[..], path, &opts)
     }
 
     /// file_stream_size computes the current size of a fabric file stream given its stream name
@@ -0,0 +1,289 @@
+//! Memoization of expensive bitcode computations in a Q state store <br>
+//! Entries are keyed by a content hash plus a canonicalized parameter set, so identical requests against
+//! the same content version share a result, the parameters are kept with the entry and compared on a hit.
+//! Times are in milliseconds, as returned by [BitcodeContext::q_system_time](crate::BitcodeContext::q_system_time).
+//! Results should be small; large outputs (e.g. transformed
+//! images) are best stored as a part with the part hash cached instead.
+//! ```rust
+//! use elvwasm::{BitcodeCache, CachePolicy, StateStore};
+//! use serde_json::json;
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let store = StateStore::from_meta(bcc, "/state_store/qssid")?;
+//!   let cache = BitcodeCache::new(&store, CachePolicy::default().ttl(3_600_000));
+//!   let hash = bcc.request.q_info.hash.clone();
+//!   let qp = json!(bcc.request.params.http.query);
+//!   let res: String = cache.get_or_compute(&hash, &qp, || {
+//!     // expensive work here
+//!     Ok("hqp_result".to_string())
+//!   })?;
+//!   Ok(res.into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{StateStore, SystemTimeResult};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+/// Namespace of the state store used for cache entries
+pub const CACHE_NAMESPACE: &str = "cache";
+
+/// Limits applied to a [BitcodeCache], 0 means unlimited for every field
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicy {
+    /// milliseconds an entry remains valid
+    pub ttl_ms: u64,
+    /// largest serialized entry that is cached
+    pub max_entry_bytes: usize,
+    /// number of entries kept, the oldest are evicted first.  Entries are swept in batches, the count may
+    /// exceed the limit by up to a tenth before a sweep brings it back down.
+    pub max_entries: usize,
+}
+
+impl CachePolicy {
+    pub fn ttl(mut self, ttl_ms: u64) -> CachePolicy {
+        self.ttl_ms = ttl_ms;
+        self
+    }
+
+    pub fn max_entry_bytes(mut self, max_entry_bytes: usize) -> CachePolicy {
+        self.max_entry_bytes = max_entry_bytes;
+        self
+    }
+
+    pub fn max_entries(mut self, max_entries: usize) -> CachePolicy {
+        self.max_entries = max_entries;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CacheEntry {
+    created: u64,
+    #[serde(default)]
+    params: String,
+    value: Value,
+}
+
+/// BitcodeCache memoizes results in a [StateStore], see the [module documentation](self)
+pub struct BitcodeCache<'a> {
+    store: StateStore<'a>,
+    policy: CachePolicy,
+}
+
+/// canonical_json renders a value with object keys sorted at every level so that equal parameter sets
+/// produce identical strings regardless of insertion order
+pub fn canonical_json(v: &Value) -> String {
+    match v {
+        Value::Object(m) => {
+            let mut keys: Vec<&String> = m.keys().collect();
+            keys.sort();
+            let items: Vec<String> = keys
+                .iter()
+                .map(|k| {
+                    format!(
+                        "{}:{}",
+                        Value::String(k.to_string()),
+                        canonical_json(&m[*k])
+                    )
+                })
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+        Value::Array(a) => {
+            let items: Vec<String> = a.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        _ => v.to_string(),
+    }
+}
+
+// 64 bit FNV-1a, stable across builds and platforms unlike std's hasher
+fn fnv1a64(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+impl<'a> BitcodeCache<'a> {
+    /// new creates a cache whose entries live in the [CACHE_NAMESPACE] namespace of `store`
+    pub fn new(store: &StateStore<'a>, policy: CachePolicy) -> BitcodeCache<'a> {
+        BitcodeCache {
+            store: store.namespace(CACHE_NAMESPACE),
+            policy,
+        }
+    }
+
+    /// cache_key forms the key of a computation over content `content_hash` with parameters `params`
+    pub fn cache_key(content_hash: &str, params: &Value) -> String {
+        format!(
+            "{content_hash}/{:016x}",
+            fnv1a64(canonical_json(params).as_bytes())
+        )
+    }
+
+    fn now(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let st: SystemTimeResult = self.store.context().q_system_time().try_into()?;
+        Ok(st.time)
+    }
+
+    fn expired(&self, entry: &CacheEntry, now: u64) -> bool {
+        self.policy.ttl_ms != 0 && now.saturating_sub(entry.created) >= self.policy.ttl_ms
+    }
+
+    /// get retrieves a cached result, None if absent or expired
+    pub fn get<T: DeserializeOwned>(
+        &self,
+        content_hash: &str,
+        params: &Value,
+    ) -> Result<Option<T>, Box<dyn Error + Send + Sync>> {
+        let key = BitcodeCache::cache_key(content_hash, params);
+        let entry = match self.store.get::<CacheEntry>(&key)? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        // a different parameter set hashing to the same key is a miss, put replaces it
+        if entry.params != canonical_json(params) {
+            return Ok(None);
+        }
+        if self.expired(&entry, self.now()?) {
+            self.store.delete(&key)?;
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(entry.value)?))
+    }
+
+    /// put caches a result
+    /// # Returns
+    /// false if the result exceeds the policy's `max_entry_bytes` and was not cached
+    pub fn put<T: Serialize>(
+        &self,
+        content_hash: &str,
+        params: &Value,
+        val: &T,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let value = serde_json::to_value(val)?;
+        if self.policy.max_entry_bytes != 0
+            && serde_json::to_vec(&value)?.len() > self.policy.max_entry_bytes
+        {
+            return Ok(false);
+        }
+        let key = BitcodeCache::cache_key(content_hash, params);
+        let now = self.now()?;
+        if self.policy.max_entries != 0 && self.store.version(&key)? == 0 {
+            let slack = std::cmp::max(self.policy.max_entries / 10, 1);
+            if self.store.list_keys("")?.len() >= self.policy.max_entries + slack - 1 {
+                self.evict(self.policy.max_entries - 1, now)?;
+            }
+        }
+        self.store.put(
+            &key,
+            &CacheEntry {
+                created: now,
+                params: canonical_json(params),
+                value,
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// get_or_compute returns the cached result for `content_hash` and `params`, calling `compute` and
+    /// caching its result on a miss
+    pub fn get_or_compute<T, F>(
+        &self,
+        content_hash: &str,
+        params: &Value,
+        compute: F,
+    ) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T, Box<dyn Error + Send + Sync>>,
+    {
+        if let Some(v) = self.get(content_hash, params)? {
+            return Ok(v);
+        }
+        let v = compute()?;
+        self.put(content_hash, params, &v)?;
+        Ok(v)
+    }
+
+    /// invalidate removes the cached result for `content_hash` and `params`
+    pub fn invalidate(
+        &self,
+        content_hash: &str,
+        params: &Value,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.store
+            .delete(&BitcodeCache::cache_key(content_hash, params))
+    }
+
+    /// invalidate_content removes every cached result computed over `content_hash`
+    pub fn invalidate_content(
+        &self,
+        content_hash: &str,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let keys = self.store.list_keys(&format!("{content_hash}/"))?;
+        for key in &keys {
+            self.store.delete(key)?;
+        }
+        Ok(keys.len())
+    }
+
+    // drops expired entries, then the oldest entries until at most `keep` remain
+    fn evict(&self, keep: usize, now: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut live: Vec<(u64, String)> = Vec::new();
+        for (key, entry) in self.store.list::<CacheEntry>("")? {
+            if self.expired(&entry, now) {
+                self.store.delete(&key)?;
+            } else {
+                live.push((entry.created, key));
+            }
+        }
+        if live.len() <= keep {
+            return Ok(());
+        }
+        live.sort();
+        let excess = live.len() - keep;
+        for (_, key) in live.into_iter().take(excess) {
+            self.store.delete(&key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_key_is_canonical() {
+        let a = json!({"height" : ["200"], "width" : ["100"], "opts" : {"b" : 1, "a" : [2, {"y" : 1, "x" : 0}]}});
+        let b = json!({"opts" : {"a" : [2, {"x" : 0, "y" : 1}], "b" : 1}, "width" : ["100"], "height" : ["200"]});
+        assert_eq!(canonical_json(&a), canonical_json(&b));
+        assert_eq!(
+            BitcodeCache::cache_key("hq__1", &a),
+            BitcodeCache::cache_key("hq__1", &b)
+        );
+        assert_ne!(
+            BitcodeCache::cache_key("hq__1", &a),
+            BitcodeCache::cache_key("hq__2", &a)
+        );
+        assert_ne!(
+            BitcodeCache::cache_key("hq__1", &a),
+            BitcodeCache::cache_key("hq__1", &json!({"height" : ["201"]}))
+        );
+    }
+}
@@ -36,6 +36,8 @@ pub enum ErrorKinds {
     NotFinalized(String),
     #[error("BadHttpParams : {0}")]
     BadHttpParams(String),
+    #[error("CircuitOpen : {0}")]
+    CircuitOpen(String),
 }
 
 fn discriminant(v: &ErrorKinds) -> u8 {
@@ -73,3 +75,14 @@ pub fn make_success_json(msg: &serde_json::Value, id: &str) -> CallResult {
     let v = serde_json::to_vec(&js_ret)?;
     Ok(v)
 }
+
+/// is_not_exist_error reports whether `err`, an error object the host returned in place of a result,
+/// designates a missing item
+pub fn is_not_exist_error(err: &serde_json::Value) -> bool {
+    err.get("op") == Some(&json!(discriminant(&ErrorKinds::NotExist(String::new()))))
+        || err.get("desc").and_then(|d| d.get("NotExist")).is_some()
+        || err
+            .get("kind")
+            .and_then(|k| k.as_str())
+            .map_or(false, |k| k.contains("not exist"))
+}
@@ -0,0 +1,327 @@
+//! Typed client for calling the handlers of another bitcode module <br>
+//! [BitcodeContext::call_external_bitcode] takes a hand built `http` parameter object and returns either an
+//! [ExternalCallResult] whose `fout` holds the base64 encoded output or a reply whose `body` names a fabric
+//! stream holding it.  An [ExternalBitcode] is bound to an object hash and code part hash, builds the
+//! parameters from an [ExternalRequest] and decodes both kinds of reply into an [ExternalResponse].
+//! ```rust
+//! use elvwasm::{ExternalBitcode, ExternalRequest};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let qp = &bcc.request.params.http.query;
+//!   let img_obj = &qp["img_obj"][0];
+//!   let image = ExternalBitcode::new(bcc, img_obj, "builtin");
+//!   let resp = image.call(
+//!     "image",
+//!     &ExternalRequest::get("/image/default/files/assets/birds.jpg").query("height", "200"),
+//!   )?;
+//!   bcc.callback(resp.status, resp.content_type().unwrap_or("image/jpeg"), resp.body.len())?;
+//!   bcc.write_stream("fos", &resp.body)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::bccontext_http::headers_from_value;
+use crate::{is_not_exist_error, BitcodeContext, ErrorKinds, ExternalCallResult};
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+
+/// A request to a handler of an external bitcode module
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExternalRequest {
+    pub verb: String,
+    pub path: String,
+    #[serde(default)]
+    pub query: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub headers: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub body: Value,
+}
+
+impl ExternalRequest {
+    pub fn new(verb: &str, path: &str) -> ExternalRequest {
+        ExternalRequest {
+            verb: verb.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get(path: &str) -> ExternalRequest {
+        ExternalRequest::new("GET", path)
+    }
+
+    pub fn post(path: &str) -> ExternalRequest {
+        ExternalRequest::new("POST", path)
+    }
+
+    /// query appends a value to query parameter `key`
+    pub fn query(mut self, key: &str, val: &str) -> ExternalRequest {
+        self.query
+            .entry(key.to_string())
+            .or_default()
+            .push(val.to_string());
+        self
+    }
+
+    /// header appends a value to header `key`
+    pub fn header(mut self, key: &str, val: &str) -> ExternalRequest {
+        self.headers
+            .entry(key.to_string())
+            .or_default()
+            .push(val.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: Value) -> ExternalRequest {
+        self.body = body;
+        self
+    }
+
+    /// to_params renders the request as the params expected by [BitcodeContext::call_external_bitcode]
+    pub fn to_params(&self) -> Value {
+        json!({ "http": self })
+    }
+}
+
+/// The decoded reply of an external bitcode handler
+#[derive(Clone, Debug, Default)]
+pub struct ExternalResponse {
+    pub status: usize,
+    pub headers: HashMap<String, Vec<String>>,
+    pub body: Vec<u8>,
+    /// the handler's own return value, if any
+    pub function_return: Value,
+}
+
+impl ExternalResponse {
+    /// header retrieves the first value of header `key` (case insensitive)
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.first())
+            .map(|s| s.as_str())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// json decodes the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error + Send + Sync>> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// text decodes the body as utf8
+    pub fn text(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(String::from_utf8(self.body.clone())?)
+    }
+}
+
+// inline replies carry the output base64 encoded in fout, status and headers when supplied by the
+// handler in its return value and the content type otherwise in format
+fn decode_inline(
+    exr: ExternalCallResult,
+) -> Result<ExternalResponse, Box<dyn Error + Send + Sync>> {
+    let body = general_purpose::STANDARD.decode(exr.fout.trim())?;
+    let status = exr.function_return["status"].as_u64().unwrap_or(200) as usize;
+    let mut headers = headers_from_value(&exr.function_return["headers"]);
+    if !headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case("Content-Type"))
+        && !exr.format.is_empty()
+    {
+        headers.insert("Content-Type".to_string(), exr.format.clone());
+    }
+    Ok(ExternalResponse {
+        status,
+        headers,
+        body,
+        function_return: exr.function_return,
+    })
+}
+
+// a failed call comes back as the host's error object, {"op" : .., "desc" : ..} or {"op" : .., "kind" : ..},
+// in place of the reply
+fn is_error_object(v: &Value) -> bool {
+    v.get("op").is_some()
+        && (v.get("desc").is_some() || v.get("kind").is_some())
+        && v.get("fout").is_none()
+        && v.get("body").is_none()
+}
+
+/// ExternalBitcode calls the handlers of a bitcode module, see the [module documentation](self)
+pub struct ExternalBitcode<'a> {
+    bcc: &'a BitcodeContext,
+    object_hash: String,
+    code_part_hash: String,
+}
+
+impl<'a> ExternalBitcode<'a> {
+    /// new binds a client to a bitcode module
+    /// # Arguments
+    /// * `object_hash` : the content providing the context of the calls
+    /// * `code_part_hash` : the part holding the bitcode module, or `builtin` for the fabric's own handlers
+    pub fn new(
+        bcc: &'a BitcodeContext,
+        object_hash: &str,
+        code_part_hash: &str,
+    ) -> ExternalBitcode<'a> {
+        ExternalBitcode {
+            bcc,
+            object_hash: object_hash.to_string(),
+            code_part_hash: code_part_hash.to_string(),
+        }
+    }
+
+    pub fn object_hash(&self) -> &str {
+        &self.object_hash
+    }
+
+    pub fn code_part_hash(&self) -> &str {
+        &self.code_part_hash
+    }
+
+    /// call invokes handler `function` of the module
+    /// # Returns
+    /// the decoded [ExternalResponse], whether the output came back inline or as a stream
+    pub fn call(
+        &self,
+        function: &str,
+        req: &ExternalRequest,
+    ) -> Result<ExternalResponse, Box<dyn Error + Send + Sync>> {
+        let res = self.bcc.call_external_bitcode(
+            function,
+            &req.to_params(),
+            &self.object_hash,
+            &self.code_part_hash,
+        )?;
+        let v: Value = serde_json::from_slice(&res)?;
+        if is_error_object(&v) {
+            let msg = format!("{function} on {} failed err={v}", self.object_hash);
+            return Err(Box::new(if is_not_exist_error(&v) {
+                ErrorKinds::NotExist(msg)
+            } else {
+                ErrorKinds::Other(msg)
+            }));
+        }
+        if v.get("fout").is_some() {
+            return decode_inline(serde_json::from_value(v)?);
+        }
+        let stream_id = v["body"].as_str().unwrap_or_default().to_string();
+        if stream_id.is_empty() {
+            return Err(Box::new(ErrorKinds::Other(format!(
+                "{function} on {} returned neither output nor stream: {v}",
+                self.object_hash
+            ))));
+        }
+        // read_stream_chunked stops quietly on a failed read, a truncated body must be an error
+        let mut body = Vec::new();
+        let read = self.bcc.stream_reader(&stream_id).read_to_end(&mut body);
+        let _ = self.bcc.close_stream(stream_id.clone());
+        if let Err(e) = read {
+            return Err(Box::new(ErrorKinds::IO(format!(
+                "{function} on {} failed reading stream {stream_id} after {} bytes err={e}",
+                self.object_hash,
+                body.len()
+            ))));
+        }
+        Ok(ExternalResponse {
+            status: v["status"].as_u64().unwrap_or(200) as usize,
+            headers: headers_from_value(&v["headers"]),
+            body,
+            function_return: Value::Null,
+        })
+    }
+
+    /// get invokes handler `function` with a GET of `path`
+    pub fn get(
+        &self,
+        function: &str,
+        path: &str,
+        query: &HashMap<String, Vec<String>>,
+    ) -> Result<ExternalResponse, Box<dyn Error + Send + Sync>> {
+        let mut req = ExternalRequest::get(path);
+        req.query = query.clone();
+        self.call(function, &req)
+    }
+
+    /// post invokes handler `function` with a POST of a JSON `body` to `path`
+    pub fn post(
+        &self,
+        function: &str,
+        path: &str,
+        body: Value,
+    ) -> Result<ExternalResponse, Box<dyn Error + Send + Sync>> {
+        self.call(
+            function,
+            &ExternalRequest::post(path)
+                .header("Content-Type", "application/json")
+                .body(body),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_inline() {
+        let exr = ExternalCallResult {
+            function_return: json!({"headers" : {"Content-Type" : ["image/png"]}, "status" : 206}),
+            fout: general_purpose::STANDARD.encode(b"bits"),
+            format: vec!["image/jpeg".to_string()],
+        };
+        let resp = decode_inline(exr).unwrap();
+        assert_eq!(resp.status, 206);
+        assert_eq!(resp.body, b"bits");
+        assert_eq!(resp.content_type(), Some("image/png"));
+
+        let exr = ExternalCallResult {
+            function_return: Value::Null,
+            fout: general_purpose::STANDARD.encode(b"{}"),
+            format: vec!["application/json".to_string()],
+        };
+        let resp = decode_inline(exr).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("content-type"), Some("application/json"));
+    }
+
+    #[test]
+    fn test_is_error_object() {
+        assert!(is_error_object(
+            &json!({"op" : 6, "desc" : {"NotExist" : "no hq__1"}})
+        ));
+        assert!(is_error_object(&json!({"op" : "call", "kind" : "invalid"})));
+        assert!(!is_error_object(&json!({"fout" : "", "format" : []})));
+        assert!(!is_error_object(&json!({"body" : "sid_1", "status" : 200})));
+    }
+
+    #[test]
+    fn test_request_params() {
+        let req = ExternalRequest::get("/tar")
+            .query("object_id_or_hash", "hq__1")
+            .header("Content-type", "application/json");
+        let p = req.to_params();
+        assert_eq!(p["http"]["verb"], "GET");
+        assert_eq!(p["http"]["path"], "/tar");
+        assert_eq!(p["http"]["query"]["object_id_or_hash"][0], "hq__1");
+        assert_eq!(p["http"]["headers"]["Content-type"][0], "application/json");
+    }
+}
@@ -0,0 +1,423 @@
+//! Typed http client over the fabric's `ProxyHttp` and `RestCall` extensions <br>
+//! [BitcodeContext::proxy_http] and [BitcodeContext::rest_call] take an untyped `{"request" : {...}}` value and
+//! return the response body base64 encoded in `result`.  An [HttpClient] builds the request from an
+//! [HttpRequest] and decodes the reply into a [ProxyResponse].
+//! ```rust
+//! use elvwasm::{HttpClient, HttpRequest};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let client = HttpClient::proxy(bcc).timeout_ms(10000);
+//!   let resp = client.send(
+//!     &HttpRequest::get("https://www.googleapis.com/customsearch/v1")
+//!       .query("q", "fabric & friends")
+//!       .header("Accept", "application/json"),
+//!   )?;
+//!   let v: serde_json::Value = resp.json()?;
+//!   bcc.make_success_json(&v)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{BitcodeContext, ErrorKinds};
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// The fabric extension carrying an [HttpClient]'s requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpTransport {
+    /// arbitrary urls through [BitcodeContext::proxy_http]
+    ProxyHttp,
+    /// rest services configured on the node through [BitcodeContext::rest_call]
+    RestCall,
+}
+
+/// encode_uri_component percent encodes every byte of `s` outside the unreserved set of RFC 3986
+/// ```rust
+/// assert_eq!(elvwasm::encode_uri_component("a b&c=d/é"), "a%20b%26c%3Dd%2F%C3%A9");
+/// ```
+pub fn encode_uri_component(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+#[derive(Clone, Debug)]
+enum HttpBody {
+    Empty,
+    Json(Value),
+    Bytes(Vec<u8>),
+}
+
+/// An http request sent by an [HttpClient]
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    method: String,
+    url: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: HttpBody,
+    timeout_ms: Option<u64>,
+    max_redirects: Option<u32>,
+}
+
+impl HttpRequest {
+    pub fn new(method: &str, url: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.to_ascii_uppercase(),
+            url: url.to_string(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: HttpBody::Empty,
+            timeout_ms: None,
+            max_redirects: None,
+        }
+    }
+
+    pub fn get(url: &str) -> HttpRequest {
+        HttpRequest::new("GET", url)
+    }
+
+    pub fn post(url: &str) -> HttpRequest {
+        HttpRequest::new("POST", url)
+    }
+
+    pub fn put(url: &str) -> HttpRequest {
+        HttpRequest::new("PUT", url)
+    }
+
+    pub fn delete(url: &str) -> HttpRequest {
+        HttpRequest::new("DELETE", url)
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// query appends a query parameter, encoded when the url is rendered
+    pub fn query(mut self, key: &str, val: &str) -> HttpRequest {
+        self.query.push((key.to_string(), val.to_string()));
+        self
+    }
+
+    /// header sets a header, replacing any previous value
+    pub fn header(mut self, key: &str, val: &str) -> HttpRequest {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.headers.push((key.to_string(), val.to_string()));
+        self
+    }
+
+    /// json sets a JSON body along with its `Content-Type`
+    pub fn json(self, body: Value) -> HttpRequest {
+        let mut req = self.header("Content-Type", "application/json");
+        req.body = HttpBody::Json(body);
+        req
+    }
+
+    /// bytes sets a binary body, which is sent base64 encoded
+    pub fn bytes(self, body: Vec<u8>, content_type: &str) -> HttpRequest {
+        let mut req = self.header("Content-Type", content_type);
+        req.body = HttpBody::Bytes(body);
+        req
+    }
+
+    /// timeout_ms overrides the client's timeout for this request
+    pub fn timeout_ms(mut self, timeout_ms: u64) -> HttpRequest {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// max_redirects overrides the client's redirect limit for this request, 0 disables redirects
+    pub fn max_redirects(mut self, max_redirects: u32) -> HttpRequest {
+        self.max_redirects = Some(max_redirects);
+        self
+    }
+
+    /// full_url renders the url with the encoded query parameters appended
+    pub fn full_url(&self) -> String {
+        if self.query.is_empty() {
+            return self.url.clone();
+        }
+        let qs: Vec<String> = self
+            .query
+            .iter()
+            .map(|(k, v)| format!("{}={}", encode_uri_component(k), encode_uri_component(v)))
+            .collect();
+        let sep = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{sep}{}", self.url, qs.join("&"))
+    }
+
+    /// to_value renders the request as the `request` object expected by the fabric
+    pub fn to_value(&self) -> Value {
+        let mut headers = Map::new();
+        for (k, v) in &self.headers {
+            headers.insert(k.to_string(), json!(v));
+        }
+        let mut req = json!({
+            "url" : self.full_url(),
+            "method" : self.method,
+            "headers" : headers,
+        });
+        match &self.body {
+            HttpBody::Empty => {}
+            HttpBody::Json(v) => req["body"] = v.clone(),
+            HttpBody::Bytes(b) => {
+                req["body"] = json!(general_purpose::STANDARD.encode(b));
+                req["body_encoding"] = json!("base64");
+            }
+        }
+        if let Some(t) = self.timeout_ms {
+            req["timeout_ms"] = json!(t);
+        }
+        if let Some(r) = self.max_redirects {
+            req["follow_redirects"] = json!(r > 0);
+            req["max_redirects"] = json!(r);
+        }
+        req
+    }
+}
+
+/// The decoded reply to an [HttpRequest]
+#[derive(Clone, Debug, Default)]
+pub struct ProxyResponse {
+    pub status: usize,
+    pub headers: HashMap<String, Vec<String>>,
+    pub body: Vec<u8>,
+}
+
+impl ProxyResponse {
+    /// header retrieves the first value of header `key` (case insensitive)
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.first())
+            .map(|s| s.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// json decodes the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error + Send + Sync>> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// text decodes the body as utf8
+    pub fn text(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(String::from_utf8(self.body.clone())?)
+    }
+
+    /// from_result decodes the `result` of a `ProxyHttp` or `RestCall`, either the base64 encoded body
+    /// alone or an object carrying `status`, `headers` and `body`
+    pub fn from_result(result: &Value) -> Result<ProxyResponse, Box<dyn Error + Send + Sync>> {
+        match result {
+            Value::String(s) => Ok(ProxyResponse {
+                status: 200,
+                headers: HashMap::new(),
+                body: decode_body(s),
+            }),
+            Value::Object(m) if m.contains_key("op") && m.contains_key("desc") => Err(Box::new(
+                ErrorKinds::Other(format!("http call failed: {}", m["desc"])),
+            )),
+            Value::Object(m) if m.contains_key("body") || m.contains_key("status") => {
+                let body = match &m.get("body") {
+                    Some(Value::String(s)) => decode_body(s),
+                    Some(Value::Null) | None => Vec::new(),
+                    Some(v) => serde_json::to_vec(v)?,
+                };
+                Ok(ProxyResponse {
+                    status: m.get("status").and_then(|s| s.as_u64()).unwrap_or(200) as usize,
+                    headers: headers_from_value(m.get("headers").unwrap_or(&Value::Null)),
+                    body,
+                })
+            }
+            Value::Null => Ok(ProxyResponse {
+                status: 200,
+                ..Default::default()
+            }),
+            v => Ok(ProxyResponse {
+                status: 200,
+                headers: HashMap::new(),
+                body: serde_json::to_vec(v)?,
+            }),
+        }
+    }
+}
+
+// bodies are base64 encoded by the fabric, anything else is taken verbatim
+fn decode_body(s: &str) -> Vec<u8> {
+    general_purpose::STANDARD
+        .decode(s.trim())
+        .unwrap_or_else(|_| s.as_bytes().to_vec())
+}
+
+// header values may be a single string or a list of strings
+pub(crate) fn headers_from_value(v: &Value) -> HashMap<String, Vec<String>> {
+    let mut headers = HashMap::new();
+    if let Some(m) = v.as_object() {
+        for (k, val) in m {
+            let vals = match val {
+                Value::Array(a) => a
+                    .iter()
+                    .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                    .collect(),
+                Value::String(s) => vec![s.to_string()],
+                _ => continue,
+            };
+            headers.insert(k.to_string(), vals);
+        }
+    }
+    headers
+}
+
+/// HttpClient sends [HttpRequest]s through the fabric, see the [module documentation](self)
+#[derive(Clone)]
+pub struct HttpClient<'a> {
+    bcc: &'a BitcodeContext,
+    transport: HttpTransport,
+    headers: Vec<(String, String)>,
+    timeout_ms: Option<u64>,
+    max_redirects: Option<u32>,
+}
+
+impl<'a> HttpClient<'a> {
+    pub fn new(bcc: &'a BitcodeContext, transport: HttpTransport) -> HttpClient<'a> {
+        HttpClient {
+            bcc,
+            transport,
+            headers: Vec::new(),
+            timeout_ms: None,
+            max_redirects: None,
+        }
+    }
+
+    /// proxy creates a client for arbitrary urls through `ProxyHttp`
+    pub fn proxy(bcc: &'a BitcodeContext) -> HttpClient<'a> {
+        HttpClient::new(bcc, HttpTransport::ProxyHttp)
+    }
+
+    /// rest creates a client for the node's configured rest services through `RestCall`
+    pub fn rest(bcc: &'a BitcodeContext) -> HttpClient<'a> {
+        HttpClient::new(bcc, HttpTransport::RestCall)
+    }
+
+    /// default_header adds a header sent with every request that does not set it itself
+    pub fn default_header(mut self, key: &str, val: &str) -> HttpClient<'a> {
+        self.headers.push((key.to_string(), val.to_string()));
+        self
+    }
+
+    pub fn timeout_ms(mut self, timeout_ms: u64) -> HttpClient<'a> {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// max_redirects limits the redirects followed, 0 disables redirects
+    pub fn max_redirects(mut self, max_redirects: u32) -> HttpClient<'a> {
+        self.max_redirects = Some(max_redirects);
+        self
+    }
+
+    fn prepare(&self, req: &HttpRequest) -> HttpRequest {
+        let mut req = req.clone();
+        for (k, v) in &self.headers {
+            if !req.headers.iter().any(|(rk, _)| rk.eq_ignore_ascii_case(k)) {
+                req.headers.push((k.to_string(), v.to_string()));
+            }
+        }
+        req.timeout_ms = req.timeout_ms.or(self.timeout_ms);
+        req.max_redirects = req.max_redirects.or(self.max_redirects);
+        req
+    }
+
+    pub fn transport(&self) -> HttpTransport {
+        self.transport
+    }
+
+    /// send issues a request
+    /// # Returns
+    /// the decoded [ProxyResponse], whatever its status
+    pub fn send(&self, req: &HttpRequest) -> Result<ProxyResponse, Box<dyn Error + Send + Sync>> {
+        let v = json!({ "request": self.prepare(req).to_value() });
+        let res = match self.transport {
+            HttpTransport::ProxyHttp => self.bcc.proxy_http(Some(v))?,
+            HttpTransport::RestCall => self.bcc.rest_call(Some(v))?,
+        };
+        let reply: Value = serde_json::from_slice(&res)?;
+        ProxyResponse::from_result(&reply["result"])
+    }
+
+    pub fn get(&self, url: &str) -> Result<ProxyResponse, Box<dyn Error + Send + Sync>> {
+        self.send(&HttpRequest::get(url))
+    }
+
+    pub fn post_json(
+        &self,
+        url: &str,
+        body: Value,
+    ) -> Result<ProxyResponse, Box<dyn Error + Send + Sync>> {
+        self.send(&HttpRequest::post(url).json(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_value() {
+        let req = HttpRequest::get("https://example.com/search?cx=1")
+            .query("q", "a&b c")
+            .header("Accept", "text/plain")
+            .header("accept", "application/json")
+            .timeout_ms(500)
+            .max_redirects(0);
+        let v = req.to_value();
+        assert_eq!(v["url"], "https://example.com/search?cx=1&q=a%26b%20c");
+        assert_eq!(v["method"], "GET");
+        assert_eq!(v["headers"], json!({"accept" : "application/json"}));
+        assert_eq!(v["timeout_ms"], 500);
+        assert_eq!(v["follow_redirects"], false);
+        let v = HttpRequest::post("/x")
+            .bytes(vec![0, 1], "image/png")
+            .to_value();
+        assert_eq!(v["body"], "AAE=");
+        assert_eq!(v["headers"]["Content-Type"], "image/png");
+    }
+
+    #[test]
+    fn test_response_from_result() {
+        let enc = general_purpose::STANDARD.encode(br#"{"a":1}"#);
+        let resp = ProxyResponse::from_result(&json!(enc)).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.json::<Value>().unwrap(), json!({"a" : 1}));
+        let resp = ProxyResponse::from_result(
+            &json!({"status" : 404, "headers" : {"Content-Type" : ["text/plain"]}, "body" : "bm9wZQ=="}),
+        )
+        .unwrap();
+        assert_eq!(resp.status, 404);
+        assert_eq!(resp.header("content-type"), Some("text/plain"));
+        assert_eq!(resp.text().unwrap(), "nope");
+        assert!(ProxyResponse::from_result(&json!({"op" : 0, "desc" : "boom"})).is_err());
+    }
+}
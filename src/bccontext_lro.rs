@@ -0,0 +1,454 @@
+//! Long running operation (LRO) jobs with status, progress and cancellation <br>
+//! [BitcodeContext::start_bitcode_lro] only hands back an opaque handle.  This module layers typed jobs on
+//! top of it: a job type implements [LroJob], is started with [BitcodeContext::lro_start] and executed by
+//! the registered callback handler through [BitcodeContext::lro_run].  Job arguments, status, progress,
+//! intermediate state and the final result are kept in a [StateStore] so that any later request can poll
+//! them ([lro_status_handler]) or request cancellation ([lro_cancel_handler]). <br>
+//! The state store is created by the first [BitcodeContext::lro_start] and its id recorded in the metadata
+//! at [LRO_STATE_META_PATH], which requires a write token.  Job ids name the state store holding them, so
+//! polling and cancelling work from any version of the content, including the finalized version of a draft
+//! the store was recorded in or a version predating it.  Later starts against a version lacking the
+//! metadata create a new store, finalize the draft recording it to share one store across starts.
+//! ```rust
+//! use elvwasm::{implement_bitcode_module, jpc, register_handler, lro_status_handler, LroContext, LroJob};
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! implement_bitcode_module!("start", do_start, "transcode", do_transcode, "status", lro_status_handler);
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct TranscodeArgs {
+//!     files: Vec<String>,
+//! }
+//!
+//! struct Transcode;
+//!
+//! impl LroJob for Transcode {
+//!     const JOB_TYPE: &'static str = "transcode";
+//!     type Args = TranscodeArgs;
+//!     type Output = usize;
+//!     fn run(ctx: &LroContext, args: TranscodeArgs) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+//!         for (i, f) in args.files.iter().enumerate() {
+//!             ctx.check_cancelled()?;
+//!             ctx.report_progress(i as f64 / args.files.len() as f64, f)?;
+//!         }
+//!         Ok(args.files.len())
+//!     }
+//! }
+//!
+//! fn do_start(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
+//!     let status = bcc.lro_start::<Transcode>(&TranscodeArgs { files: vec!["a.mp4".to_string()] })?;
+//!     bcc.make_success_json(&serde_json::to_value(status)?)
+//! }
+//!
+//! fn do_transcode(bcc: &mut elvwasm::BitcodeContext) -> CallResult {
+//!     bcc.lro_run::<Transcode>()
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{
+    is_not_exist_error, BitcodeContext, ErrorKinds, LROResult, StateStore, SystemTimeResult,
+};
+
+use guest::CallResult;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Metadata path recording the id of the state store holding LRO job state
+pub const LRO_STATE_META_PATH: &str = "/lro/qssid";
+
+/// Namespace of the state store used for LRO job state
+pub const LRO_NAMESPACE: &str = "lro";
+
+/// Lifecycle of an LRO job
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LroState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl LroState {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            LroState::Succeeded | LroState::Failed | LroState::Cancelled
+        )
+    }
+}
+
+/// Status of an LRO job as reported by [BitcodeContext::lro_status]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LroStatus {
+    /// `<job type>_<sequence>@<state store id>`
+    pub job_id: String,
+    pub job_type: String,
+    pub state: LroState,
+    /// fraction of the work done in [0, 1]
+    #[serde(default)]
+    pub progress: f64,
+    #[serde(default)]
+    pub message: String,
+    /// job defined state reported through [LroContext::report_state]
+    #[serde(default)]
+    pub intermediate: Value,
+    /// the job's output once it has succeeded
+    #[serde(default)]
+    pub result: Value,
+    #[serde(default)]
+    pub error: String,
+    #[serde(default)]
+    pub lro_handle: String,
+    /// job id of a follow-up job started with [LroContext::chain]
+    #[serde(default)]
+    pub next: Option<String>,
+    /// system time of the last update
+    #[serde(default)]
+    pub updated: u64,
+}
+
+/// LroJob defines a type of long running operation.  `JOB_TYPE` doubles as the name of the handler
+/// registered in the bitcode module that calls [BitcodeContext::lro_run] for this job.
+pub trait LroJob {
+    const JOB_TYPE: &'static str;
+    type Args: Serialize + DeserializeOwned;
+    type Output: Serialize;
+
+    fn run(
+        ctx: &LroContext,
+        args: Self::Args,
+    ) -> Result<Self::Output, Box<dyn Error + Send + Sync>>;
+}
+
+fn status_key(job_id: &str) -> String {
+    format!("{job_id}/status")
+}
+
+fn args_key(job_id: &str) -> String {
+    format!("{job_id}/args")
+}
+
+fn cancel_key(job_id: &str) -> String {
+    format!("{job_id}/cancel")
+}
+
+/// LroContext is handed to [LroJob::run] to report on and control the running job
+pub struct LroContext<'a> {
+    bcc: &'a BitcodeContext,
+    store: StateStore<'a>,
+    job_id: String,
+}
+
+impl<'a> LroContext<'a> {
+    pub fn bcc(&self) -> &'a BitcodeContext {
+        self.bcc
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    fn update<F: FnOnce(&mut LroStatus)>(&self, f: F) -> Result<(), Box<dyn Error + Send + Sync>> {
+        update_status(self.bcc, &self.store, &self.job_id, f)
+    }
+
+    /// report_progress records the fraction of work done along with a message
+    pub fn report_progress(
+        &self,
+        progress: f64,
+        message: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.update(|s| {
+            s.progress = progress.clamp(0.0, 1.0);
+            s.message = message.to_string();
+        })
+    }
+
+    /// report_state records job defined intermediate state, e.g. to resume after a failure
+    pub fn report_state<T: Serialize>(
+        &self,
+        state: &T,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let intermediate = serde_json::to_value(state)?;
+        self.update(|s| s.intermediate = intermediate)
+    }
+
+    /// is_cancelled checks whether cancellation of the job has been requested
+    pub fn is_cancelled(&self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .store
+            .get::<bool>(&cancel_key(&self.job_id))?
+            .unwrap_or(false))
+    }
+
+    /// check_cancelled returns an error if cancellation of the job has been requested, allowing a job to
+    /// bail out with `?`
+    pub fn check_cancelled(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.is_cancelled()? {
+            return Err(Box::new(ErrorKinds::Other(format!(
+                "lro job {} cancelled",
+                self.job_id
+            ))));
+        }
+        Ok(())
+    }
+
+    /// chain starts a follow-up job and records it as the next job of this one
+    /// # Returns
+    /// the job id of the follow-up job
+    pub fn chain<J: LroJob>(&self, args: &J::Args) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let next = self.bcc.lro_start::<J>(args)?;
+        let next_id = next.job_id.clone();
+        self.update(|s| s.next = Some(next_id))?;
+        Ok(next.job_id)
+    }
+}
+
+fn now(bcc: &BitcodeContext) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let st: SystemTimeResult = bcc.q_system_time().try_into()?;
+    Ok(st.time)
+}
+
+// the store jobs are started in, created and recorded in the metadata on first use
+fn lro_start_store(bcc: &BitcodeContext) -> Result<StateStore<'_>, Box<dyn Error + Send + Sync>> {
+    let res = bcc.sqmd_get_json(LRO_STATE_META_PATH)?;
+    let store = match serde_json::from_slice::<Value>(&res)? {
+        Value::String(qssid) if !qssid.is_empty() => StateStore::new(bcc, &qssid),
+        v if v.is_null() || v.as_str() == Some("") || is_not_exist_error(&v) => {
+            let store = StateStore::create(bcc)?;
+            let res = bcc.sqmd_set_json(LRO_STATE_META_PATH, &json!(store.qssid()))?;
+            if let Ok(err) = serde_json::from_slice::<Value>(&res) {
+                if err.get("op").is_some() {
+                    return Err(Box::new(ErrorKinds::Permission(format!(
+                        "unable to record the lro state store at {LRO_STATE_META_PATH} err={err}"
+                    ))));
+                }
+            }
+            store
+        }
+        v => {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "unexpected lro state store id at {LRO_STATE_META_PATH} : {v}"
+            ))))
+        }
+    };
+    Ok(store.namespace(LRO_NAMESPACE))
+}
+
+// the store holding an existing job, named by its id
+fn lro_job_store<'b>(
+    bcc: &'b BitcodeContext,
+    job_id: &str,
+) -> Result<StateStore<'b>, Box<dyn Error + Send + Sync>> {
+    match job_id.rsplit_once('@') {
+        Some((_, qssid)) if !qssid.is_empty() => {
+            Ok(StateStore::new(bcc, qssid).namespace(LRO_NAMESPACE))
+        }
+        _ => Err(Box::new(ErrorKinds::BadHttpParams(format!(
+            "malformed lro job id {job_id}"
+        )))),
+    }
+}
+
+fn update_status<F: FnOnce(&mut LroStatus)>(
+    bcc: &BitcodeContext,
+    store: &StateStore,
+    job_id: &str,
+    f: F,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut status = store
+        .get::<LroStatus>(&status_key(job_id))?
+        .ok_or_else(|| ErrorKinds::NotExist(format!("lro job {job_id} not found")))?;
+    f(&mut status);
+    status.updated = now(bcc)?;
+    store.put(&status_key(job_id), &status)?;
+    Ok(())
+}
+
+// the callback may receive the job id in its body, its arguments or the query
+fn callback_job_id(bcc: &BitcodeContext) -> Option<String> {
+    let http_p = &bcc.request.params.http;
+    let body = &http_p.body;
+    for candidate in [&body["job_id"], &body["args"]["job_id"]] {
+        if let Some(s) = candidate.as_str() {
+            return Some(s.to_string());
+        }
+    }
+    http_p.query.get("job_id").and_then(|v| v.first().cloned())
+}
+
+impl<'a> BitcodeContext {
+    /// lro_start starts a new job of type `J`
+    /// # Arguments
+    /// * `args` : the job's arguments, handed to [LroJob::run]
+    /// # Returns
+    /// the initial [LroStatus] of the job, whose `job_id` is used to poll or cancel it
+    pub fn lro_start<J: LroJob>(
+        &'a self,
+        args: &J::Args,
+    ) -> Result<LroStatus, Box<dyn Error + Send + Sync>> {
+        let store = lro_start_store(self)?;
+        let seq = store.increment("seq", 1)?;
+        let job_id = format!("{}_{seq}@{}", J::JOB_TYPE, store.qssid());
+        store.put(&args_key(&job_id), args)?;
+        let mut status = LroStatus {
+            job_id: job_id.clone(),
+            job_type: J::JOB_TYPE.to_string(),
+            state: LroState::Pending,
+            progress: 0.0,
+            message: String::new(),
+            intermediate: Value::Null,
+            result: Value::Null,
+            error: String::new(),
+            lro_handle: String::new(),
+            next: None,
+            updated: now(self)?,
+        };
+        store.put(&status_key(&job_id), &status)?;
+        let lro: LROResult = self
+            .start_bitcode_lro("", J::JOB_TYPE, &json!({ "job_id": job_id }))
+            .try_into()?;
+        status.lro_handle = lro.lro_handle;
+        store.put(&status_key(&job_id), &status)?;
+        Ok(status)
+    }
+
+    /// lro_run executes the job of type `J` the current LRO callback refers to, recording its
+    /// progress and outcome.  This is the body of the handler registered under [LroJob::JOB_TYPE].
+    /// A job whose cancellation was requested before it started is marked cancelled without running.
+    pub fn lro_run<J: LroJob>(&'a self) -> CallResult {
+        let job_id = callback_job_id(self).ok_or_else(|| {
+            ErrorKinds::BadHttpParams(format!("no job_id supplied to lro {}", J::JOB_TYPE))
+        })?;
+        let store = lro_job_store(self, &job_id)?;
+        let args: J::Args = store
+            .get(&args_key(&job_id))?
+            .ok_or_else(|| ErrorKinds::NotExist(format!("arguments of lro job {job_id}")))?;
+        let ctx = LroContext {
+            bcc: self,
+            store: store.clone(),
+            job_id: job_id.clone(),
+        };
+        // a job cancelled while queued never runs
+        if ctx.is_cancelled()? {
+            update_status(self, &store, &job_id, |s| {
+                s.state = LroState::Cancelled;
+                s.message = "cancelled before it started".to_string();
+            })?;
+            let status = self.lro_status(&job_id)?;
+            return self.make_success_json(&serde_json::to_value(status)?);
+        }
+        update_status(self, &store, &job_id, |s| s.state = LroState::Running)?;
+        let outcome = J::run(&ctx, args);
+        let cancelled = ctx.is_cancelled()?;
+        match outcome {
+            Ok(out) => {
+                let result = serde_json::to_value(out)?;
+                update_status(self, &store, &job_id, |s| {
+                    s.state = LroState::Succeeded;
+                    s.progress = 1.0;
+                    s.result = result;
+                })?;
+            }
+            Err(e) => {
+                self.log_error(&format!("lro job {job_id} failed err={e}"))?;
+                update_status(self, &store, &job_id, |s| {
+                    s.state = if cancelled {
+                        LroState::Cancelled
+                    } else {
+                        LroState::Failed
+                    };
+                    s.error = e.to_string();
+                })?;
+            }
+        }
+        let status = self.lro_status(&job_id)?;
+        self.make_success_json(&serde_json::to_value(status)?)
+    }
+
+    /// lro_status retrieves the status of a job
+    pub fn lro_status(&'a self, job_id: &str) -> Result<LroStatus, Box<dyn Error + Send + Sync>> {
+        let store = lro_job_store(self, job_id)?;
+        Ok(store
+            .get::<LroStatus>(&status_key(job_id))?
+            .ok_or_else(|| ErrorKinds::NotExist(format!("lro job {job_id} not found")))?)
+    }
+
+    /// lro_cancel requests cancellation of a job, which takes effect the next time the job checks
+    /// [LroContext::is_cancelled]
+    pub fn lro_cancel(&'a self, job_id: &str) -> Result<LroStatus, Box<dyn Error + Send + Sync>> {
+        let store = lro_job_store(self, job_id)?;
+        let status = self.lro_status(job_id)?;
+        if !status.state.is_terminal() {
+            store.put(&cancel_key(job_id), &true)?;
+        }
+        Ok(status)
+    }
+}
+
+fn query_job_id(bcc: &BitcodeContext) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(bcc
+        .request
+        .params
+        .http
+        .query
+        .get("job_id")
+        .and_then(|v| v.first().cloned())
+        .ok_or_else(|| ErrorKinds::BadHttpParams("job_id not present".to_string()))?)
+}
+
+// answers with the status, or the error's kind when it has one
+fn status_response(
+    bcc: &BitcodeContext,
+    status: Result<LroStatus, Box<dyn Error + Send + Sync>>,
+) -> CallResult {
+    match status {
+        Ok(s) => bcc.make_success_json(&serde_json::to_value(s)?),
+        Err(e) => match e.downcast_ref::<ErrorKinds>() {
+            Some(kind) => bcc.make_error_with_kind(kind.clone()),
+            None => Err(e),
+        },
+    }
+}
+
+/// lro_status_handler is a ready made handler returning the [LroStatus] of the job named by the
+/// `job_id` query parameter
+pub fn lro_status_handler(bcc: &mut BitcodeContext) -> CallResult {
+    let job_id = query_job_id(bcc)?;
+    status_response(bcc, bcc.lro_status(&job_id))
+}
+
+/// lro_cancel_handler is a ready made handler requesting cancellation of the job named by the
+/// `job_id` query parameter
+pub fn lro_cancel_handler(bcc: &mut BitcodeContext) -> CallResult {
+    let job_id = query_job_id(bcc)?;
+    status_response(bcc, bcc.lro_cancel(&job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Request;
+
+    #[test]
+    fn test_lro_job_store() {
+        let bcc = BitcodeContext::new(Request::default());
+        let store = lro_job_store(&bcc, "transcode_3@qss_1234").unwrap();
+        assert_eq!(store.qssid(), "qss_1234");
+        assert!(lro_job_store(&bcc, "transcode_3").is_err());
+        assert!(lro_job_store(&bcc, "transcode_3@").is_err());
+        assert!(LroState::Cancelled.is_terminal());
+        assert!(!LroState::Running.is_terminal());
+    }
+}
@@ -0,0 +1,232 @@
+//! MIME type detection for bitcode content <br>
+//! The type is sniffed from the well known signatures at the start of the data and falls back to the file
+//! extension.  The result is suitable both for storing files ([BitcodeContext::q_upload_reader],
+//! [BitcodeContext::q_create_file_from_stream]) and for the `Content-Type` of a response
+//! ([BitcodeContext::callback_auto]).
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{BitcodeContext, DEFAULT_MIME_TYPE};
+
+use guest::CallResult;
+
+/// Number of leading bytes needed to recognize every supported signature (tar is the furthest at 257)
+pub const MIME_SNIFF_LEN: usize = 512;
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("avif", "image/avif"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("m4a", "audio/mp4"),
+    ("mov", "video/quicktime"),
+    ("3gp", "video/3gpp"),
+    ("3g2", "video/3gpp2"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("json", "application/json"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("xml", "application/xml"),
+    ("wasm", "application/wasm"),
+];
+
+/// mime_from_magic identifies the MIME type of data from its leading bytes
+/// # Arguments
+/// * `data` : the data or at least its first [MIME_SNIFF_LEN] bytes
+/// # Returns
+/// the MIME type for JPEG, PNG, GIF, WebP, MP4, PDF, ZIP, GZIP, TAR and JSON, None otherwise
+pub fn mime_from_magic(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+    if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some("image/png");
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(mime_from_ftyp_brand(&data[8..12]));
+    }
+    if data.starts_with(b"%PDF-") {
+        return Some("application/pdf");
+    }
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        return Some("application/zip");
+    }
+    if data.starts_with(&[0x1F, 0x8B]) {
+        return Some("application/gzip");
+    }
+    if data.len() >= 262 && &data[257..262] == b"ustar" {
+        return Some("application/x-tar");
+    }
+    if looks_like_json(data) {
+        return Some("application/json");
+    }
+    None
+}
+
+fn looks_like_json(data: &[u8]) -> bool {
+    let data = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+    let start = match data.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(s) => s,
+        None => return false,
+    };
+    if data[start] != b'{' && data[start] != b'[' {
+        return false;
+    }
+    // a sniffed prefix may be cut short, so running out of input still counts as json
+    match serde_json::from_slice::<serde::de::IgnoredAny>(&data[start..]) {
+        Ok(_) => true,
+        Err(e) => e.is_eof(),
+    }
+}
+
+// the ISO base media file format shares the ftyp box, its major brand tells the formats apart
+fn mime_from_ftyp_brand(brand: &[u8]) -> &'static str {
+    match brand {
+        b"qt  " => "video/quicktime",
+        b"M4A " | b"M4B " => "audio/mp4",
+        b"heic" | b"heix" | b"heim" | b"heis" => "image/heic",
+        b"mif1" | b"msf1" => "image/heif",
+        b"avif" | b"avis" => "image/avif",
+        b"3gp4" | b"3gp5" | b"3gp6" | b"3gs7" | b"3ge6" | b"3gg6" => "video/3gpp",
+        b"3g2a" | b"3g2b" | b"3g2c" => "video/3gpp2",
+        _ => "video/mp4",
+    }
+}
+
+/// mime_from_extension looks up the MIME type for the extension of a path (case insensitive)
+/// # Arguments
+/// * `path` : a fabric file path or file name e.g. `/assets/birds.jpg`
+pub fn mime_from_extension(path: &str) -> Option<&'static str> {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let (_, ext) = file_name.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// detect_mime determines the MIME type of data, preferring its signature to the path's extension
+/// # Arguments
+/// * `data` : the data or at least its first [MIME_SNIFF_LEN] bytes
+/// * `path` : optional file path used when the signature is not recognized
+/// # Returns
+/// the detected type or [DEFAULT_MIME_TYPE]
+/// ```rust
+/// assert_eq!(elvwasm::detect_mime(b"%PDF-1.7", None), "application/pdf");
+/// assert_eq!(elvwasm::detect_mime(b"", Some("/assets/birds.JPG")), "image/jpeg");
+/// ```
+pub fn detect_mime(data: &[u8], path: Option<&str>) -> &'static str {
+    mime_from_magic(data)
+        .or_else(|| path.and_then(mime_from_extension))
+        .unwrap_or(DEFAULT_MIME_TYPE)
+}
+
+impl<'a> BitcodeContext {
+    /// callback_auto issues a [BitcodeContext::callback] whose `Content-Type` is detected from the output
+    /// # Arguments
+    /// * `status`-    the http status of the call
+    /// * `data`-      the output that will be written to the output stream
+    /// * `path`-      optional file path used when the data's signature is not recognized
+    pub fn callback_auto(&'a self, status: usize, data: &[u8], path: Option<&str>) -> CallResult {
+        self.callback(status, detect_mime(data, path), data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_from_magic() {
+        assert_eq!(
+            mime_from_magic(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(mime_from_magic(b"GIF89a......"), Some("image/gif"));
+        assert_eq!(
+            mime_from_magic(b"RIFF\x10\x00\x00\x00WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x18ftypmp42"),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x14ftypqt  "),
+            Some("video/quicktime")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x20ftypM4A "),
+            Some("audio/mp4")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x18ftypheic"),
+            Some("image/heic")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x1cftypavif"),
+            Some("image/avif")
+        );
+        assert_eq!(
+            mime_from_magic(b"\x00\x00\x00\x14ftyp3gp5"),
+            Some("video/3gpp")
+        );
+        assert_eq!(mime_from_magic(b"PK\x03\x04rest"), Some("application/zip"));
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(mime_from_magic(&tar), Some("application/x-tar"));
+        assert_eq!(
+            mime_from_magic(b"  {\"a\" : [1, 2"),
+            Some("application/json")
+        );
+        assert_eq!(mime_from_magic(b"{not json}"), None);
+        assert_eq!(mime_from_magic(b"plain text"), None);
+    }
+
+    #[test]
+    fn test_detect_mime_fallback() {
+        assert_eq!(
+            mime_from_extension("/a.b/archive.TGZ"),
+            Some("application/gzip")
+        );
+        assert_eq!(mime_from_extension("/assets/noext"), None);
+        assert_eq!(
+            detect_mime(b"\x1F\x8B\x08", Some("x.json")),
+            "application/gzip"
+        );
+        assert_eq!(detect_mime(b"hello", Some("notes.txt")), "text/plain");
+        assert_eq!(detect_mime(b"hello", None), DEFAULT_MIME_TYPE);
+    }
+}
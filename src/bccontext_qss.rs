@@ -0,0 +1,416 @@
+//! Typed, namespaced key/value store layered over the Q state store APIs of the [BitcodeContext] <br>
+//! [BitcodeContext::qss_set] and [BitcodeContext::qss_get] only move strings.  A [StateStore] stores serde
+//! values as JSON inside a small envelope carrying a version, which lets an update be made only if the key
+//! has not changed since it was read ([StateStore::put_if_version]) and backs simple counters.  None of these
+//! are safe under concurrency: the host has no conditional write, the version is checked by bitcode before it
+//! writes, so two requests racing on the same key may both succeed and one update is silently lost. <br>
+//! Keys are grouped into namespaces and each namespace keeps an index of its keys, under the reserved key
+//! `.keys`, so that they can be listed by prefix.  The index is updated the same way, keys added by
+//! concurrent requests may be missing from a listing although their values are stored.
+//! ```rust
+//! use elvwasm::StateStore;
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let store = StateStore::from_meta(bcc, "/state_store/qssid")?.namespace("jobs");
+//!   store.put("job1", &serde_json::json!({"state" : "running"}))?;
+//!   let visits = store.increment("visits", 1)?;
+//!   let job: Option<serde_json::Value> = store.get("job1")?;
+//!   Ok(serde_json::to_vec(&job)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{is_not_exist_error, BitcodeContext, ErrorKinds};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Number of times [StateStore::increment] retries when the counter changed since it was read
+const MAX_UPDATE_RETRIES: usize = 8;
+
+/// Name of the per namespace key index, reserved and not itself listed
+const KEY_INDEX: &str = ".keys";
+
+/// A value as stored in a [StateStore] along with its version.  Versions start at 1 and grow by one
+/// on every write, 0 designates a key that does not exist.  Values stored without the envelope (e.g. by
+/// [BitcodeContext::qss_set]) are at version 1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Versioned<T> {
+    pub version: u64,
+    pub value: T,
+}
+
+/// StateStore provides typed access to a Q state store, see the [module documentation](self)
+#[derive(Clone)]
+pub struct StateStore<'a> {
+    bcc: &'a BitcodeContext,
+    qssid: String,
+    namespace: String,
+}
+
+// decodes a QSSGet result, missing keys come back as null or as a not exist error object
+fn decode_get_result(
+    key: &str,
+    res: &[u8],
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    match serde_json::from_slice::<Value>(res) {
+        Ok(Value::String(s)) => Ok(Some(s)),
+        Ok(Value::Null) => Ok(None),
+        Ok(v) if v.get("op").is_some() => {
+            if is_not_exist_error(&v) {
+                Ok(None)
+            } else {
+                Err(Box::new(ErrorKinds::Other(format!(
+                    "QSSGet {key} failed err={v}"
+                ))))
+            }
+        }
+        Ok(v) => Ok(Some(v.to_string())),
+        Err(_) => Ok(Some(String::from_utf8_lossy(res).to_string())),
+    }
+}
+
+fn check_key(key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // a key ending in the index name would also collide with the index of a nested namespace
+    if key.rsplit('/').next() == Some(KEY_INDEX) {
+        return Err(Box::new(ErrorKinds::Invalid(format!(
+            "{KEY_INDEX} is reserved for the key index"
+        ))));
+    }
+    Ok(())
+}
+
+fn decode_string_result(res: &[u8]) -> String {
+    match serde_json::from_slice::<String>(res) {
+        Ok(s) => s,
+        Err(_) => String::from_utf8_lossy(res).trim().to_string(),
+    }
+}
+
+impl<'a> StateStore<'a> {
+    /// new binds a StateStore to an existing state store
+    /// # Arguments
+    /// * `qssid`- string identifier aquired from [BitcodeContext::q_create_q_state_store]
+    pub fn new(bcc: &'a BitcodeContext, qssid: &str) -> StateStore<'a> {
+        StateStore {
+            bcc,
+            qssid: qssid.to_string(),
+            namespace: String::new(),
+        }
+    }
+
+    /// create creates a new state store on the fabric and binds a StateStore to it
+    pub fn create(bcc: &'a BitcodeContext) -> Result<StateStore<'a>, Box<dyn Error + Send + Sync>> {
+        let res = bcc.q_create_q_state_store()?;
+        let qssid = decode_string_result(&res);
+        if qssid.is_empty() {
+            return Err(Box::new(ErrorKinds::Invalid(
+                "QCreateQStateStore returned an empty id".to_string(),
+            )));
+        }
+        Ok(StateStore::new(bcc, &qssid))
+    }
+
+    /// from_meta binds a StateStore to the state store whose id is kept in the content's metadata at
+    /// `meta_path`.  If there is none yet a new state store is created and its id recorded at `meta_path`,
+    /// which requires the context to hold a write token.
+    pub fn from_meta(
+        bcc: &'a BitcodeContext,
+        meta_path: &str,
+    ) -> Result<StateStore<'a>, Box<dyn Error + Send + Sync>> {
+        if let Ok(res) = bcc.sqmd_get_json(meta_path) {
+            if let Ok(Value::String(qssid)) = serde_json::from_slice::<Value>(&res) {
+                if !qssid.is_empty() {
+                    return Ok(StateStore::new(bcc, &qssid));
+                }
+            }
+        }
+        let store = StateStore::create(bcc)?;
+        if let Err(e) = bcc.sqmd_set_json(meta_path, &json!(store.qssid)) {
+            bcc.log_warn(&format!(
+                "unable to record state store {} at {meta_path} err={e}",
+                store.qssid
+            ))?;
+        }
+        Ok(store)
+    }
+
+    pub fn qssid(&self) -> &str {
+        &self.qssid
+    }
+
+    pub fn context(&self) -> &'a BitcodeContext {
+        self.bcc
+    }
+
+    /// namespace returns a StateStore over the same state store whose keys are nested in namespace `ns`
+    pub fn namespace(&self, ns: &str) -> StateStore<'a> {
+        let ns = ns.trim_matches('/');
+        StateStore {
+            bcc: self.bcc,
+            qssid: self.qssid.clone(),
+            namespace: if self.namespace.is_empty() {
+                ns.to_string()
+            } else {
+                format!("{}/{ns}", self.namespace)
+            },
+        }
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        if self.namespace.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{key}", self.namespace)
+        }
+    }
+
+    fn get_raw(&self, key: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let full_key = self.full_key(key);
+        let res = self.bcc.qss_get(&self.qssid, &full_key)?;
+        decode_get_result(&full_key, &res)
+    }
+
+    fn set_raw<T: Serialize>(
+        &self,
+        key: &str,
+        val: &Versioned<T>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let s = serde_json::to_string(val)?;
+        self.bcc.qss_set(&self.qssid, &self.full_key(key), &s)?;
+        Ok(())
+    }
+
+    /// get_versioned retrieves the value at `key` along with its version
+    pub fn get_versioned<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<Versioned<T>>, Box<dyn Error + Send + Sync>> {
+        check_key(key)?;
+        self.load(key)
+    }
+
+    fn load<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<Versioned<T>>, Box<dyn Error + Send + Sync>> {
+        let raw = match self.get_raw(key)? {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        if let Ok(v) = serde_json::from_str::<Versioned<T>>(&raw) {
+            return Ok(Some(v));
+        }
+        // values written with qss_set directly have no envelope
+        let value = match serde_json::from_str::<T>(&raw) {
+            Ok(v) => v,
+            Err(_) => serde_json::from_value(Value::String(raw))?,
+        };
+        Ok(Some(Versioned { version: 1, value }))
+    }
+
+    /// get retrieves the value at `key`, None if the key does not exist
+    pub fn get<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, Box<dyn Error + Send + Sync>> {
+        Ok(self.get_versioned(key)?.map(|v| v.value))
+    }
+
+    /// version returns the current version of `key`, 0 if the key does not exist
+    pub fn version(&self, key: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .get_versioned::<Value>(key)?
+            .map(|v| v.version)
+            .unwrap_or(0))
+    }
+
+    /// put unconditionally stores `val` at `key`
+    /// # Returns
+    /// the new version of the key
+    pub fn put<T: Serialize>(
+        &self,
+        key: &str,
+        val: &T,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        check_key(key)?;
+        let current = self.get_versioned::<Value>(key)?;
+        let version = current.as_ref().map(|v| v.version).unwrap_or(0) + 1;
+        self.set_raw(
+            key,
+            &Versioned {
+                version,
+                value: val,
+            },
+        )?;
+        if current.is_none() {
+            self.index_add(key)?;
+        }
+        Ok(version)
+    }
+
+    /// put_if_version stores `val` at `key` only if the key is at `expected_version` (0 meaning the key must
+    /// not exist yet).  This is not a compare-and-swap and is unsafe under concurrency: the version is checked
+    /// by bitcode before the write, a request writing in between is silently overwritten.
+    /// # Returns
+    /// the new version, or None if the key has moved on from `expected_version`
+    pub fn put_if_version<T: Serialize>(
+        &self,
+        key: &str,
+        expected_version: u64,
+        val: &T,
+    ) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        check_key(key)?;
+        if self.version(key)? != expected_version {
+            return Ok(None);
+        }
+        let version = expected_version + 1;
+        self.set_raw(
+            key,
+            &Versioned {
+                version,
+                value: val,
+            },
+        )?;
+        if expected_version == 0 {
+            self.index_add(key)?;
+        }
+        Ok(Some(version))
+    }
+
+    /// delete removes `key`
+    /// # Returns
+    /// whether the key existed
+    pub fn delete(&self, key: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        check_key(key)?;
+        let existed = self.get_raw(key)?.is_some();
+        self.bcc.qss_delete(&self.qssid, &self.full_key(key))?;
+        self.index_remove(key)?;
+        Ok(existed)
+    }
+
+    /// increment adds `delta` to the counter at `key`, creating it at 0 if needed.  It is a read followed by a
+    /// [StateStore::put_if_version], retried when the counter changed in between, and is unsafe under
+    /// concurrency: increments racing with it may be lost.
+    /// # Returns
+    /// the counter value after the increment
+    pub fn increment(&self, key: &str, delta: i64) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        for _ in 0..MAX_UPDATE_RETRIES {
+            let (version, current) = match self.get_versioned::<i64>(key)? {
+                Some(v) => (v.version, v.value),
+                None => (0, 0),
+            };
+            let next = current + delta;
+            if self.put_if_version(key, version, &next)?.is_some() {
+                return Ok(next);
+            }
+        }
+        Err(Box::new(ErrorKinds::Other(format!(
+            "increment of {} failed after {MAX_UPDATE_RETRIES} conflicting updates",
+            self.full_key(key)
+        ))))
+    }
+
+    /// list_keys lists the keys of this namespace starting with `prefix`, sorted
+    pub fn list_keys(&self, prefix: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let mut keys: Vec<String> = self
+            .index()?
+            .into_iter()
+            .filter(|k| k.starts_with(prefix))
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// list retrieves every key of this namespace starting with `prefix` along with its value
+    pub fn list<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, T)>, Box<dyn Error + Send + Sync>> {
+        let mut ret = Vec::new();
+        for key in self.list_keys(prefix)? {
+            if let Some(v) = self.get::<T>(&key)? {
+                ret.push((key, v));
+            }
+        }
+        Ok(ret)
+    }
+
+    fn index(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .load::<Vec<String>>(KEY_INDEX)?
+            .map(|v| v.value)
+            .unwrap_or_default())
+    }
+
+    fn index_add(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut keys = self.index()?;
+        if !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+            self.set_raw(
+                KEY_INDEX,
+                &Versioned {
+                    version: 0,
+                    value: keys,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn index_remove(&self, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut keys = self.index()?;
+        let len = keys.len();
+        keys.retain(|k| k != key);
+        if keys.len() != len {
+            self.set_raw(
+                KEY_INDEX,
+                &Versioned {
+                    version: 0,
+                    value: keys,
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_get_result() {
+        assert_eq!(
+            decode_get_result("k", b"\"{\\\"version\\\":1}\"").unwrap(),
+            Some("{\"version\":1}".to_string())
+        );
+        assert_eq!(decode_get_result("k", b"null").unwrap(), None);
+        let missing = json!({"op" : 6, "desc" : {"NotExist" : "key k not found"}});
+        assert_eq!(
+            decode_get_result("k", missing.to_string().as_bytes()).unwrap(),
+            None
+        );
+        let denied = json!({"op" : 3, "desc" : {"Permission" : "denied"}});
+        assert!(decode_get_result("k", denied.to_string().as_bytes()).is_err());
+        assert_eq!(
+            decode_get_result("k", b"plain").unwrap(),
+            Some("plain".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("job1").is_ok());
+        assert!(check_key("jobs/.keys.bak").is_ok());
+        assert!(check_key("jobs/.keys").is_err());
+        assert!(check_key(KEY_INDEX).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+//! Retry with backoff and circuit breaking for calls through [HttpClient] <br>
+//! A [ResilientClient] retries failed idempotent requests with exponential backoff and keeps a circuit
+//! breaker per target (the host of a `ProxyHttp` url or the service of a `RestCall`) in a [StateStore], so
+//! that once a target is failing later requests are refused with [ErrorKinds::CircuitOpen] instead of paying
+//! for the failure again.  Policies are read per target from metadata, by default at [HTTP_POLICY_META_PATH]:
+//! ```json
+//! {
+//!   "default" : { "retry" : { "max_attempts" : 3 }, "breaker" : { "failure_threshold" : 5, "reset_ms" : 30000 } },
+//!   "targets" : { "api.example.com" : { "retry" : { "max_attempts" : 1 } } }
+//! }
+//! ```
+//! Times are in milliseconds, as returned by [BitcodeContext::q_system_time].  Bitcode cannot sleep, so
+//! [ResilientClient::send] only chains retries without a backoff, which is the default.  With a backoff
+//! configured, [ResilientClient::attempt] hands the wait back to the caller, e.g. to retry from a later
+//! request or an LRO job.
+//! ```rust
+//! use elvwasm::{HttpClient, HttpRequest, ResiliencePolicies, ResilientClient, StateStore, HTTP_POLICY_META_PATH};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let store = StateStore::from_meta(bcc, "/state_store/qssid")?;
+//!   let policies = ResiliencePolicies::from_meta(bcc, HTTP_POLICY_META_PATH)?;
+//!   let client = ResilientClient::new(HttpClient::proxy(bcc), &store, policies);
+//!   let resp = client.send(&HttpRequest::get("https://api.example.com/items"))?;
+//!   Ok(resp.body)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{
+    BitcodeContext, ErrorKinds, HttpClient, HttpRequest, HttpTransport, ProxyResponse, StateStore,
+    SystemTimeResult,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Default metadata path of the [ResiliencePolicies]
+pub const HTTP_POLICY_META_PATH: &str = "/http_policies";
+
+/// Namespace of the state store used for circuit breaker state
+pub const BREAKER_NAMESPACE: &str = "breaker";
+
+/// How failed calls to a target are retried
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// total number of attempts, 1 disables retries
+    pub max_attempts: u32,
+    /// wait before the first retry, 0 retries at once
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: u64,
+    /// response statuses treated as failures and retried
+    pub retry_on_status: Vec<usize>,
+    /// also retry methods that are not idempotent, e.g. POST
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 0,
+            max_backoff_ms: 8000,
+            multiplier: 2,
+            retry_on_status: vec![429, 502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// backoff_ms is the wait before retry number `retry` (starting at 1)
+    pub fn backoff_ms(&self, retry: u32) -> u64 {
+        let mut b = self.initial_backoff_ms;
+        for _ in 1..retry {
+            b = b.saturating_mul(self.multiplier.max(1));
+        }
+        b.min(self.max_backoff_ms)
+    }
+
+    pub fn is_retryable_status(&self, status: usize) -> bool {
+        self.retry_on_status.contains(&status)
+    }
+}
+
+/// When a target's circuit opens and for how long
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BreakerPolicy {
+    /// consecutive failed calls that open the circuit, 0 disables the breaker
+    pub failure_threshold: u32,
+    /// milliseconds the circuit stays open before a single trial call is let through, and the time after
+    /// which a trial call that never reported back is given up on
+    pub reset_ms: u64,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy {
+            failure_threshold: 5,
+            reset_ms: 30000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TargetPolicy {
+    pub retry: RetryPolicy,
+    pub breaker: BreakerPolicy,
+}
+
+/// The [TargetPolicy] of each target along with the default for all others
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ResiliencePolicies {
+    pub default: TargetPolicy,
+    pub targets: HashMap<String, TargetPolicy>,
+}
+
+impl ResiliencePolicies {
+    /// from_meta reads the policies from the metadata at `meta_path`, the defaults if there are none
+    pub fn from_meta(
+        bcc: &BitcodeContext,
+        meta_path: &str,
+    ) -> Result<ResiliencePolicies, Box<dyn Error + Send + Sync>> {
+        let v: Value = match bcc.sqmd_get_json(meta_path) {
+            Ok(res) => serde_json::from_slice(&res).unwrap_or(Value::Null),
+            Err(_) => Value::Null,
+        };
+        if !v.is_object() || v.get("op").is_some() {
+            return Ok(ResiliencePolicies::default());
+        }
+        Ok(serde_json::from_value(v)?)
+    }
+
+    pub fn for_target(&self, target: &str) -> &TargetPolicy {
+        self.targets.get(target).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl Default for CircuitState {
+    fn default() -> Self {
+        CircuitState::Closed
+    }
+}
+
+/// The circuit breaker state of a target as kept in the state store
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BreakerState {
+    pub state: CircuitState,
+    pub failures: u32,
+    /// when the circuit opened, or when the trial call of a half open circuit was let through
+    pub opened_at: u64,
+}
+
+impl BreakerState {
+    /// admit decides whether a call may proceed at time `now`.  An expired open circuit moves to half open
+    /// and admits the call as its trial, a half open circuit admits nothing else until the trial has reported
+    /// or expired.
+    pub fn admit(&mut self, policy: &BreakerPolicy, now: u64) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open | CircuitState::HalfOpen => {
+                if now.saturating_sub(self.opened_at) >= policy.reset_ms {
+                    self.state = CircuitState::HalfOpen;
+                    self.opened_at = now;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.state = CircuitState::Closed;
+        self.failures = 0;
+    }
+
+    pub fn record_failure(&mut self, policy: &BreakerPolicy, now: u64) {
+        self.failures += 1;
+        if policy.failure_threshold != 0
+            && (self.state == CircuitState::HalfOpen || self.failures >= policy.failure_threshold)
+        {
+            self.state = CircuitState::Open;
+            self.opened_at = now;
+        }
+    }
+}
+
+/// target_of names the breaker target of a url: its host for absolute urls, its first segment otherwise
+/// ```rust
+/// assert_eq!(elvwasm::target_of("https://api.example.com:8443/v1?q=1"), "api.example.com:8443");
+/// assert_eq!(elvwasm::target_of("node_service_1/rep/tagger"), "node_service_1");
+/// ```
+pub fn target_of(url: &str) -> String {
+    let rest = match url.split_once("://") {
+        Some((_, r)) => r,
+        None => url.trim_start_matches('/'),
+    };
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let host = &rest[..end];
+    host.rsplit_once('@')
+        .map(|(_, h)| h)
+        .unwrap_or(host)
+        .to_string()
+}
+
+fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+    )
+}
+
+/// The outcome of one [ResilientClient::attempt]
+#[derive(Debug)]
+pub enum Attempt {
+    /// the response to hand back, successful, not retryable or of the last allowed attempt
+    Done(ProxyResponse),
+    /// the attempt failed and attempt `next` may be made once `retry_after_ms` have passed
+    Retry {
+        next: u32,
+        retry_after_ms: u64,
+        /// the failed response, None if the call itself failed
+        response: Option<ProxyResponse>,
+        reason: String,
+    },
+}
+
+/// ResilientClient wraps an [HttpClient] with retries and circuit breaking, see the
+/// [module documentation](self)
+pub struct ResilientClient<'a> {
+    client: HttpClient<'a>,
+    store: StateStore<'a>,
+    policies: ResiliencePolicies,
+}
+
+impl<'a> ResilientClient<'a> {
+    /// new creates a client keeping breaker state in the [BREAKER_NAMESPACE] namespace of `store`
+    pub fn new(
+        client: HttpClient<'a>,
+        store: &StateStore<'a>,
+        policies: ResiliencePolicies,
+    ) -> ResilientClient<'a> {
+        ResilientClient {
+            client,
+            store: store.namespace(BREAKER_NAMESPACE),
+            policies,
+        }
+    }
+
+    fn now(&self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let st: SystemTimeResult = self.store.context().q_system_time().try_into()?;
+        Ok(st.time)
+    }
+
+    fn breaker_key(&self, target: &str) -> String {
+        let prefix = match self.client.transport() {
+            HttpTransport::ProxyHttp => "proxy",
+            HttpTransport::RestCall => "rest",
+        };
+        format!("{prefix}/{target}")
+    }
+
+    /// breaker retrieves the circuit breaker state of `target`
+    pub fn breaker(&self, target: &str) -> Result<BreakerState, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .store
+            .get::<BreakerState>(&self.breaker_key(target))?
+            .unwrap_or_default())
+    }
+
+    /// reset closes the circuit of `target`
+    pub fn reset(&self, target: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.delete(&self.breaker_key(target))?;
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        target: &str,
+        policy: &BreakerPolicy,
+        success: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut b = self.breaker(target)?;
+        if success {
+            if b.state == CircuitState::Closed && b.failures == 0 {
+                return Ok(());
+            }
+            b.record_success();
+        } else {
+            b.record_failure(policy, self.now()?);
+        }
+        self.store.put(&self.breaker_key(target), &b)?;
+        Ok(())
+    }
+
+    // admits a call through the breaker of `target`, a request finding the circuit moved to half open by
+    // another is refused, requests racing on the state store may still both make the trial call
+    fn admit(
+        &self,
+        target: &str,
+        policy: &BreakerPolicy,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let key = self.breaker_key(target);
+        let (version, mut b) = match self.store.get_versioned::<BreakerState>(&key)? {
+            Some(v) => (v.version, v.value),
+            None => (0, BreakerState::default()),
+        };
+        let before = b.clone();
+        let admitted = b.admit(policy, self.now()?)
+            && (b == before || self.store.put_if_version(&key, version, &b)?.is_some());
+        if !admitted {
+            return Err(Box::new(ErrorKinds::CircuitOpen(format!(
+                "circuit for {target} open after {} failures",
+                b.failures
+            ))));
+        }
+        Ok(())
+    }
+
+    /// attempt makes attempt number `attempt` (starting at 1) of a request
+    /// # Returns
+    /// [Attempt::Retry] with the backoff to wait if the attempt failed and the target's [RetryPolicy]
+    /// allows another, otherwise [Attempt::Done] with the response.  The call's error is returned if it
+    /// failed on the last attempt, and [ErrorKinds::CircuitOpen] if the target's circuit is open.
+    pub fn attempt(
+        &self,
+        req: &HttpRequest,
+        attempt: u32,
+    ) -> Result<Attempt, Box<dyn Error + Send + Sync>> {
+        let target = target_of(req.url());
+        let policy = self.policies.for_target(&target).clone();
+        let attempts = if is_idempotent(req.method()) || policy.retry.retry_non_idempotent {
+            policy.retry.max_attempts.max(1)
+        } else {
+            1
+        };
+        self.admit(&target, &policy.breaker)?;
+        let outcome = self.client.send(req);
+        let reason = match &outcome {
+            Ok(resp) if policy.retry.is_retryable_status(resp.status) => {
+                format!("status {}", resp.status)
+            }
+            Ok(_) => String::new(),
+            Err(e) => e.to_string(),
+        };
+        let failed = !reason.is_empty();
+        self.record(&target, &policy.breaker, !failed)?;
+        if !failed || attempt >= attempts {
+            return Ok(Attempt::Done(outcome?));
+        }
+        let retry_after_ms = policy.retry.backoff_ms(attempt);
+        self.store.context().log_warn(&format!(
+            "attempt {attempt} of {attempts} of {} to {target} failed, retry in {retry_after_ms}ms err={reason}",
+            req.method()
+        ))?;
+        Ok(Attempt::Retry {
+            next: attempt + 1,
+            retry_after_ms,
+            response: outcome.ok(),
+            reason,
+        })
+    }
+
+    /// send issues a request, retrying failures as allowed by the target's [RetryPolicy] as long as no
+    /// backoff is due.  A failure calling for a wait ends the retries, see [ResilientClient::attempt].
+    /// # Returns
+    /// the response of the last attempt, or [ErrorKinds::CircuitOpen] if the target's circuit is open
+    pub fn send(&self, req: &HttpRequest) -> Result<ProxyResponse, Box<dyn Error + Send + Sync>> {
+        let mut attempt = 1;
+        loop {
+            match self.attempt(req, attempt)? {
+                Attempt::Done(resp) => return Ok(resp),
+                Attempt::Retry {
+                    next,
+                    retry_after_ms: 0,
+                    ..
+                } => attempt = next,
+                Attempt::Retry {
+                    retry_after_ms,
+                    response,
+                    reason,
+                    ..
+                } => {
+                    return match response {
+                        Some(resp) => Ok(resp),
+                        None => Err(Box::new(ErrorKinds::IO(format!(
+                            "{} failed, retry after {retry_after_ms}ms err={reason}",
+                            req.url()
+                        )))),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_backoff_and_policies() {
+        let mut r = RetryPolicy::default();
+        assert_eq!(r.backoff_ms(2), 0);
+        r.initial_backoff_ms = 1000;
+        assert_eq!(
+            (1..6).map(|i| r.backoff_ms(i)).collect::<Vec<u64>>(),
+            vec![1000, 2000, 4000, 8000, 8000]
+        );
+        let p: ResiliencePolicies = serde_json::from_value(json!({
+            "default" : {"breaker" : {"failure_threshold" : 2}},
+            "targets" : {"api.example.com" : {"retry" : {"max_attempts" : 1}}}
+        }))
+        .unwrap();
+        assert_eq!(p.default.breaker.failure_threshold, 2);
+        assert_eq!(p.default.breaker.reset_ms, 30000);
+        assert_eq!(p.for_target("api.example.com").retry.max_attempts, 1);
+        assert_eq!(p.for_target("other").retry.max_attempts, 3);
+    }
+
+    #[test]
+    fn test_breaker_transitions() {
+        let policy = BreakerPolicy {
+            failure_threshold: 2,
+            reset_ms: 10000,
+        };
+        let mut b = BreakerState::default();
+        b.record_failure(&policy, 100_000);
+        assert_eq!(b.state, CircuitState::Closed);
+        b.record_failure(&policy, 101_000);
+        assert_eq!(b.state, CircuitState::Open);
+        assert!(!b.admit(&policy, 105_000));
+        assert!(b.admit(&policy, 111_000));
+        assert_eq!(b.state, CircuitState::HalfOpen);
+        // a single trial call while half open, until it expires
+        assert!(!b.admit(&policy, 112_000));
+        b.record_failure(&policy, 112_000);
+        assert_eq!(b.state, CircuitState::Open);
+        assert!(b.admit(&policy, 122_000));
+        assert!(!b.admit(&policy, 123_000));
+        assert!(b.admit(&policy, 132_000));
+        b.record_success();
+        assert_eq!(b.state, CircuitState::Closed);
+        assert_eq!(b.failures, 0);
+    }
+}
@@ -16,7 +16,7 @@ impl<'a> BitcodeContext {
     ///
     /// [Example](https://github.com/eluv-io/elv-wasm/blob/d261ece2140e5fc498edc470c6495065d1643b14/samples/search/src/lib.rs#L85)
     ///
-    pub fn new_index_builder(&'a mut self, _v: serde_json::Value) -> CallResult {
+    pub fn new_index_builder(&'a self, _v: serde_json::Value) -> CallResult {
         let method = "NewIndexBuilder";
         let vp = json!({});
         let impl_result = self.call_function(method, vp, "search")?;
@@ -57,6 +57,17 @@ impl<'a> BitcodeContext {
         self.call_function("QueryParserParseQuery", json!({ "query": query }), "search")
     }
 
+    /// query_parser_parse_json_query Queries the index using a structured query, the JSON form of a
+    /// [Query](crate::search::Query), for the clauses the query parser syntax cannot express
+    ///
+    pub fn query_parser_parse_json_query(&'a self, query: &serde_json::Value) -> CallResult {
+        self.call_function(
+            "QueryParserParseJsonQuery",
+            json!({ "query": query }),
+            "search",
+        )
+    }
+
     implement_ext_func!(
         /// builder_add_text_field adds a new text field to a Tantivy index
         /// # Arguments
@@ -80,6 +91,163 @@ impl<'a> BitcodeContext {
         "BuilderAddTextField",
         "search"
     );
+    implement_ext_func!(
+        /// builder_register_tokenizer registers a named text analyzer with the index, used by the text fields
+        /// naming it as their tokenizer and by the query parser searching them
+        /// # Arguments
+        /// * `v` : a JSON Value, the name and the [Analyzer](crate::search::Analyzer)
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   let v = json!({
+        ///     "name": "english",
+        ///     "analyzer": { "tokenizer": { "type": "simple" }, "lowercase": true, "stemmer": "english" },
+        ///   });
+        ///   bcc.builder_register_tokenizer(Some(v))
+        /// }
+        /// ```
+        ///
+        builder_register_tokenizer,
+        "BuilderRegisterTokenizer",
+        "search"
+    );
+    implement_ext_func!(
+        /// builder_add_u64_field adds a new unsigned 64 bit integer field to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_u64_field(Some(json!({ "name": "year", "indexed": true, "stored": true, "fast": true })))
+        /// }
+        /// ```
+        ///
+        builder_add_u64_field,
+        "BuilderAddU64Field",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_i64_field adds a new signed 64 bit integer field to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_i64_field(Some(json!({ "name": "offset", "indexed": true, "stored": true, "fast": false })))
+        /// }
+        /// ```
+        ///
+        builder_add_i64_field,
+        "BuilderAddI64Field",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_f64_field adds a new 64 bit float field to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_f64_field(Some(json!({ "name": "rating", "indexed": true, "stored": true, "fast": true })))
+        /// }
+        /// ```
+        ///
+        builder_add_f64_field,
+        "BuilderAddF64Field",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_date_field adds a new date field to a Tantivy index, values being RFC 3339 timestamps
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_date_field(Some(json!({ "name": "release_date", "indexed": true, "stored": true, "fast": true })))
+        /// }
+        /// ```
+        ///
+        builder_add_date_field,
+        "BuilderAddDateField",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_bool_field adds a new boolean field to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_bool_field(Some(json!({ "name": "published", "indexed": true, "stored": true, "fast": false })))
+        /// }
+        /// ```
+        ///
+        builder_add_bool_field,
+        "BuilderAddBoolField",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_facet_field adds a new hierarchical facet field (e.g. `/genre/drama`) to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_facet_field(Some(json!({ "name": "category", "stored": true })))
+        /// }
+        /// ```
+        ///
+        builder_add_facet_field,
+        "BuilderAddFacetField",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_bytes_field adds a new raw bytes field to a Tantivy index, values being base64 encoded
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_bytes_field(Some(json!({ "name": "thumbnail", "indexed": false, "stored": true, "fast": false })))
+        /// }
+        /// ```
+        ///
+        builder_add_bytes_field,
+        "BuilderAddBytesField",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// builder_add_json_field adds a new JSON object field to a Tantivy index
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.builder_add_json_field(Some(json!({ "name": "attributes", "type": 2, "stored": true, "tokenizer": "default" })))
+        /// }
+        /// ```
+        ///
+        builder_add_json_field,
+        "BuilderAddJsonField",
+        "search"
+    );
+
     implement_ext_func!(
         /// builder_build builds the new Index
         /// Arguments None
@@ -129,6 +297,142 @@ impl<'a> BitcodeContext {
         "search"
     );
 
+    implement_ext_func!(
+        /// document_add_u64 adds an unsigned integer value to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_u64(Some(json!({ "field": 3, "value": 1999, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_u64,
+        "DocumentAddU64",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_i64 adds a signed integer value to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_i64(Some(json!({ "field": 3, "value": -5, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_i64,
+        "DocumentAddI64",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_f64 adds a floating point value to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_f64(Some(json!({ "field": 3, "value": 7.5, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_f64,
+        "DocumentAddF64",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_date adds a date, in seconds since the epoch, to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_date(Some(json!({ "field": 3, "value": 1672531200, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_date,
+        "DocumentAddDate",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_bool adds a boolean value to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_bool(Some(json!({ "field": 3, "value": true, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_bool,
+        "DocumentAddBool",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_facet adds a facet path to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_facet(Some(json!({ "field": 3, "value": "/genre/drama", "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_facet,
+        "DocumentAddFacet",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_bytes adds base64 encoded bytes to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_bytes(Some(json!({ "field": 3, "value": "AAEC", "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_bytes,
+        "DocumentAddBytes",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// document_add_json adds a JSON object to a given document
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.document_add_json(Some(json!({ "field": 3, "value": { "cast": ["a", "b"] }, "doc_id": 0 })))
+        /// }
+        /// ```
+        ///
+        document_add_json,
+        "DocumentAddJson",
+        "search"
+    );
+
     implement_ext_func!(
         /// document_create_index creates an index given a set of documents
         ///
@@ -169,6 +473,40 @@ impl<'a> BitcodeContext {
         "search"
     );
 
+    implement_ext_func!(
+        /// index_writer_delete_term deletes all documents containing the given term
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.index_writer_delete_term(Some(json!({ "field": 0, "value": "iq__abc" })))
+        /// }
+        /// ```
+        ///
+        index_writer_delete_term,
+        "IndexWriterDeleteTerm",
+        "search"
+    );
+
+    implement_ext_func!(
+        /// index_writer_rollback discards every change made since the last commit
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.index_writer_rollback(Some(json!({})))
+        /// }
+        /// ```
+        ///
+        index_writer_rollback,
+        "IndexWriterRollback",
+        "search"
+    );
+
     implement_ext_func!(
         /// index_reader_builder_create creates a new reader builder on an index
         ///
@@ -222,6 +560,28 @@ impl<'a> BitcodeContext {
         "search"
     );
 
+    implement_ext_func!(
+        /// index_reader_terms lists the terms of a field's dictionary along with their document frequency,
+        /// either those starting with `prefix` or those within a Levenshtein `distance` of a `fuzzy` term
+        /// # Arguments
+        /// * `v` : a JSON Value
+        /// ```
+        /// use serde_json::json;
+        ///
+        ///fn do_something<'s>(bcc: &'s elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+        ///   bcc.index_reader_terms(Some(json!({
+        ///     "field": "title",
+        ///     "fuzzy": { "term": "mna", "distance": 2, "transpositions": true },
+        ///     "limit": 10,
+        ///   })))
+        /// }
+        /// ```
+        ///
+        index_reader_terms,
+        "IndexReaderTerms",
+        "search"
+    );
+
     implement_ext_func!(
         /// query_parser_search searches the given QueryParser for the term
         ///
@@ -0,0 +1,314 @@
+//! Stream helpers layered over the raw fabric stream APIs of the [BitcodeContext] <br>
+//! [StreamReader] adapts a fabric stream (e.g. the incoming `fis`, or a stream fed by
+//! [BitcodeContext::write_part_to_stream]) to [std::io::Read], and [BitcodeContext::q_upload_reader]
+//! uploads any [std::io::Read] into a fabric file in bounded chunks.
+
+extern crate md5;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{detect_mime, BitcodeContext, ErrorKinds, FileStream, WriteResult, MIME_SNIFF_LEN};
+
+use guest::CallResult;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io::{ErrorKind, Read};
+
+/// Default chunk size used when reading from or writing to fabric streams
+pub const DEFAULT_STREAM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Default MIME type used when no type is supplied for an upload
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Checksum verification of an uploaded file
+#[derive(Clone, Debug)]
+pub struct UploadChecksum {
+    /// checksum method ("MD5" or "SHA256")
+    pub method: String,
+    /// expected hex-encoded checksum
+    pub expected: String,
+}
+
+/// Options for [BitcodeContext::q_upload_reader]
+#[derive(Clone, Debug, Default)]
+pub struct UploadOptions {
+    /// size of each write to the file stream, 0 selects [DEFAULT_STREAM_CHUNK_SIZE]
+    pub chunk_size: usize,
+    /// MIME type to store the file as, detected from the data and path if None (see [crate::detect_mime])
+    pub mime: Option<String>,
+    /// optional checksum the data must match before the file is created
+    pub checksum: Option<UploadChecksum>,
+}
+
+impl UploadOptions {
+    pub fn new() -> UploadOptions {
+        UploadOptions::default()
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> UploadOptions {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn mime(mut self, mime: &str) -> UploadOptions {
+        self.mime = Some(mime.to_string());
+        self
+    }
+
+    pub fn checksum(mut self, method: &str, expected: &str) -> UploadOptions {
+        self.checksum = Some(UploadChecksum {
+            method: method.to_string(),
+            expected: expected.to_string(),
+        });
+        self
+    }
+
+    fn effective_chunk_size(&self) -> usize {
+        if self.chunk_size == 0 {
+            DEFAULT_STREAM_CHUNK_SIZE
+        } else {
+            self.chunk_size
+        }
+    }
+}
+
+// the running digest of an upload with a checksum option
+enum UploadHasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl UploadHasher {
+    fn new(method: &str) -> Result<UploadHasher, Box<dyn Error + Send + Sync>> {
+        match method.to_ascii_uppercase().as_str() {
+            "MD5" => Ok(UploadHasher::Md5(Md5::new())),
+            "SHA256" => Ok(UploadHasher::Sha256(Sha256::new())),
+            _ => Err(Box::new(ErrorKinds::Invalid(format!(
+                "unsupported checksum method {method}"
+            )))),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            UploadHasher::Md5(h) => h.update(data),
+            UploadHasher::Sha256(h) => h.update(data),
+        }
+    }
+
+    fn hex(self) -> String {
+        let digest = match self {
+            UploadHasher::Md5(h) => h.finalize().to_vec(),
+            UploadHasher::Sha256(h) => h.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+fn verify_checksum(
+    path: &str,
+    cs: &UploadChecksum,
+    hasher: UploadHasher,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let actual = hasher.hex();
+    if !actual.eq_ignore_ascii_case(cs.expected.trim()) {
+        return Err(Box::new(ErrorKinds::Invalid(format!(
+            "checksum mismatch path={path} method={} expected={} actual={actual}",
+            cs.method, cs.expected
+        ))));
+    }
+    Ok(())
+}
+
+/// StreamReader implements [std::io::Read] over a fabric stream using the `Reader` stream operation
+/// ```rust
+/// use std::io::Read;
+/// fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+///   let mut data = vec![];
+///   bcc.stream_reader("fis").read_to_end(&mut data)?;
+///   Ok(data)
+/// }
+/// ```
+pub struct StreamReader<'a> {
+    bcc: &'a BitcodeContext,
+    stream_id: String,
+    chunk_size: usize,
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+impl<'a> StreamReader<'a> {
+    pub fn new(bcc: &'a BitcodeContext, stream_id: &str, chunk_size: usize) -> StreamReader<'a> {
+        StreamReader {
+            bcc,
+            stream_id: stream_id.to_string(),
+            chunk_size: if chunk_size == 0 {
+                DEFAULT_STREAM_CHUNK_SIZE
+            } else {
+                chunk_size
+            },
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+
+    pub fn stream_id(&self) -> &str {
+        &self.stream_id
+    }
+}
+
+impl Read for StreamReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() && !self.eof {
+            let want = std::cmp::min(buf.len(), self.chunk_size);
+            self.pending = self
+                .bcc
+                .read_stream(self.stream_id.clone(), want)
+                .map_err(|e| std::io::Error::new(ErrorKind::Other, e.to_string()))?;
+            if self.pending.is_empty() {
+                self.eof = true;
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<'a> BitcodeContext {
+    /// stream_reader creates a [StreamReader] over the given fabric stream
+    /// # Arguments
+    /// * `stream_id` : the stream to read (e.g. "fis")
+    pub fn stream_reader(&'a self, stream_id: &str) -> StreamReader<'a> {
+        StreamReader::new(self, stream_id, DEFAULT_STREAM_CHUNK_SIZE)
+    }
+
+    /// q_upload_reader : uploads the contents of a reader to the fabric file location without buffering
+    /// the whole payload.  The data is written to a new file stream in chunks of at most `opts.chunk_size`
+    /// and the file is then created with the accumulated size.  With a checksum option the data is hashed as it
+    /// is written and no file is created unless it matches.
+    /// # Arguments
+    /// * `qwt` : a fabric write token
+    /// * `reader` : any [std::io::Read] e.g. a [StreamReader]
+    /// * `path` : fabric file location
+    /// * `opts` : chunking, MIME type and checksum options
+    /// # Returns
+    /// slice of [u8] parseable to [crate::QPartInfo]
+    /// ```rust
+    /// use elvwasm::UploadOptions;
+    /// fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+    ///   let qwt = bcc.request.q_info.write_token.clone();
+    ///   let mut fis = bcc.stream_reader("fis");
+    ///   bcc.q_upload_reader(&qwt, &mut fis, "/assets/upload.bin", &UploadOptions::new().mime("image/png"))
+    /// }
+    /// ```
+    pub fn q_upload_reader<R: Read>(
+        &'a self,
+        qwt: &str,
+        reader: &mut R,
+        path: &str,
+        opts: &UploadOptions,
+    ) -> CallResult {
+        let mut hasher = match &opts.checksum {
+            Some(cs) => Some(UploadHasher::new(&cs.method)?),
+            None => None,
+        };
+        let new_stream: FileStream = self.new_file_stream().try_into()?;
+        let sid = new_stream.stream_id.clone();
+        defer! {
+          let _ = self.close_stream(sid.clone());
+        }
+        let mut chunk = vec![0u8; opts.effective_chunk_size()];
+        let mut total: usize = 0;
+        let mut sniffed: Vec<u8> = Vec::new();
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(Box::new(ErrorKinds::IO(format!(
+                        "q_upload_reader read failed path={path} after {total} bytes err={e}"
+                    ))))
+                }
+            };
+            let wr: WriteResult = self
+                .write_stream(&new_stream.stream_id, &chunk[..n])
+                .try_into()?;
+            if wr.written != n {
+                return Err(Box::new(ErrorKinds::IO(format!(
+                    "q_upload_reader short write path={path} wrote {} of {n}",
+                    wr.written
+                ))));
+            }
+            if let Some(h) = hasher.as_mut() {
+                h.update(&chunk[..n]);
+            }
+            if opts.mime.is_none() && sniffed.len() < MIME_SNIFF_LEN {
+                let take = std::cmp::min(n, MIME_SNIFF_LEN - sniffed.len());
+                sniffed.extend_from_slice(&chunk[..take]);
+            }
+            total += n;
+        }
+        if let (Some(cs), Some(h)) = (&opts.checksum, hasher) {
+            verify_checksum(path, cs, h)?;
+        }
+        let mime = match &opts.mime {
+            Some(m) => m.as_str(),
+            None => detect_mime(&sniffed, Some(path)),
+        };
+        self.log_debug(&format!(
+            "q_upload_reader path={path} size={total} mime={mime}"
+        ))?;
+        self.q_create_file_from_stream(&new_stream.stream_id, qwt, path, mime, total as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(method: &str, chunks: &[&[u8]]) -> String {
+        let mut h = UploadHasher::new(method).unwrap();
+        for c in chunks {
+            h.update(c);
+        }
+        h.hex()
+    }
+
+    #[test]
+    fn test_upload_hasher() {
+        assert_eq!(
+            digest("MD5", &[b"hello ", b"world"]),
+            "5eb63bbbe01eeed093cb22bb8f5acdc3"
+        );
+        assert_eq!(
+            digest("sha256", &[b"hello", b" world"]),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert!(UploadHasher::new("crc32").is_err());
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let opts = UploadOptions::new().checksum("MD5", " 5EB63BBBE01EEED093CB22BB8F5ACDC3 ");
+        let cs = opts.checksum.as_ref().unwrap();
+        let mut h = UploadHasher::new("MD5").unwrap();
+        h.update(b"hello world");
+        assert!(verify_checksum("/f", cs, h).is_ok());
+        let mut h = UploadHasher::new("MD5").unwrap();
+        h.update(b"hello there");
+        assert!(verify_checksum("/f", cs, h).is_err());
+        assert_eq!(opts.effective_chunk_size(), DEFAULT_STREAM_CHUNK_SIZE);
+        assert_eq!(opts.chunk_size(16).effective_chunk_size(), 16);
+    }
+}
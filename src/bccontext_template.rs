@@ -0,0 +1,337 @@
+//! Safe rendering of request templates kept in content metadata <br>
+//! A template is any JSON value, typically the `/request_parameters` of an rproxy style bitcode, whose
+//! strings reference variables as `${NAME}`.  Variables come from the request's query parameters and headers,
+//! the content's [QInfo](crate::QInfo) and other metadata:
+//! * `${NAME}` or `${query.NAME}` : the first value of query parameter `NAME`
+//! * `${header.NAME}` : the first value of request header `NAME` (case insensitive)
+//! * `${qinfo.hash}`, `${qinfo.id}`, `${qinfo.qlib_id}`, `${qinfo.type}`, `${qinfo.write_token}`, `${qinfo.qhot}`
+//! * `${meta./some/path}` : the string (or JSON rendering) of the metadata at `/some/path`
+//!
+//! A variable may carry a default, `${NAME:-fallback}`; without one a missing variable is an error.  Values are
+//! escaped according to where they land, URL encoded inside a `url`, rejected when they would break a header
+//! and inserted verbatim elsewhere since the rendered value remains structured JSON.  An explicit filter,
+//! `${NAME|url}`, `${NAME|header}`, `${NAME|json}` or `${NAME|raw}`, overrides the context, e.g.
+//! `${QUERY|json:-*}` for a variable embedded in a string holding JSON text.
+//! ```rust
+//! use elvwasm::TemplateVars;
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let vars = TemplateVars::from_request(bcc).allow(&["API_KEY", "QUERY", "CONTEXT"]);
+//!   let req = bcc.render_request_template("/request_parameters", &vars)?;
+//!   bcc.proxy_http(Some(serde_json::json!({ "request": req })))
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{encode_uri_component, BitcodeContext, ErrorKinds};
+
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+/// How a variable's value is escaped when substituted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// inserted verbatim
+    Raw,
+    /// percent encoded as a URL component
+    Url,
+    /// rejected if it contains control characters such as CR or LF
+    Header,
+    /// escaped for inclusion inside a JSON string literal
+    Json,
+}
+
+impl Escape {
+    fn from_filter(f: &str) -> Option<Escape> {
+        match f {
+            "raw" => Some(Escape::Raw),
+            "url" => Some(Escape::Url),
+            "header" => Some(Escape::Header),
+            "json" => Some(Escape::Json),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, name: &str, val: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self {
+            Escape::Raw => Ok(val.to_string()),
+            Escape::Url => Ok(encode_uri_component(val)),
+            Escape::Header => {
+                if val.chars().any(|c| c.is_control() && c != '\t') {
+                    return Err(Box::new(ErrorKinds::BadHttpParams(format!(
+                        "template variable {name} contains characters not allowed in a header"
+                    ))));
+                }
+                Ok(val.to_string())
+            }
+            Escape::Json => {
+                let quoted = Value::String(val.to_string()).to_string();
+                Ok(quoted[1..quoted.len() - 1].to_string())
+            }
+        }
+    }
+}
+
+/// The variables available to a template, see the [module documentation](self)
+#[derive(Clone, Default)]
+pub struct TemplateVars<'a> {
+    bcc: Option<&'a BitcodeContext>,
+    vars: HashMap<String, String>,
+    allowed: Option<HashSet<String>>,
+}
+
+impl<'a> TemplateVars<'a> {
+    pub fn new() -> TemplateVars<'a> {
+        TemplateVars::default()
+    }
+
+    /// from_request collects the query parameters, headers and [QInfo](crate::QInfo) of the current request
+    /// and resolves `meta.` variables through `bcc`
+    pub fn from_request(bcc: &'a BitcodeContext) -> TemplateVars<'a> {
+        let mut tv = TemplateVars {
+            bcc: Some(bcc),
+            ..Default::default()
+        };
+        let http_p = &bcc.request.params.http;
+        for (k, v) in &http_p.query {
+            if let Some(first) = v.first() {
+                tv.vars.insert(format!("query.{k}"), first.to_string());
+            }
+        }
+        for (k, v) in &http_p.headers {
+            if let Some(first) = v.first() {
+                tv.vars.insert(
+                    format!("header.{}", k.to_ascii_lowercase()),
+                    first.to_string(),
+                );
+            }
+        }
+        let qi = &bcc.request.q_info;
+        for (k, v) in [
+            ("hash", &qi.hash),
+            ("id", &qi.id),
+            ("qlib_id", &qi.qlib_id),
+            ("type", &qi.qtype),
+            ("write_token", &qi.write_token),
+        ] {
+            tv.vars.insert(format!("qinfo.{k}"), v.to_string());
+        }
+        tv.vars.insert("qinfo.qhot".to_string(), qi.qhot());
+        tv
+    }
+
+    /// set defines a variable, e.g. `set("query.QUERY", "fabric")` or `set("TOKEN", "...")`
+    pub fn set(mut self, name: &str, val: &str) -> TemplateVars<'a> {
+        self.vars.insert(name.to_string(), val.to_string());
+        self
+    }
+
+    /// allow restricts the variables a template may reference to `names`, as written in the template
+    pub fn allow(mut self, names: &[&str]) -> TemplateVars<'a> {
+        self.allowed = Some(names.iter().map(|n| n.to_string()).collect());
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        if let Some(v) = self.vars.get(name) {
+            return Ok(Some(v.to_string()));
+        }
+        if let Some(h) = name.strip_prefix("header.") {
+            return Ok(self
+                .vars
+                .get(&format!("header.{}", h.to_ascii_lowercase()))
+                .cloned());
+        }
+        if let Some(path) = name.strip_prefix("meta.") {
+            let bcc = match self.bcc {
+                Some(b) => b,
+                None => return Ok(None),
+            };
+            let v: Value = match bcc.sqmd_get_json(path) {
+                Ok(res) => serde_json::from_slice(&res).unwrap_or(Value::Null),
+                Err(_) => Value::Null,
+            };
+            return Ok(match v {
+                Value::Null => None,
+                Value::Object(m) if m.contains_key("op") && m.contains_key("desc") => None,
+                Value::String(s) => Some(s),
+                v => Some(v.to_string()),
+            });
+        }
+        if !name.contains('.') {
+            return Ok(self.vars.get(&format!("query.{name}")).cloned());
+        }
+        Ok(None)
+    }
+
+    /// render_str substitutes the variables of a single template string
+    /// # Arguments
+    /// * `template` : the string containing `${...}` references
+    /// * `context` : the escaping applied to variables without an explicit filter
+    pub fn render_str(
+        &self,
+        template: &str,
+        context: Escape,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("${") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after.find('}').ok_or_else(|| {
+                ErrorKinds::Invalid(format!("unterminated variable in template {template}"))
+            })?;
+            out.push_str(&self.expand(&after[..end], context)?);
+            rest = &after[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    // expands NAME[|filter][:-default]
+    fn expand(&self, expr: &str, context: Escape) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let (spec, default) = match expr.split_once(":-") {
+            Some((s, d)) => (s, Some(d)),
+            None => (expr, None),
+        };
+        let (name, escape) = match spec.split_once('|') {
+            Some((n, f)) => (
+                n.trim(),
+                Escape::from_filter(f.trim()).ok_or_else(|| {
+                    ErrorKinds::Invalid(format!("unknown template filter {f} for {n}"))
+                })?,
+            ),
+            None => (spec.trim(), context),
+        };
+        if name.is_empty() {
+            return Err(Box::new(ErrorKinds::Invalid(
+                "empty template variable".to_string(),
+            )));
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.contains(name) {
+                return Err(Box::new(ErrorKinds::Permission(format!(
+                    "template variable {name} is not allowed"
+                ))));
+            }
+        }
+        let val = match (self.lookup(name)?, default) {
+            (Some(v), _) => v,
+            (None, Some(d)) => d.to_string(),
+            (None, None) => {
+                return Err(Box::new(ErrorKinds::BadHttpParams(format!(
+                    "missing required template variable {name}"
+                ))))
+            }
+        };
+        escape.apply(name, &val)
+    }
+
+    /// render substitutes the variables throughout a JSON template.  Strings under a `url` key are rendered
+    /// in the [Escape::Url] context, those under `headers` in the [Escape::Header] context and all others
+    /// in the [Escape::Raw] context.
+    pub fn render(&self, template: &Value) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        self.render_in(template, Escape::Raw)
+    }
+
+    fn render_in(
+        &self,
+        template: &Value,
+        context: Escape,
+    ) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        Ok(match template {
+            Value::String(s) => Value::String(self.render_str(s, context)?),
+            Value::Array(a) => Value::Array(
+                a.iter()
+                    .map(|v| self.render_in(v, context))
+                    .collect::<Result<Vec<Value>, _>>()?,
+            ),
+            Value::Object(m) => {
+                let mut out = Map::new();
+                for (k, v) in m {
+                    let ctx = match k.to_ascii_lowercase().as_str() {
+                        "url" => Escape::Url,
+                        "headers" => Escape::Header,
+                        _ => context,
+                    };
+                    out.insert(k.to_string(), self.render_in(v, ctx)?);
+                }
+                Value::Object(out)
+            }
+            v => v.clone(),
+        })
+    }
+}
+
+impl<'a> BitcodeContext {
+    /// render_request_template renders the request template kept in metadata
+    /// # Arguments
+    /// * `meta_path` : the metadata path of the template e.g. `/request_parameters`
+    /// * `vars` : the variables available to the template, usually from [TemplateVars::from_request]
+    /// # Returns
+    /// the rendered template
+    pub fn render_request_template(
+        &'a self,
+        meta_path: &str,
+        vars: &TemplateVars,
+    ) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let template: Value = serde_json::from_slice(&self.sqmd_get_json(meta_path)?)?;
+        vars.render(&template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_escapes_by_context() {
+        let vars = TemplateVars::new()
+            .set("query.QUERY", "fish & chips")
+            .set("query.API_KEY", "k1")
+            .set("header.authorization", "Bearer t");
+        let t = json!({
+            "url" : "https://example.com/s?key=${API_KEY}&q=${QUERY}",
+            "method" : "GET",
+            "headers" : {"Authorization" : "${header.Authorization}"},
+            "body" : {"q" : "${QUERY}", "raw" : "{\"q\":\"${QUERY|json}\"}", "n" : 1},
+        });
+        let r = vars.render(&t).unwrap();
+        assert_eq!(
+            r["url"],
+            "https://example.com/s?key=k1&q=fish%20%26%20chips"
+        );
+        assert_eq!(r["headers"]["Authorization"], "Bearer t");
+        assert_eq!(r["body"]["q"], "fish & chips");
+        assert_eq!(r["body"]["n"], 1);
+    }
+
+    #[test]
+    fn test_render_defaults_and_errors() {
+        let vars = TemplateVars::new().set("query.QUERY", "a\r\nX-Evil: 1");
+        assert_eq!(
+            vars.render_str("${CONTEXT:-none}/${QUERY|url}", Escape::Raw)
+                .unwrap(),
+            "none/a%0D%0AX-Evil%3A%201"
+        );
+        assert!(vars.render_str("${CONTEXT}", Escape::Raw).is_err());
+        assert!(vars
+            .render(&json!({"headers" : {"X" : "${QUERY}"}}))
+            .is_err());
+        assert!(vars.render_str("${QUERY|bogus}", Escape::Raw).is_err());
+        assert!(vars.render_str("${QUERY", Escape::Raw).is_err());
+        let vars = vars.allow(&["CONTEXT"]);
+        assert!(vars.render_str("${QUERY:-x}", Escape::Raw).is_err());
+        assert_eq!(vars.render_str("${CONTEXT:-c}", Escape::Raw).unwrap(), "c");
+        assert_eq!(
+            Escape::Json.apply("q", "say \"hi\"").unwrap(),
+            "say \\\"hi\\\""
+        );
+    }
+}
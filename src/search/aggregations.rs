@@ -0,0 +1,649 @@
+//! Facets and field statistics <br>
+//! [Aggregation]s ride along with a [SearchRequest] and come back, keyed by name, in
+//! [SearchResults::aggregations](crate::search::SearchResults), ready for building filter sidebars.  They are
+//! sent to the host in Tantivy's aggregation request format; should the host return none, they are computed
+//! from the stored values of the returned hits instead.
+//! ```rust
+//! use elvwasm::search::{Aggregation, SchemaBuilder, SearchRequest, Searcher, TextOptions, NumericOptions};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let schema = SchemaBuilder::new(bcc)
+//!     .text("title", TextOptions::text().stored())
+//!     .text("asset_type", TextOptions::string().stored().fast())
+//!     .date("release_date", NumericOptions::indexed().stored().fast())
+//!     .facet("genre", true)
+//!     .build()?;
+//!   let req = SearchRequest::new("sea")
+//!     .aggregate("types", Aggregation::terms("asset_type", 10))
+//!     .aggregate("by_year", Aggregation::date_histogram("release_date", 365 * 86400))
+//!     .aggregate("genres", Aggregation::facet("genre", "/"))
+//!     .with_field_stats(&schema);
+//!   let results = Searcher::new(bcc, &schema)?.search(&req)?;
+//!   Ok(serde_json::to_vec(&results.aggregations)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{parse_date, FieldEntry, FieldType, Hit, Schema};
+use crate::ErrorKinds;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Number of values a terms aggregation returns when no size is given
+pub const DEFAULT_TERMS_SIZE: usize = 10;
+
+/// Seconds per bucket of a date histogram when no interval is given
+pub const DEFAULT_DATE_INTERVAL_SECS: i64 = 86400;
+
+/// One bucket of a range aggregation, `from` inclusive and `to` exclusive
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RangeSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<f64>,
+}
+
+impl RangeSpec {
+    fn contains(&self, v: f64) -> bool {
+        self.from.map_or(true, |f| v >= f) && self.to.map_or(true, |t| v < t)
+    }
+
+    fn key(&self) -> String {
+        match &self.key {
+            Some(k) => k.clone(),
+            None => format!(
+                "{}-{}",
+                self.from
+                    .map(|f| f.to_string())
+                    .unwrap_or_else(|| "*".to_string()),
+                self.to
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| "*".to_string())
+            ),
+        }
+    }
+}
+
+/// An aggregation over the documents matching a query
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// the most frequent values of a field
+    Terms { field: String, size: usize },
+    /// document counts per numeric range
+    Range {
+        field: String,
+        ranges: Vec<RangeSpec>,
+    },
+    /// document counts per numeric bucket of the given width
+    Histogram { field: String, interval: f64 },
+    /// document counts per date bucket of the given number of seconds
+    DateHistogram { field: String, interval_secs: i64 },
+    /// count, min, max, average and sum of a numeric or date field
+    Stats { field: String },
+    /// document counts per child of a facet path
+    Facet { field: String, path: String },
+}
+
+impl Aggregation {
+    pub fn terms(field: &str, size: usize) -> Aggregation {
+        Aggregation::Terms {
+            field: field.to_string(),
+            size,
+        }
+    }
+
+    pub fn range(field: &str, ranges: Vec<RangeSpec>) -> Aggregation {
+        Aggregation::Range {
+            field: field.to_string(),
+            ranges,
+        }
+    }
+
+    pub fn histogram(field: &str, interval: f64) -> Aggregation {
+        Aggregation::Histogram {
+            field: field.to_string(),
+            interval,
+        }
+    }
+
+    pub fn date_histogram(field: &str, interval_secs: i64) -> Aggregation {
+        Aggregation::DateHistogram {
+            field: field.to_string(),
+            interval_secs,
+        }
+    }
+
+    pub fn stats(field: &str) -> Aggregation {
+        Aggregation::Stats {
+            field: field.to_string(),
+        }
+    }
+
+    pub fn facet(field: &str, path: &str) -> Aggregation {
+        Aggregation::Facet {
+            field: field.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        match self {
+            Aggregation::Terms { field, .. }
+            | Aggregation::Range { field, .. }
+            | Aggregation::Histogram { field, .. }
+            | Aggregation::DateHistogram { field, .. }
+            | Aggregation::Stats { field }
+            | Aggregation::Facet { field, .. } => field,
+        }
+    }
+
+    /// for_field returns the aggregations the field's [FieldStats](crate::search::FieldStats) ask for, named
+    /// after the field, with a `.stats` suffix for simple statistics
+    /// * string and text fields get value counts
+    /// * facet fields get counts per top level facet
+    /// * numeric and date fields get histograms and simple statistics
+    pub fn for_field(fe: &FieldEntry) -> Vec<(String, Aggregation)> {
+        let stats = match &fe.stats {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        let name = fe.name.as_str();
+        let mut aggs = Vec::new();
+        match &fe.field_type {
+            FieldType::Text(_) | FieldType::Bool(_) if stats.histogram => aggs.push((
+                name.to_string(),
+                Aggregation::terms(name, stats.size.unwrap_or(DEFAULT_TERMS_SIZE)),
+            )),
+            FieldType::Facet { .. } if stats.histogram => {
+                aggs.push((name.to_string(), Aggregation::facet(name, "/")))
+            }
+            FieldType::U64(_) | FieldType::I64(_) | FieldType::F64(_) => {
+                if stats.histogram {
+                    aggs.push((
+                        name.to_string(),
+                        Aggregation::histogram(name, stats.interval.unwrap_or(1.0)),
+                    ));
+                }
+                if stats.simple {
+                    aggs.push((format!("{name}.stats"), Aggregation::stats(name)));
+                }
+            }
+            FieldType::Date(_) => {
+                if stats.histogram {
+                    let secs = stats
+                        .interval
+                        .map(|i| i as i64)
+                        .unwrap_or(DEFAULT_DATE_INTERVAL_SECS);
+                    aggs.push((name.to_string(), Aggregation::date_histogram(name, secs)));
+                }
+                if stats.simple {
+                    aggs.push((format!("{name}.stats"), Aggregation::stats(name)));
+                }
+            }
+            _ => {}
+        }
+        aggs
+    }
+
+    /// to_host renders the aggregation in Tantivy's aggregation request format; facet counts, which Tantivy
+    /// gathers with a collector instead, are rendered as `{"facet" : {"field" : .., "path" : ..}}`
+    pub fn to_host(&self) -> Value {
+        match self {
+            Aggregation::Terms { field, size } => {
+                json!({"terms" : {"field" : field, "size" : size}})
+            }
+            Aggregation::Range { field, ranges } => {
+                json!({"range" : {"field" : field, "ranges" : ranges}})
+            }
+            Aggregation::Histogram { field, interval } => {
+                json!({"histogram" : {"field" : field, "interval" : interval}})
+            }
+            Aggregation::DateHistogram {
+                field,
+                interval_secs,
+            } => {
+                json!({"date_histogram" : {"field" : field, "fixed_interval" : format!("{interval_secs}s")}})
+            }
+            Aggregation::Stats { field } => json!({"stats" : {"field" : field}}),
+            Aggregation::Facet { field, path } => {
+                json!({"facet" : {"field" : field, "path" : path}})
+            }
+        }
+    }
+
+    /// compute computes the aggregation over the stored values of the hits
+    pub fn compute(&self, hits: &[Hit]) -> AggregationResult {
+        let values = |hit: &'_ Hit| -> Vec<Value> {
+            match hit.fields.get(self.field()) {
+                Some(Value::Array(a)) => a.clone(),
+                Some(v) => vec![v.clone()],
+                None => Vec::new(),
+            }
+        };
+        let numbers = |hit: &'_ Hit| -> Vec<f64> {
+            values(hit)
+                .iter()
+                .filter_map(|v| {
+                    v.as_f64().or_else(|| {
+                        let s = v.as_str()?;
+                        s.parse().ok().or_else(|| parse_date(s).map(|d| d as f64))
+                    })
+                })
+                .collect()
+        };
+        // counts every document once per distinct key
+        let count_keys = |key_of: &dyn Fn(&Hit) -> Vec<String>| -> BTreeMap<String, u64> {
+            let mut counts = BTreeMap::new();
+            for hit in hits {
+                let mut keys = key_of(hit);
+                keys.sort();
+                keys.dedup();
+                for k in keys {
+                    *counts.entry(k).or_insert(0) += 1;
+                }
+            }
+            counts
+        };
+        match self {
+            Aggregation::Terms { size, .. } => {
+                let counts = count_keys(&|h| {
+                    values(h)
+                        .iter()
+                        .map(|v| match v {
+                            Value::String(s) => s.clone(),
+                            _ => v.to_string(),
+                        })
+                        .collect()
+                });
+                let mut buckets: Vec<Bucket> = counts
+                    .into_iter()
+                    .map(|(k, n)| Bucket::new(json!(k), n))
+                    .collect();
+                buckets.sort_by_key(|b| std::cmp::Reverse(b.doc_count));
+                buckets.truncate(*size);
+                AggregationResult::Buckets(buckets)
+            }
+            Aggregation::Facet { path, .. } => {
+                let prefix = if path.ends_with('/') {
+                    path.clone()
+                } else {
+                    format!("{path}/")
+                };
+                let counts = count_keys(&|h| {
+                    values(h)
+                        .iter()
+                        .filter_map(|v| {
+                            let child = v.as_str()?.strip_prefix(&prefix)?.split('/').next()?;
+                            (!child.is_empty()).then(|| format!("{prefix}{child}"))
+                        })
+                        .collect()
+                });
+                let mut buckets: Vec<Bucket> = counts
+                    .into_iter()
+                    .map(|(k, n)| Bucket::new(json!(k), n))
+                    .collect();
+                buckets.sort_by_key(|b| std::cmp::Reverse(b.doc_count));
+                AggregationResult::Buckets(buckets)
+            }
+            Aggregation::Range { ranges, .. } => AggregationResult::Buckets(
+                ranges
+                    .iter()
+                    .map(|r| Bucket {
+                        from: r.from,
+                        to: r.to,
+                        ..Bucket::new(
+                            json!(r.key()),
+                            hits.iter()
+                                .filter(|h| numbers(h).iter().any(|v| r.contains(*v)))
+                                .count() as u64,
+                        )
+                    })
+                    .collect(),
+            ),
+            Aggregation::Histogram { interval, .. } => {
+                let interval = if *interval > 0.0 { *interval } else { 1.0 };
+                let counts = count_keys(&|h| {
+                    numbers(h)
+                        .iter()
+                        .map(|v| ((v / interval).floor() * interval).to_string())
+                        .collect()
+                });
+                let mut buckets: Vec<Bucket> = counts
+                    .into_iter()
+                    .filter_map(|(k, n)| Some(Bucket::new(json!(k.parse::<f64>().ok()?), n)))
+                    .collect();
+                buckets.sort_by(|a, b| compare_keys(&a.key, &b.key));
+                AggregationResult::Buckets(buckets)
+            }
+            Aggregation::DateHistogram { interval_secs, .. } => {
+                let interval = (*interval_secs).max(1);
+                let counts = count_keys(&|h| {
+                    numbers(h)
+                        .iter()
+                        .map(|v| ((*v as i64).div_euclid(interval) * interval).to_string())
+                        .collect()
+                });
+                let mut buckets: Vec<Bucket> = counts
+                    .into_iter()
+                    .filter_map(|(k, n)| Some(Bucket::new(json!(k.parse::<i64>().ok()?), n)))
+                    .collect();
+                buckets.sort_by(|a, b| compare_keys(&a.key, &b.key));
+                AggregationResult::Buckets(buckets)
+            }
+            Aggregation::Stats { .. } => {
+                let all: Vec<f64> = hits.iter().flat_map(numbers).collect();
+                let sum: f64 = all.iter().sum();
+                AggregationResult::Stats(Stats {
+                    count: all.len() as u64,
+                    min: all.iter().cloned().reduce(f64::min),
+                    max: all.iter().cloned().reduce(f64::max),
+                    avg: (!all.is_empty()).then(|| sum / all.len() as f64),
+                    sum,
+                })
+            }
+        }
+    }
+}
+
+fn compare_keys(a: &Value, b: &Value) -> std::cmp::Ordering {
+    a.as_f64()
+        .partial_cmp(&b.as_f64())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// A group of documents sharing a value or falling in a range
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bucket {
+    /// the value, facet path, range key or bucket start (seconds since the epoch for dates)
+    pub key: Value,
+    pub doc_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<f64>,
+}
+
+impl Bucket {
+    fn new(key: Value, doc_count: u64) -> Bucket {
+        Bucket {
+            key,
+            doc_count,
+            from: None,
+            to: None,
+        }
+    }
+}
+
+/// Simple statistics of a numeric or date field, min, max and avg being absent when no value was seen
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub sum: f64,
+}
+
+/// The outcome of an [Aggregation]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum AggregationResult {
+    Buckets(Vec<Bucket>),
+    Stats(Stats),
+}
+
+impl AggregationResult {
+    /// from_host decodes a Tantivy aggregation result, whose buckets come either as an array or as an
+    /// object keyed by bucket key.  Date histogram buckets are keyed by their `key_as_string`, converted
+    /// to seconds since the epoch.
+    pub fn from_host(
+        agg: &Aggregation,
+        v: &Value,
+    ) -> Result<AggregationResult, Box<dyn Error + Send + Sync>> {
+        let invalid = || -> Box<dyn Error + Send + Sync> {
+            Box::new(ErrorKinds::BadHttpParams(format!(
+                "unexpected aggregation result {v}"
+            )))
+        };
+        if let Aggregation::Stats { .. } = agg {
+            return Ok(AggregationResult::Stats(
+                serde_json::from_value(v.clone()).map_err(|_| invalid())?,
+            ));
+        }
+        let bucket =
+            |key: Option<&str>, b: &Value| -> Result<Bucket, Box<dyn Error + Send + Sync>> {
+                let mut key = match key {
+                    Some(k) => json!(k),
+                    None => b.get("key").cloned().ok_or_else(invalid)?,
+                };
+                if let Aggregation::DateHistogram { .. } = agg {
+                    if let Some(secs) = b["key_as_string"].as_str().and_then(parse_date) {
+                        key = json!(secs);
+                    }
+                }
+                Ok(Bucket {
+                    key,
+                    doc_count: b["doc_count"].as_u64().ok_or_else(invalid)?,
+                    from: b["from"].as_f64(),
+                    to: b["to"].as_f64(),
+                })
+            };
+        let buckets = match v.get("buckets") {
+            Some(Value::Array(a)) => a
+                .iter()
+                .map(|b| bucket(None, b))
+                .collect::<Result<Vec<Bucket>, _>>()?,
+            Some(Value::Object(o)) => o
+                .iter()
+                .map(|(k, b)| bucket(Some(k), b))
+                .collect::<Result<Vec<Bucket>, _>>()?,
+            _ => return Err(invalid()),
+        };
+        Ok(AggregationResult::Buckets(buckets))
+    }
+
+    pub fn buckets(&self) -> &[Bucket] {
+        match self {
+            AggregationResult::Buckets(b) => b,
+            AggregationResult::Stats(_) => &[],
+        }
+    }
+
+    /// merge adds the result of the same aggregation over other documents, such as another index shard.
+    /// Buckets are summed by key, terms buckets being ordered by count again and cut to the aggregation size.
+    pub fn merge(&mut self, agg: &Aggregation, other: &AggregationResult) {
+        match (self, other) {
+            (AggregationResult::Buckets(mine), AggregationResult::Buckets(theirs)) => {
+                for b in theirs {
+                    match mine.iter_mut().find(|m| m.key == b.key) {
+                        Some(m) => m.doc_count += b.doc_count,
+                        None => mine.push(b.clone()),
+                    }
+                }
+                if let Aggregation::Terms { size, .. } = agg {
+                    mine.sort_by_key(|b| std::cmp::Reverse(b.doc_count));
+                    mine.truncate(*size);
+                }
+            }
+            (AggregationResult::Stats(mine), AggregationResult::Stats(theirs)) => {
+                let pick = |a: Option<f64>, b: Option<f64>, f: fn(f64, f64) -> f64| match (a, b) {
+                    (Some(x), Some(y)) => Some(f(x, y)),
+                    (x, y) => x.or(y),
+                };
+                mine.min = pick(mine.min, theirs.min, f64::min);
+                mine.max = pick(mine.max, theirs.max, f64::max);
+                mine.count += theirs.count;
+                mine.sum += theirs.sum;
+                mine.avg = if mine.count > 0 {
+                    Some(mine.sum / mine.count as f64)
+                } else {
+                    None
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// to_host renders named aggregations as a Tantivy aggregation request
+pub fn aggregations_to_host(aggs: &BTreeMap<String, Aggregation>) -> Value {
+    Value::Object(
+        aggs.iter()
+            .map(|(name, agg)| (name.clone(), agg.to_host()))
+            .collect::<Map<String, Value>>(),
+    )
+}
+
+/// aggregations_from_host decodes the results of named aggregations, computing any the host left out from
+/// the hits
+pub fn aggregations_from_host(
+    aggs: &BTreeMap<String, Aggregation>,
+    v: Option<&Value>,
+    hits: &[Hit],
+) -> Result<BTreeMap<String, AggregationResult>, Box<dyn Error + Send + Sync>> {
+    aggs.iter()
+        .map(|(name, agg)| {
+            let res = match v.and_then(|v| v.get(name)) {
+                Some(r) => AggregationResult::from_host(agg, r)?,
+                None => agg.compute(hits),
+            };
+            Ok((name.clone(), res))
+        })
+        .collect()
+}
+
+/// field_stats_aggregations returns the aggregations asked for by every field of the schema
+pub fn field_stats_aggregations(schema: &Schema) -> Vec<(String, Aggregation)> {
+    schema
+        .fields()
+        .flat_map(|(fe, _)| Aggregation::for_field(fe))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{FieldStats, NumericOptions, TextOptions};
+
+    fn hit(fields: Value) -> Hit {
+        Hit {
+            score: 1.0,
+            address: None,
+            part: None,
+            fields: fields.as_object().unwrap().clone(),
+            snippets: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let hits = vec![
+            hit(
+                json!({"type" : ["movie"], "year" : [1999], "genre" : ["/drama/crime", "/comedy"], "date" : ["2023-01-02"]}),
+            ),
+            hit(
+                json!({"type" : ["movie", "movie"], "year" : [2005], "genre" : ["/drama"], "date" : [1672531200]}),
+            ),
+            hit(json!({"type" : ["episode"], "year" : [2011]})),
+        ];
+        let terms = Aggregation::terms("type", 10).compute(&hits);
+        assert_eq!(
+            terms.buckets(),
+            &[
+                Bucket::new(json!("movie"), 2),
+                Bucket::new(json!("episode"), 1)
+            ]
+        );
+        let facets = Aggregation::facet("genre", "/").compute(&hits);
+        assert_eq!(facets.buckets()[0], Bucket::new(json!("/drama"), 2));
+        let ranges = Aggregation::range(
+            "year",
+            vec![
+                RangeSpec {
+                    to: Some(2000.0),
+                    ..Default::default()
+                },
+                RangeSpec {
+                    key: Some("recent".to_string()),
+                    from: Some(2000.0),
+                    ..Default::default()
+                },
+            ],
+        )
+        .compute(&hits);
+        assert_eq!(ranges.buckets()[0].key, json!("*-2000"));
+        assert_eq!(ranges.buckets()[1].doc_count, 2);
+        let hist = Aggregation::histogram("year", 10.0).compute(&hits);
+        assert_eq!(hist.buckets().len(), 3);
+        assert_eq!(hist.buckets()[0].key, json!(1990.0));
+        let dates = Aggregation::date_histogram("date", 7 * 86400).compute(&hits);
+        assert_eq!(dates.buckets().len(), 1);
+        assert_eq!(dates.buckets()[0].doc_count, 2);
+        match Aggregation::stats("year").compute(&hits) {
+            AggregationResult::Stats(s) => {
+                assert_eq!(
+                    (s.count, s.min, s.max, s.avg),
+                    (3, Some(1999.0), Some(2011.0), Some(2005.0))
+                )
+            }
+            r => panic!("unexpected {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_host_and_field_stats() {
+        let agg = Aggregation::date_histogram("date", 86400);
+        let res = AggregationResult::from_host(
+            &agg,
+            &json!({"buckets" : [{"key" : 1672531200000.0, "key_as_string" : "2023-01-01T00:00:00Z", "doc_count" : 4}]}),
+        )
+        .unwrap();
+        assert_eq!(res.buckets(), &[Bucket::new(json!(1672531200), 4)]);
+        let stats = AggregationResult::from_host(
+            &Aggregation::stats("year"),
+            &json!({"count" : 0, "min" : null, "max" : null, "avg" : null, "sum" : 0.0}),
+        )
+        .unwrap();
+        assert_eq!(stats, AggregationResult::Stats(Stats::default()));
+        assert_eq!(
+            agg.to_host(),
+            json!({"date_histogram" : {"field" : "date", "fixed_interval" : "86400s"}})
+        );
+
+        let fe = FieldEntry::new("asset_type", FieldType::Text(TextOptions::string())).with_stats(
+            FieldStats {
+                histogram: true,
+                ..Default::default()
+            },
+        );
+        assert!(fe.field_type.is_fast());
+        assert_eq!(
+            Aggregation::for_field(&fe),
+            vec![(
+                "asset_type".to_string(),
+                Aggregation::terms("asset_type", 10)
+            )]
+        );
+        let fe = FieldEntry::new("year", FieldType::U64(NumericOptions::indexed())).with_stats(
+            FieldStats {
+                simple: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            Aggregation::for_field(&fe),
+            vec![("year.stats".to_string(), Aggregation::stats("year"))]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Configurable text analyzers <br>
+//! An [Analyzer] is the pipeline turning the text of a field into terms: a [Tokenizer] followed by the
+//! lowercasing, ASCII folding, stop word, stemming and synonym filters. It is registered with the host under
+//! its name when the schema is built, and the query parser analyzes the query text of a field with the
+//! analyzer the field was indexed with, so that both sides agree on the terms. Analyzers are set with
+//! [TextOptions::analyzer](crate::search::TextOptions::analyzer) or the `analyzer` option of an indexer
+//! configuration field.
+//! ```rust
+//! use elvwasm::search::{Analyzer, Language, SchemaBuilder, TextOptions, Tokenizer};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   bcc.new_index_builder(serde_json::json!({}))?;
+//!   let english = Analyzer::new("english", Tokenizer::Simple)
+//!     .ascii_folding()
+//!     .stop_words_for(Language::English)
+//!     .stemmer(Language::English)
+//!     .synonym("movie", &["film"]);
+//!   let schema = SchemaBuilder::new(bcc)
+//!     .text("title", TextOptions::text().analyzer(english).stored())
+//!     .build()?;
+//!   Ok(schema.field("title").unwrap_or_default().to_string().into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::ErrorKinds;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Names of the tokenizers the host provides, which analyzers may not redefine
+pub const BUILTIN_TOKENIZERS: &[&str] = &["default", "raw", "en_stem", "whitespace"];
+
+/// How text is split into tokens
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Tokenizer {
+    /// splits on any character that is not alphanumeric
+    Simple,
+    /// splits on whitespace only
+    Whitespace,
+    /// the whole text is a single token
+    Raw,
+    /// every substring of `min_gram` to `max_gram` characters, or only the prefixes when `prefix_only`
+    Ngram {
+        min_gram: usize,
+        max_gram: usize,
+        #[serde(default)]
+        prefix_only: bool,
+    },
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Tokenizer::Simple
+    }
+}
+
+/// The languages stemmers and stop word lists are available for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    Arabic,
+    Danish,
+    Dutch,
+    English,
+    Finnish,
+    French,
+    German,
+    Greek,
+    Hungarian,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Romanian,
+    Russian,
+    Spanish,
+    Swedish,
+    Tamil,
+    Turkish,
+}
+
+/// The stop words removed by an [Analyzer], either the host's list for a language or an explicit list
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum StopWords {
+    Language(Language),
+    List(Vec<String>),
+}
+
+/// A named text analysis pipeline, see the [module documentation](self)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Analyzer {
+    /// the name the analyzer is registered under, the field name when configured without one
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub tokenizer: Tokenizer,
+    #[serde(default = "default_true")]
+    pub lowercase: bool,
+    /// folds accented and other non ASCII letters to their ASCII equivalent
+    #[serde(default)]
+    pub ascii_folding: bool,
+    /// drops tokens longer than this many bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remove_long: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_words: Option<StopWords>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stemmer: Option<Language>,
+    /// each term is also indexed and searched as its synonyms
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub synonyms: BTreeMap<String, Vec<String>>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Analyzer {
+    /// new creates an analyzer lowercasing the tokens of `tokenizer` and applying no other filter
+    pub fn new(name: &str, tokenizer: Tokenizer) -> Analyzer {
+        Analyzer {
+            name: name.to_string(),
+            tokenizer,
+            lowercase: true,
+            ascii_folding: false,
+            remove_long: None,
+            stop_words: None,
+            stemmer: None,
+            synonyms: BTreeMap::new(),
+        }
+    }
+
+    /// from_config parses the `analyzer` option of an indexer configuration field, named after the field
+    /// unless it names itself
+    /// ```rust
+    /// use elvwasm::search::{Analyzer, Language, StopWords, Tokenizer};
+    /// let a = Analyzer::from_config("title", &serde_json::json!({
+    ///   "tokenizer" : {"type" : "whitespace"},
+    ///   "stemmer" : "french",
+    ///   "stop_words" : ["le", "la"],
+    /// })).unwrap();
+    /// assert_eq!(a.name, "title");
+    /// assert_eq!(a.tokenizer, Tokenizer::Whitespace);
+    /// assert_eq!(a.stemmer, Some(Language::French));
+    /// assert_eq!(a.stop_words, Some(StopWords::List(vec!["le".to_string(), "la".to_string()])));
+    /// ```
+    pub fn from_config(field: &str, v: &Value) -> Result<Analyzer, Box<dyn Error + Send + Sync>> {
+        let mut analyzer: Analyzer = serde_json::from_value(v.clone())
+            .map_err(|e| ErrorKinds::Invalid(format!("bad analyzer for field {field}: {e}")))?;
+        if analyzer.name.is_empty() {
+            analyzer.name = field.to_string();
+        }
+        analyzer.validate()?;
+        Ok(analyzer)
+    }
+
+    pub fn lowercase(mut self, lowercase: bool) -> Analyzer {
+        self.lowercase = lowercase;
+        self
+    }
+
+    pub fn ascii_folding(mut self) -> Analyzer {
+        self.ascii_folding = true;
+        self
+    }
+
+    pub fn remove_long(mut self, max_bytes: usize) -> Analyzer {
+        self.remove_long = Some(max_bytes);
+        self
+    }
+
+    /// stop_words removes the given words, compared after lowercasing and folding
+    pub fn stop_words(mut self, words: &[&str]) -> Analyzer {
+        self.stop_words = Some(StopWords::List(
+            words.iter().map(|w| w.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// stop_words_for removes the host's stop words of a language
+    pub fn stop_words_for(mut self, language: Language) -> Analyzer {
+        self.stop_words = Some(StopWords::Language(language));
+        self
+    }
+
+    pub fn stemmer(mut self, language: Language) -> Analyzer {
+        self.stemmer = Some(language);
+        self
+    }
+
+    /// synonym adds synonyms of a term, accumulating over calls
+    pub fn synonym(mut self, term: &str, synonyms: &[&str]) -> Analyzer {
+        let entry = self.synonyms.entry(term.to_string()).or_default();
+        for s in synonyms {
+            if !entry.iter().any(|e| e == s) {
+                entry.push(s.to_string());
+            }
+        }
+        self
+    }
+
+    /// validate checks the analyzer can be registered with the host
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let invalid = |msg: String| -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err(Box::new(ErrorKinds::Invalid(msg)))
+        };
+        if self.name.is_empty() {
+            return invalid("analyzer has no name".to_string());
+        }
+        if BUILTIN_TOKENIZERS.contains(&self.name.as_str()) {
+            return invalid(format!(
+                "analyzer {} would replace a builtin tokenizer",
+                self.name
+            ));
+        }
+        if let Tokenizer::Ngram {
+            min_gram, max_gram, ..
+        } = self.tokenizer
+        {
+            if min_gram == 0 || min_gram > max_gram {
+                return invalid(format!(
+                    "analyzer {} has bad ngram bounds {min_gram}..{max_gram}",
+                    self.name
+                ));
+            }
+        }
+        if self.remove_long == Some(0) {
+            return invalid(format!("analyzer {} removes every token", self.name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_analyzer_config() {
+        let a = Analyzer::from_config(
+            "title",
+            &json!({
+                "name" : "edge",
+                "tokenizer" : {"type" : "ngram", "min_gram" : 2, "max_gram" : 5, "prefix_only" : true},
+                "ascii_folding" : true,
+                "stop_words" : "english",
+                "synonyms" : {"movie" : ["film"]},
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            a,
+            Analyzer::new(
+                "edge",
+                Tokenizer::Ngram {
+                    min_gram: 2,
+                    max_gram: 5,
+                    prefix_only: true
+                }
+            )
+            .ascii_folding()
+            .stop_words_for(Language::English)
+            .synonym("movie", &["film"])
+        );
+        assert_eq!(
+            serde_json::to_value(&a).unwrap()["stop_words"],
+            json!("english")
+        );
+        let a = Analyzer::from_config("body", &json!({})).unwrap();
+        assert_eq!(a, Analyzer::new("body", Tokenizer::Simple));
+        assert!(Analyzer::from_config("body", &json!({"stemmer" : "klingon"})).is_err());
+        assert!(Analyzer::from_config("body", &json!({"name" : "raw"})).is_err());
+        assert!(Analyzer::from_config(
+            "body",
+            &json!({"tokenizer" : {"type" : "ngram", "min_gram" : 3, "max_gram" : 2}})
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Index configuration <br>
+//! The `/indexer/config` metadata of an index object names the content to crawl, the paths of its metadata
+//! the policy allows and the fields to extract from each document. [IndexerConfig::parse_index_config]
+//! reads it into typed form.
+//! ```rust
+//! use elvwasm::search::IndexerConfig;
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let names: Vec<&str> = config.fields.iter().map(|f| f.name.as_str()).collect();
+//!   Ok(names.join(",").into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::{BitcodeContext, ErrorKinds};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+/// Metadata path of the index config of an index object
+pub const INDEX_CONFIG_PATH: &str = "/indexer/config";
+
+/// The content object a crawl starts from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RootConfig {
+    pub content: String,
+    pub library: String,
+}
+
+/// The metadata paths of the root content that may be crawled, `*` and `[*]` matching any object key and
+/// array item
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FabricPolicy {
+    pub paths: Vec<String>,
+    /// link hops followed from the root, [DEFAULT_CRAWL_DEPTH](crate::search::DEFAULT_CRAWL_DEPTH) if unset
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FabricConfig {
+    pub policy: FabricPolicy,
+    pub root: RootConfig,
+}
+
+/// Where the documents are found in the metadata of the objects linked from the root
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FabricDocument {
+    pub prefix: String,
+}
+
+/// A field of the index, its values being extracted from the metadata found at `paths`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    /// the options [FieldEntry::from_config](crate::search::FieldEntry::from_config) understands
+    pub options: Value,
+    /// dotted paths such as `site_map.searchables.*.asset_metadata.title`
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IndexerConfig {
+    #[serde(rename = "type")]
+    pub indexer_type: String,
+    pub document: FabricDocument,
+    pub fields: Vec<FieldConfig>,
+    pub fabric: FabricConfig,
+}
+
+impl IndexerConfig {
+    /**
+     * Given a JSON index config, `{"indexer" : {"type", "arguments" : {"document", "fields"}}, "fabric"}`,
+     * returns an IndexerConfig with proper fields filled out.
+     */
+    pub fn parse_index_config(
+        config_value: &Value,
+    ) -> Result<IndexerConfig, Box<dyn Error + Send + Sync>> {
+        let indexer_config_val: &Value = &config_value["indexer"];
+        let fabric_config_val: &Value = &config_value["fabric"];
+        let indexer_arguments_val = &indexer_config_val["arguments"];
+        let flds = match indexer_arguments_val["fields"].as_object() {
+            Some(x) => x,
+            None => {
+                return Err(
+                    ErrorKinds::NotExist("fields is not available in index".to_string()).into(),
+                )
+            }
+        };
+        let mut field_configs: Vec<FieldConfig> = Vec::new();
+        for (field_name, field_value) in flds {
+            field_configs.push(FieldConfig {
+                name: field_name.to_string(),
+                options: field_value["options"].clone(),
+                field_type: serde_json::from_value(field_value["type"].clone())?,
+                paths: serde_json::from_value(field_value["paths"].clone())?,
+            });
+        }
+        Ok(IndexerConfig {
+            fabric: serde_json::from_value(fabric_config_val.clone())?,
+            indexer_type: serde_json::from_value(indexer_config_val["type"].clone())?,
+            document: serde_json::from_value(indexer_arguments_val["document"].clone())?,
+            fields: field_configs,
+        })
+    }
+
+    /// from_meta reads the config from the metadata of the content at [INDEX_CONFIG_PATH]
+    pub fn from_meta(bcc: &BitcodeContext) -> Result<IndexerConfig, Box<dyn Error + Send + Sync>> {
+        let res = bcc.sqmd_get_json(INDEX_CONFIG_PATH)?;
+        let config: Value = serde_json::from_slice(&res)?;
+        IndexerConfig::parse_index_config(&config)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldConfig> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_index_config() {
+        let config = json!({
+            "indexer" : {
+                "type" : "metadata-text",
+                "arguments" : {
+                    "document" : {"prefix" : "/"},
+                    "fields" : {
+                        "title" : {
+                            "type" : "text",
+                            "options" : {"stats" : {"histogram" : true}},
+                            "paths" : ["site_map.searchables.*.asset_metadata.title"],
+                        },
+                    },
+                },
+            },
+            "fabric" : {
+                "root" : {"content" : "iq__abc", "library" : "ilib123"},
+                "policy" : {"paths" : ["/offerings/*"]},
+            },
+        });
+        let ic = IndexerConfig::parse_index_config(&config).unwrap();
+        assert_eq!("metadata-text", ic.indexer_type);
+        assert_eq!("/", ic.document.prefix);
+        assert_eq!("iq__abc", ic.fabric.root.content);
+        assert_eq!(None, ic.fabric.policy.max_depth);
+        let title = ic.field("title").unwrap();
+        assert_eq!("text", title.field_type);
+        assert_eq!(json!({"stats" : {"histogram" : true}}), title.options);
+        assert!(IndexerConfig::parse_index_config(&json!({"indexer" : {}})).is_err());
+    }
+}
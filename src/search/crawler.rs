@@ -0,0 +1,432 @@
+//! Metadata crawler <br>
+//! A [Crawler] walks the metadata of the root content of an [IndexerConfig] under the paths its policy
+//! allows, following the fabric links found there to the objects making up the documents of the index.
+//! ```rust
+//! use elvwasm::search::{Crawler, IndexerConfig};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let crawled = Crawler::new(bcc, "").crawl(&config)?;
+//!   Ok(serde_json::to_vec(&crawled.stats)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{IndexerConfig, Prefix};
+use crate::{BitcodeContext, ErrorKinds, QRef};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+
+/// Link hops followed from the root content when the policy sets no `max_depth`
+pub const DEFAULT_CRAWL_DEPTH: usize = 3;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Crawls the content named by an [IndexerConfig], following the links found under its policy paths
+pub struct Crawler<'a> {
+    bcc: &'a BitcodeContext,
+    inception_url: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CrawlResult {
+    /// content hash to `{"id", "hash", "qlib_id", "depth", "links" : [concrete paths linking to it], "meta"}`
+    /// for every object crawled; the root's `meta` holds the values matched by the policy paths, keyed by
+    /// their concrete path
+    pub mdregistry: Map<String, Value>,
+    pub stats: Map<String, Value>,
+    pub sorted_ids: Vec<String>,
+    pub sorted_hashes: Vec<String>,
+}
+
+// a link found in the metadata of object `from`, to be followed
+struct Link {
+    path: String,
+    hash: String,
+    from: String,
+    depth: usize,
+}
+
+// the objects and counters of a crawl in progress
+#[derive(Default)]
+struct CrawlState {
+    library: String,
+    prefix: String,
+    max_depth: usize,
+    registry: BTreeMap<String, Map<String, Value>>,
+    // the object each crawled object was first reached from, which makes up its chain of links
+    parents: BTreeMap<String, String>,
+    // links found and not yet followed, nearest first
+    pending: VecDeque<Link>,
+    values: u64,
+    links: u64,
+    cycles: u64,
+    depth_limited: u64,
+    errors: u64,
+    deepest: usize,
+}
+
+impl<'a> Crawler<'a> {
+    pub fn new(bcc: &'a BitcodeContext, iurl: &str) -> Crawler<'a> {
+        Crawler {
+            bcc,
+            inception_url: iurl.to_string(),
+        }
+    }
+
+    pub fn inception_url(&self) -> &str {
+        &self.inception_url
+    }
+
+    /**
+     * Crawls the root content of the config: the metadata under each policy path is walked and
+     * every fabric link to another object found there is followed, the metadata of the linked object at
+     * the document prefix being registered and walked in turn, up to the policy's max_depth link hops.
+     * Links are followed breadth first, so every object is registered at the fewest hops it can be reached
+     * in whatever order links appear, and its metadata is read from its own library.  Links back to an
+     * object on the chain of links leading to the linking object are counted as cycles and not followed.
+     */
+    pub fn crawl(
+        &self,
+        config: &IndexerConfig,
+    ) -> Result<CrawlResult, Box<dyn Error + Send + Sync>> {
+        let root = &config.fabric.root;
+        let root_hash = self.latest_hash(&root.content)?;
+        let mut state = CrawlState {
+            library: root.library.clone(),
+            prefix: config.document.prefix.clone(),
+            max_depth: config
+                .fabric
+                .policy
+                .max_depth
+                .unwrap_or(DEFAULT_CRAWL_DEPTH),
+            ..Default::default()
+        };
+        state.registry.insert(
+            root_hash.clone(),
+            registry_entry(&root.content, &root_hash, &root.library, 0),
+        );
+        let mut root_meta = Map::new();
+        for path in &config.fabric.policy.paths {
+            let prefix = Prefix::from_jpath(path);
+            let literal = prefix.literal_jpath();
+            let meta = match self.fetch_meta(&root.library, &root_hash, &literal) {
+                Ok(m) => m,
+                Err(e) => {
+                    self.bcc.log_warn(&format!(
+                        "crawl: no metadata at policy path {path} of {root_hash}: {e}"
+                    ))?;
+                    state.errors += 1;
+                    continue;
+                }
+            };
+            let skip = Prefix::from_jpath(&literal).len();
+            for (concrete, v) in prefix.expand(&meta, skip) {
+                state.values += 1;
+                walk(&mut state, &concrete, v, &root_hash, 1);
+                root_meta.insert(concrete, v.clone());
+            }
+        }
+        while let Some(link) = state.pending.pop_front() {
+            self.follow(&mut state, link)?;
+        }
+        if let Some(entry) = state.registry.get_mut(&root_hash) {
+            entry.insert("meta".to_string(), Value::Object(root_meta));
+        }
+
+        let mut sorted_hashes: Vec<String> = state.registry.keys().cloned().collect();
+        sorted_hashes.sort();
+        let mut sorted_ids: Vec<String> = state
+            .registry
+            .values()
+            .filter_map(|e| e["id"].as_str().map(|s| s.to_string()))
+            .collect();
+        sorted_ids.sort();
+        sorted_ids.dedup();
+        let stats = json!({
+            "root" : root_hash,
+            "objects" : state.registry.len(),
+            "values" : state.values,
+            "links" : state.links,
+            "cycles" : state.cycles,
+            "depth_limited" : state.depth_limited,
+            "errors" : state.errors,
+            "max_depth" : state.deepest,
+        });
+        Ok(CrawlResult {
+            mdregistry: state
+                .registry
+                .into_iter()
+                .map(|(k, v)| (k, Value::Object(v)))
+                .collect(),
+            stats: stats.as_object().cloned().unwrap_or_default(),
+            sorted_ids,
+            sorted_hashes,
+        })
+    }
+
+    /// latest_hash looks up the latest version hash of a content object, the first one listed
+    pub fn latest_hash(&self, qid: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        if qid == self.bcc.request.q_info.id && !self.bcc.request.q_info.hash.is_empty() {
+            return Ok(self.bcc.request.q_info.hash.clone());
+        }
+        let res = self.bcc.q_get_versions(qid, false)?;
+        let versions: QRef = serde_json::from_slice(&res)?;
+        match versions.versions.first() {
+            Some(q) => Ok(q.hash.clone()),
+            None => Err(Box::new(ErrorKinds::NotExist(format!(
+                "content {qid} has no versions"
+            )))),
+        }
+    }
+
+    fn fetch_meta(
+        &self,
+        library: &str,
+        hash: &str,
+        path: &str,
+    ) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let res = self.bcc.sqmd_get_json_external(library, hash, path)?;
+        let v: Value = serde_json::from_slice(&res)?;
+        if let Some(err) = v.as_object().and_then(|o| o.get("error")) {
+            return Err(Box::new(ErrorKinds::NotExist(format!(
+                "metadata {path} of {hash}: {err}"
+            ))));
+        }
+        Ok(v)
+    }
+
+    // the library of a linked object, that of the root when it cannot be looked up
+    fn library_of(
+        &self,
+        state: &CrawlState,
+        id: &str,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let reason = match self.bcc.q_get_versions(id, true) {
+            Ok(res) => match serde_json::from_slice::<QRef>(&res) {
+                Ok(v) => match v.versions.into_iter().next() {
+                    Some(q) if !q.qlib_id.is_empty() => return Ok(q.qlib_id),
+                    _ => "no versions".to_string(),
+                },
+                Err(e) => e.to_string(),
+            },
+            Err(e) => e.to_string(),
+        };
+        self.bcc.log_warn(&format!(
+            "crawl: no library found for {id}, assuming {}: {reason}",
+            state.library
+        ))?;
+        Ok(state.library.clone())
+    }
+
+    fn follow(
+        &self,
+        state: &mut CrawlState,
+        link: Link,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Link {
+            path,
+            hash,
+            from,
+            depth,
+        } = link;
+        state.links += 1;
+        if is_on_chain(state, &hash, &from) {
+            state.cycles += 1;
+            return Ok(());
+        }
+        if let Some(entry) = state.registry.get_mut(&hash) {
+            // already crawled through another link, at no more hops
+            if let Some(Value::Array(links)) = entry.get_mut("links") {
+                links.push(json!(path));
+            }
+            return Ok(());
+        }
+        if depth > state.max_depth {
+            state.depth_limited += 1;
+            return Ok(());
+        }
+        let id = id_from_hash(&hash).unwrap_or_else(|| hash.clone());
+        let library = self.library_of(state, &id)?;
+        let meta = match self.fetch_meta(&library, &hash, &state.prefix) {
+            Ok(m) => m,
+            Err(e) => {
+                self.bcc
+                    .log_warn(&format!("crawl: skipping link {path} to {hash}: {e}"))?;
+                state.errors += 1;
+                return Ok(());
+            }
+        };
+        let mut entry = registry_entry(&id, &hash, &library, depth);
+        entry.insert("links".to_string(), json!([path]));
+        state.registry.insert(hash.clone(), entry);
+        state.parents.insert(hash.clone(), from);
+        state.deepest = state.deepest.max(depth);
+        let prefix = state.prefix.trim_end_matches('/').to_string();
+        walk(state, &prefix, &meta, &hash, depth + 1);
+        if let Some(entry) = state.registry.get_mut(&hash) {
+            entry.insert("meta".to_string(), meta);
+        }
+        Ok(())
+    }
+}
+
+// walks a metadata value of object `from` found at path, queueing the links to other objects it holds
+fn walk(state: &mut CrawlState, path: &str, v: &Value, from: &str, depth: usize) {
+    match v {
+        Value::Object(o) => {
+            if let Some(hash) = o.get("/").and_then(|l| l.as_str()).and_then(link_hash) {
+                state.pending.push_back(Link {
+                    path: path.to_string(),
+                    hash,
+                    from: from.to_string(),
+                    depth,
+                });
+                return;
+            }
+            for (k, child) in o {
+                walk(state, &format!("{path}/{k}"), child, from, depth);
+            }
+        }
+        Value::Array(a) => {
+            for (i, child) in a.iter().enumerate() {
+                walk(state, &format!("{path}[{i}]"), child, from, depth);
+            }
+        }
+        _ => {}
+    }
+}
+
+// whether `hash` is `from` or one of the objects on the chain of links leading to it
+fn is_on_chain(state: &CrawlState, hash: &str, from: &str) -> bool {
+    let mut cur = Some(from);
+    while let Some(c) = cur {
+        if c == hash {
+            return true;
+        }
+        cur = state.parents.get(c).map(|p| p.as_str());
+    }
+    false
+}
+
+fn registry_entry(id: &str, hash: &str, library: &str, depth: usize) -> Map<String, Value> {
+    json!({
+        "id" : id,
+        "hash" : hash,
+        "qlib_id" : library,
+        "depth" : depth,
+        "links" : [],
+        "meta" : null,
+    })
+    .as_object()
+    .cloned()
+    .unwrap_or_default()
+}
+
+/// link_hash returns the content hash a fabric link such as `/qfab/hq__.../meta/title` points to, None for
+/// links within the same object (`./meta/...`) and links to anything other than metadata
+pub fn link_hash(link: &str) -> Option<String> {
+    let mut parts = link.trim_start_matches('/').split('/');
+    if parts.next()? != "qfab" {
+        return None;
+    }
+    let hash = parts.next()?;
+    if !hash.starts_with("hq__") || parts.next() != Some("meta") {
+        return None;
+    }
+    Some(hash.to_string())
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for b in bytes.iter_mut().rev() {
+            carry += (*b as u32) * 58;
+            *b = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, (carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    let mut out = vec![0; zeros];
+    out.extend(bytes);
+    Some(out)
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::new();
+    for &b in data {
+        let mut carry = b as u32;
+        for d in digits.iter_mut() {
+            carry += (*d as u32) << 8;
+            *d = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = data.iter().take_while(|&&b| b == 0).count();
+    std::iter::repeat(b'1')
+        .take(zeros)
+        .chain(digits.iter().rev().map(|&d| BASE58_ALPHABET[d as usize]))
+        .map(|b| b as char)
+        .collect()
+}
+
+/// id_from_hash recovers the content id from a content hash, which encodes a 32 byte digest, the size as
+/// an unsigned varint and the id bytes
+pub fn id_from_hash(hash: &str) -> Option<String> {
+    let bytes = base58_decode(hash.strip_prefix("hq__")?)?;
+    let mut rest = bytes.get(32..)?;
+    // skip the varint size
+    loop {
+        let (b, tail) = rest.split_first()?;
+        rest = tail;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    if rest.is_empty() {
+        return None;
+    }
+    Some(format!("iq__{}", base58_encode(rest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links_and_ids() {
+        assert_eq!(
+            Some("hq__abc".to_string()),
+            link_hash("/qfab/hq__abc/meta/public/asset_metadata")
+        );
+        assert_eq!(None, link_hash("./meta/public"));
+        assert_eq!(None, link_hash("/qfab/hq__abc/files/image.jpg"));
+        let id_bytes = [7_u8, 9, 200, 1];
+        let mut raw = vec![0xab_u8; 32];
+        raw.extend([0xac, 0x02]); // varint 300
+        raw.extend(id_bytes);
+        let hash = format!("hq__{}", base58_encode(&raw));
+        assert_eq!(Some(raw), base58_decode(&hash[4..]));
+        assert_eq!(
+            Some(format!("iq__{}", base58_encode(&id_bytes))),
+            id_from_hash(&hash)
+        );
+        assert_eq!(None, id_from_hash("hq__111"));
+    }
+}
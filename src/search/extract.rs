@@ -0,0 +1,558 @@
+//! Field value extraction <br>
+//! An [Extractor] matches the paths of the fields of an index config against the metadata of a document
+//! all at once, through a [FilterDAG] of the paths, yielding each value found with its concrete path.
+//! Links met along the paths can be followed with a resolver such as [fabric_resolver].
+//! ```rust
+//! use elvwasm::search::{fabric_resolver, Extractor, IndexerConfig};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let meta: serde_json::Value = serde_json::from_slice(&bcc.sqmd_get_json("/")?)?;
+//!   let library = bcc.request.q_info.qlib_id.clone();
+//!   let found = Extractor::new(&config.fields).extract_with_links(&meta, fabric_resolver(bcc, &library));
+//!   Ok(serde_json::to_vec(&found)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    field_jpath, link_hash, FieldConfig, Prefix, PrefixValue, DEFAULT_CRAWL_DEPTH,
+};
+use crate::BitcodeContext;
+
+use serde_derive::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::VecDeque;
+
+/// A vertex of a [FilterDAG]
+pub type NodeIndex = usize;
+
+#[derive(Clone, Debug, Default)]
+struct Vertex {
+    // names of the fields whose path ends here
+    fields: Vec<String>,
+    edges: Vec<(PrefixValue, NodeIndex)>,
+    parent: Option<(PrefixValue, NodeIndex)>,
+}
+
+/// FilterDAG represent the automata to filter hierarchical paths, the edges being the elements of the
+/// paths, shared by paths with a common prefix, and each vertex holding the fields whose path ends there
+#[derive(Clone, Debug)]
+pub struct FilterDAG {
+    vertices: Vec<Vertex>,
+}
+
+impl FilterDAG {
+    /// new builds the automata of the given paths, each field being named after its path
+    pub fn new(fields: &[String]) -> Self {
+        let mut s = FilterDAG::empty();
+        for field in fields {
+            s.add_path(field, field);
+        }
+        s
+    }
+
+    /// from_fields builds the automata of the paths of an index config's fields
+    pub fn from_fields(fields: &[FieldConfig]) -> Self {
+        let mut s = FilterDAG::empty();
+        for field in fields {
+            for path in &field.paths {
+                s.add_path(&field.name, path);
+            }
+        }
+        s
+    }
+
+    fn empty() -> Self {
+        FilterDAG {
+            vertices: vec![Vertex::default()],
+        }
+    }
+
+    /// add_path adds the path of a field, written with dots or as a jpath
+    pub fn add_path(&mut self, name: &str, path: &str) {
+        let mut vertex = self.root();
+        for elem in Prefix::from_jpath(&field_jpath(path)).prefix {
+            let existing = self.vertices[vertex]
+                .edges
+                .iter()
+                .find(|(e, _)| *e == elem)
+                .map(|(_, child)| *child);
+            vertex = match existing {
+                Some(child) => child,
+                None => {
+                    let child = self.vertices.len();
+                    self.vertices.push(Vertex {
+                        parent: Some((elem.clone(), vertex)),
+                        ..Default::default()
+                    });
+                    self.vertices[vertex].edges.push((elem, child));
+                    child
+                }
+            };
+        }
+        if !self.vertices[vertex].fields.iter().any(|f| f == name) {
+            self.vertices[vertex].fields.push(name.to_string());
+        }
+    }
+
+    pub fn root(&self) -> NodeIndex {
+        0
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    /// next_keys returns the path elements leaving a vertex along with the vertices they lead to
+    pub fn next_keys(&self, vertex: NodeIndex) -> &[(PrefixValue, NodeIndex)] {
+        &self.vertices[vertex].edges
+    }
+
+    /// fields returns the names of the fields whose path ends at the vertex
+    pub fn fields(&self, vertex: NodeIndex) -> &[String] {
+        &self.vertices[vertex].fields
+    }
+
+    pub fn is_final(&self, vertex: NodeIndex) -> bool {
+        !self.vertices[vertex].fields.is_empty()
+    }
+
+    /// prefix returns the path from the root to the vertex
+    pub fn prefix(&self, mut vertex: NodeIndex) -> Prefix {
+        let mut prefix_vec = Vec::new();
+        while let Some((elem, parent)) = &self.vertices[vertex].parent {
+            prefix_vec.push(elem.clone());
+            vertex = *parent;
+        }
+        prefix_vec.reverse();
+        Prefix { prefix: prefix_vec }
+    }
+}
+
+// the values waiting to be scanned, with the vertex of the automata they were reached at and their path
+struct ScanQueue<'a, 'b> {
+    filter_dag: &'a FilterDAG,
+    scanning_queue: VecDeque<(&'b Value, NodeIndex, String)>,
+}
+
+impl<'a, 'b> ScanQueue<'a, 'b> {
+    fn new(
+        filter_dag: &'a FilterDAG,
+        dictionary: &'b Value,
+        field_state_option: Option<NodeIndex>,
+        prefix_option: Option<String>,
+    ) -> Self {
+        let field_state = field_state_option.unwrap_or_else(|| filter_dag.root());
+        let prefix = prefix_option.unwrap_or_default();
+        ScanQueue {
+            filter_dag,
+            scanning_queue: VecDeque::from([(dictionary, field_state, prefix)]),
+        }
+    }
+
+    // queues the children of the value matched by the edges leaving its vertex
+    fn expand(&mut self, dictionary: &'b Value, field_state: NodeIndex, prefix: &str) {
+        for (next_key, next_field_state) in self.filter_dag.next_keys(field_state) {
+            for (path, child) in next_key.select(prefix, dictionary) {
+                self.scanning_queue
+                    .push_back((child, *next_field_state, path));
+            }
+        }
+    }
+}
+
+/// as_link returns the target of a link `{"/" : "/qfab/hq__.../meta/..."}`
+pub fn as_link(v: &Value) -> Option<&str> {
+    v.as_object()?.get("/")?.as_str()
+}
+
+/// What the [LinkScanner] yields
+pub enum Scanned<'b> {
+    /// a value at the end of a path
+    Value(&'b Value, NodeIndex, String),
+    /// a link met along a path, scanning is resumed from the vertex on the value it resolves to
+    Link(&'b str, NodeIndex, String),
+}
+
+pub struct LinkScanner {
+    pub filter_dag: FilterDAG,
+}
+
+impl LinkScanner {
+    pub fn new(filter_dag: FilterDAG) -> LinkScanner {
+        LinkScanner { filter_dag }
+    }
+
+    pub fn scanner<'a, 'b>(
+        &'a self,
+        dictionary: &'b Value,
+        field_state_option: Option<NodeIndex>,
+        prefix_option: Option<String>,
+    ) -> LinkScannerIterator<'a, 'b> {
+        LinkScannerIterator {
+            queue: ScanQueue::new(
+                &self.filter_dag,
+                dictionary,
+                field_state_option,
+                prefix_option,
+            ),
+        }
+    }
+}
+
+pub struct LinkScannerIterator<'a, 'b> {
+    queue: ScanQueue<'a, 'b>,
+}
+
+impl<'b> Iterator for LinkScannerIterator<'_, 'b> {
+    type Item = Scanned<'b>;
+
+    fn next(&mut self) -> Option<Scanned<'b>> {
+        while let Some((dictionary, field_state, prefix)) = self.queue.scanning_queue.pop_front() {
+            if let Some(link) = as_link(dictionary) {
+                return Some(Scanned::Link(link, field_state, prefix));
+            }
+            self.queue.expand(dictionary, field_state, &prefix);
+            if self.queue.filter_dag.is_final(field_state) {
+                return Some(Scanned::Value(dictionary, field_state, prefix));
+            }
+        }
+        None
+    }
+}
+
+pub struct DictScanner {
+    pub filter_dag: FilterDAG,
+}
+
+impl DictScanner {
+    pub fn new(filter_dag: FilterDAG) -> DictScanner {
+        DictScanner { filter_dag }
+    }
+
+    pub fn scanner<'a, 'b>(
+        &'a self,
+        dictionary: &'b Value,
+        field_state_option: Option<NodeIndex>,
+        prefix_option: Option<String>,
+    ) -> DictScannerIterator<'a, 'b> {
+        DictScannerIterator {
+            queue: ScanQueue::new(
+                &self.filter_dag,
+                dictionary,
+                field_state_option,
+                prefix_option,
+            ),
+        }
+    }
+}
+
+pub struct DictScannerIterator<'a, 'b> {
+    queue: ScanQueue<'a, 'b>,
+}
+
+impl<'b> Iterator for DictScannerIterator<'_, 'b> {
+    type Item = (&'b Value, NodeIndex, String);
+
+    fn next(&mut self) -> Option<(&'b Value, NodeIndex, String)> {
+        while let Some((dictionary, field_state, prefix)) = self.queue.scanning_queue.pop_front() {
+            self.queue.expand(dictionary, field_state, &prefix);
+            if self.queue.filter_dag.is_final(field_state) {
+                return Some((dictionary, field_state, prefix));
+            }
+        }
+        None
+    }
+}
+
+/// A value extracted for a field along with its concrete path, links followed being transparent in it
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Extracted {
+    pub field: String,
+    pub path: String,
+    pub value: Value,
+}
+
+/**
+ * Extracts the values of the fields of an index config from a metadata tree. The field paths are matched
+ * together by a [FilterDAG], `*` matching every key of an object and `[*]` every item of an array, and
+ * links met along the way are followed, up to `max_depth` hops, when extracting with a resolver.
+ */
+pub struct Extractor {
+    scanner: LinkScanner,
+    max_depth: usize,
+}
+
+impl Extractor {
+    pub fn new(fields: &[FieldConfig]) -> Extractor {
+        Extractor::from_dag(FilterDAG::from_fields(fields))
+    }
+
+    pub fn from_dag(filter_dag: FilterDAG) -> Extractor {
+        Extractor {
+            scanner: LinkScanner::new(filter_dag),
+            max_depth: DEFAULT_CRAWL_DEPTH,
+        }
+    }
+
+    /// max_depth sets how many links may be followed one after the other
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// extract returns the values matching the field paths without following links, which are matched as they are
+    pub fn extract(&self, meta: &Value) -> Vec<Extracted> {
+        let mut out = Vec::new();
+        let dag = &self.scanner.filter_dag;
+        let scanner = DictScannerIterator {
+            queue: ScanQueue::new(dag, meta, None, None),
+        };
+        for (value, state, path) in scanner {
+            push_fields(&mut out, dag, state, &path, value);
+        }
+        out
+    }
+
+    /// extract_with_links returns the values matching the field paths, resolving the links met along them
+    /// with `resolve`; links within the metadata itself (`./meta/...`) are resolved against `meta`, and
+    /// unresolved links or links beyond `max_depth` or back to a link being followed are skipped
+    pub fn extract_with_links<F>(&self, meta: &Value, mut resolve: F) -> Vec<Extracted>
+    where
+        F: FnMut(&str) -> Option<Value>,
+    {
+        let mut out = Vec::new();
+        let dag = &self.scanner.filter_dag;
+        // values links resolved to along with the links followed to reach them
+        let mut pending: Vec<(Value, NodeIndex, String, Vec<String>)> = Vec::new();
+        let mut scan =
+            |value: &Value,
+             state: Option<NodeIndex>,
+             path: Option<String>,
+             chain: &[String],
+             pending: &mut Vec<(Value, NodeIndex, String, Vec<String>)>| {
+                for item in self.scanner.scanner(value, state, path) {
+                    match item {
+                        Scanned::Value(v, state, path) => {
+                            push_fields(&mut out, dag, state, &path, v)
+                        }
+                        Scanned::Link(link, state, path) => {
+                            if chain.len() >= self.max_depth || chain.iter().any(|l| l == link) {
+                                continue;
+                            }
+                            let resolved = match link.strip_prefix("./meta") {
+                                Some(local) => Prefix::from_jpath(local)
+                                    .expand(meta, 0)
+                                    .first()
+                                    .map(|(_, v)| (*v).clone()),
+                                None => resolve(link),
+                            };
+                            if let Some(v) = resolved {
+                                let mut chain = chain.to_vec();
+                                chain.push(link.to_string());
+                                pending.push((v, state, path, chain));
+                            }
+                        }
+                    }
+                }
+            };
+        scan(meta, None, None, &[], &mut pending);
+        while let Some((value, state, path, chain)) = pending.pop() {
+            scan(&value, Some(state), Some(path), &chain, &mut pending);
+        }
+        out
+    }
+}
+
+fn push_fields(out: &mut Vec<Extracted>, dag: &FilterDAG, state: NodeIndex, path: &str, v: &Value) {
+    for field in dag.fields(state) {
+        out.push(Extracted {
+            field: field.clone(),
+            path: path.to_string(),
+            value: v.clone(),
+        });
+    }
+}
+
+/// link_meta_path returns the metadata path a fabric link such as `/qfab/hq__.../meta/public/title` points to
+pub fn link_meta_path(link: &str) -> Option<String> {
+    link_hash(link)?;
+    let (_, path) = link.split_once("/meta")?;
+    Some(if path.is_empty() { "/" } else { path }.to_string())
+}
+
+/// fabric_resolver resolves links to the metadata of other content in the library
+pub fn fabric_resolver<'a>(
+    bcc: &'a BitcodeContext,
+    library: &'a str,
+) -> impl FnMut(&str) -> Option<Value> + 'a {
+    move |link: &str| {
+        let hash = link_hash(link)?;
+        let path = link_meta_path(link)?;
+        let res = bcc.sqmd_get_json_external(library, &hash, &path).ok()?;
+        let v: Value = serde_json::from_slice(&res).ok()?;
+        match v.as_object() {
+            Some(o) if o.contains_key("error") => None,
+            _ => Some(v),
+        }
+    }
+}
+
+/// document_fields gathers the scalar values found at each field's paths into `{"field" : [values]}`
+pub fn document_fields(meta: &Value, fields: &[FieldConfig]) -> Value {
+    let mut out = Map::new();
+    for e in Extractor::new(fields).extract(meta) {
+        if e.value.is_string() || e.value.is_number() || e.value.is_boolean() {
+            if let Value::Array(values) = out.entry(e.field).or_insert_with(|| json!([])) {
+                values.push(e.value);
+            }
+        }
+    }
+    Value::Object(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn field(name: &str, paths: &[&str]) -> FieldConfig {
+        serde_json::from_value(json!({
+            "name" : name, "type" : "text", "options" : {}, "paths" : paths,
+        }))
+        .unwrap()
+    }
+
+    fn found(extracted: Vec<Extracted>) -> Vec<(String, String, Value)> {
+        let mut v: Vec<(String, String, Value)> = extracted
+            .into_iter()
+            .map(|e| (e.field, e.path, e.value))
+            .collect();
+        v.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        v
+    }
+
+    #[test]
+    fn test_filter_dag() {
+        let dag = FilterDAG::new(&[
+            "site_map.searchables.*.title".to_string(),
+            "site_map.searchables.*.genre[*]".to_string(),
+        ]);
+        // the root, then site_map, searchables and * shared by both paths
+        assert_eq!(7, dag.node_count());
+        let leaves: Vec<String> = (0..dag.node_count())
+            .filter(|v| dag.is_final(*v))
+            .map(|v| dag.prefix(v).to_jpath())
+            .collect();
+        assert_eq!(
+            vec![
+                "/site_map/searchables/*/title",
+                "/site_map/searchables/*/genre[*]"
+            ],
+            leaves
+        );
+    }
+
+    #[test]
+    fn test_extract() {
+        let meta = json!({"site_map" : {"searchables" : {
+            "a" : {"title" : "Sea", "genre" : ["drama", "classic"], "cast" : [{"name" : "Spencer"}]},
+            "b" : {"title" : "Man", "genre" : "none"},
+        }}});
+        let extractor = Extractor::new(&[
+            field("title", &["site_map.searchables.*.title"]),
+            field("genre", &["site_map.searchables.*.genre[*]"]),
+            field("cast", &["/site_map/searchables/a/cast[0]/name"]),
+        ]);
+        assert_eq!(
+            vec![
+                (
+                    "cast".to_string(),
+                    "/site_map/searchables/a/cast[0]/name".to_string(),
+                    json!("Spencer")
+                ),
+                (
+                    "genre".to_string(),
+                    "/site_map/searchables/a/genre[0]".to_string(),
+                    json!("drama")
+                ),
+                (
+                    "genre".to_string(),
+                    "/site_map/searchables/a/genre[1]".to_string(),
+                    json!("classic")
+                ),
+                (
+                    "title".to_string(),
+                    "/site_map/searchables/a/title".to_string(),
+                    json!("Sea")
+                ),
+                (
+                    "title".to_string(),
+                    "/site_map/searchables/b/title".to_string(),
+                    json!("Man")
+                ),
+            ],
+            found(extractor.extract(&meta))
+        );
+    }
+
+    #[test]
+    fn test_extract_with_links() {
+        let meta = json!({
+            "searchables" : {
+                "a" : {"/" : "/qfab/hq__a/meta/public/asset_metadata"},
+                "b" : {"/" : "./meta/local"},
+                "c" : {"/" : "/qfab/hq__c/meta/public/asset_metadata"},
+            },
+            "local" : {"title" : "Local"},
+        });
+        let linked = HashMap::from([
+            (
+                "/qfab/hq__a/meta/public/asset_metadata",
+                json!({"title" : {"/" : "/qfab/hq__t/meta/title"}}),
+            ),
+            ("/qfab/hq__t/meta/title", json!("Linked")),
+            (
+                "/qfab/hq__c/meta/public/asset_metadata",
+                json!({"title" : "Cycle", "/" : "/qfab/hq__c/meta/public/asset_metadata"}),
+            ),
+        ]);
+        let extractor = Extractor::new(&[field("title", &["searchables.*.title"])]);
+        let resolve = |link: &str| linked.get(link).cloned();
+        assert_eq!(
+            vec![
+                (
+                    "title".to_string(),
+                    "/searchables/a/title".to_string(),
+                    json!("Linked")
+                ),
+                (
+                    "title".to_string(),
+                    "/searchables/b/title".to_string(),
+                    json!("Local")
+                ),
+            ],
+            found(extractor.extract_with_links(&meta, resolve))
+        );
+        let shallow = Extractor::new(&[field("title", &["searchables.*.title"])]).max_depth(1);
+        assert_eq!(
+            vec![(
+                "title".to_string(),
+                "/searchables/b/title".to_string(),
+                json!("Local")
+            )],
+            found(shallow.extract_with_links(&meta, |link: &str| linked.get(link).cloned()))
+        );
+        assert_eq!(
+            Some("/public/asset_metadata".to_string()),
+            link_meta_path("/qfab/hq__a/meta/public/asset_metadata")
+        );
+        assert_eq!(None, link_meta_path("./meta/local"));
+    }
+}
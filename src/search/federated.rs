@@ -0,0 +1,243 @@
+//! Federated search over several index parts <br>
+//! A large library can be indexed in shards, each archived to its own part, possibly of different content
+//! objects. A [FederatedSearcher] runs a [SearchRequest] against each [IndexPart] in turn, asking every
+//! shard for the top `offset + limit` hits, then merges them: ordered by score (or by the requested sort),
+//! duplicates of a document found in more than one shard dropped by their [UID_FIELD] and the page cut
+//! from the merged list. Aggregations are summed across the shards. Every shard is read with the schema of
+//! the same indexer configuration fields.
+//!
+//! Scores are the raw BM25 scores of each shard, computed from that shard's own term statistics, so they are
+//! only roughly comparable across shards: a term rare in one shard scores higher there than in another. Shards
+//! of similar size and content merge well, very uneven shards should be searched with a sort instead. Terms
+//! aggregations are truncated by each shard to its top `size` buckets before being summed, so a term just
+//! below the cut in some shards is undercounted and the merged buckets are approximate.
+//! ```rust
+//! use elvwasm::search::{FederatedSearcher, IndexPart, IndexerConfig, SearchRequest};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let mut parts: Vec<IndexPart> = IndexPart::current(bcc)?.into_iter().collect();
+//!   parts.extend(IndexPart::current_external(bcc, "ilib123", "hq__456")?);
+//!   let req = SearchRequest::new("old man sea").limit(20).offset(20);
+//!   let results = FederatedSearcher::new(bcc, config.fields, parts).search(&req)?;
+//!   Ok(serde_json::to_vec(&results)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    FieldConfig, Hit, IndexState, Indexer, SearchRequest, SearchResults, Searcher, UID_FIELD,
+};
+use crate::BitcodeContext;
+
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// An archived index: the content it belongs to and its part
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexPart {
+    pub content_hash: String,
+    pub part_hash: String,
+}
+
+impl IndexPart {
+    pub fn new(content_hash: &str, part_hash: &str) -> IndexPart {
+        IndexPart {
+            content_hash: content_hash.to_string(),
+            part_hash: part_hash.to_string(),
+        }
+    }
+
+    /// current returns the current index part of the content, None when it has not been indexed
+    pub fn current(
+        bcc: &BitcodeContext,
+    ) -> Result<Option<IndexPart>, Box<dyn Error + Send + Sync>> {
+        Ok(IndexState::load(bcc)?
+            .current
+            .map(|c| IndexPart::new(&bcc.request.q_info.hash, &c.part_hash)))
+    }
+
+    /// current_external returns the current index part of another content
+    pub fn current_external(
+        bcc: &BitcodeContext,
+        qlibid: &str,
+        qhash: &str,
+    ) -> Result<Option<IndexPart>, Box<dyn Error + Send + Sync>> {
+        Ok(IndexState::load_external(bcc, qlibid, qhash)?
+            .current
+            .map(|c| IndexPart::new(qhash, &c.part_hash)))
+    }
+}
+
+/// FederatedSearcher searches several index parts as one, see the [module documentation](self)
+pub struct FederatedSearcher<'a> {
+    bcc: &'a BitcodeContext,
+    fields: Vec<FieldConfig>,
+    parts: Vec<IndexPart>,
+}
+
+impl<'a> FederatedSearcher<'a> {
+    pub fn new(
+        bcc: &'a BitcodeContext,
+        fields: Vec<FieldConfig>,
+        parts: Vec<IndexPart>,
+    ) -> FederatedSearcher<'a> {
+        FederatedSearcher { bcc, fields, parts }
+    }
+
+    pub fn parts(&self) -> &[IndexPart] {
+        &self.parts
+    }
+
+    /// search restores each part in turn and runs the request on it, returning one page of the merged hits
+    pub fn search(
+        &self,
+        req: &SearchRequest,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let shard_req = SearchRequest {
+            offset: 0,
+            limit: req.offset + req.limit,
+            ..req.clone()
+        };
+        let mut shards = Vec::with_capacity(self.parts.len());
+        for part in &self.parts {
+            let indexer = Indexer::restore(
+                self.bcc,
+                &part.content_hash,
+                &part.part_hash,
+                self.fields.clone(),
+            )?;
+            let results = Searcher::new(self.bcc, &indexer.schema)?.search(&shard_req)?;
+            shards.push((part.part_hash.clone(), results));
+        }
+        Ok(merge_results(req, shards))
+    }
+}
+
+/// merge_results merges the results of a request run on several index parts, each holding its top
+/// `offset + limit` hits, into the requested page. The total is the sum of the shards' totals less the
+/// duplicates found among the hits they returned. Hits are ordered by their shard local scores and terms
+/// buckets summed from each shard's top buckets, see the [module documentation](self).
+pub fn merge_results(req: &SearchRequest, shards: Vec<(String, SearchResults)>) -> SearchResults {
+    let mut merged = SearchResults {
+        offset: req.offset,
+        limit: req.limit,
+        ..Default::default()
+    };
+    for (part, results) in shards {
+        merged.total_hits += results.total_hits;
+        merged.hits.extend(results.hits.into_iter().map(|h| Hit {
+            part: Some(part.clone()),
+            ..h
+        }));
+        for (name, res) in results.aggregations {
+            match (
+                merged.aggregations.get_mut(&name),
+                req.aggregations.get(&name),
+            ) {
+                (Some(mine), Some(agg)) => mine.merge(agg, &res),
+                _ => {
+                    merged.aggregations.insert(name, res);
+                }
+            }
+        }
+    }
+    match &req.sort {
+        Some(sort) => merged.sort_by_field(sort),
+        None => merged
+            .hits
+            .sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)),
+    }
+    let mut seen = HashSet::new();
+    let before = merged.hits.len();
+    merged.hits.retain(|h| match h.text(UID_FIELD) {
+        Some(uid) => seen.insert(uid.to_string()),
+        None => true,
+    });
+    merged.total_hits = merged
+        .total_hits
+        .saturating_sub((before - merged.hits.len()) as u64);
+    merged.hits = merged
+        .hits
+        .into_iter()
+        .skip(req.offset)
+        .take(req.limit)
+        .collect();
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{Aggregation, AggregationResult, Bucket};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn hit(uid: &str, score: f64) -> Hit {
+        Hit {
+            score,
+            address: None,
+            part: None,
+            fields: json!({ "uid": [uid] }).as_object().unwrap().clone(),
+            snippets: BTreeMap::new(),
+        }
+    }
+
+    fn shard(part: &str, total: u64, hits: Vec<Hit>, types: Value) -> (String, SearchResults) {
+        let buckets: Vec<Bucket> = serde_json::from_value(types).unwrap();
+        let mut results = SearchResults {
+            total_hits: total,
+            hits,
+            ..Default::default()
+        };
+        results
+            .aggregations
+            .insert("types".to_string(), AggregationResult::Buckets(buckets));
+        (part.to_string(), results)
+    }
+
+    #[test]
+    fn test_merge_results() {
+        let req = SearchRequest::new("sea")
+            .limit(2)
+            .offset(1)
+            .aggregate("types", Aggregation::terms("type", 2));
+        let merged = merge_results(
+            &req,
+            vec![
+                shard(
+                    "hqp_a",
+                    10,
+                    vec![hit("iq__1", 3.0), hit("iq__2", 1.0), hit("iq__3", 0.5)],
+                    json!([{"key" : "movie", "doc_count" : 6}, {"key" : "show", "doc_count" : 4}]),
+                ),
+                shard(
+                    "hqp_b",
+                    5,
+                    vec![hit("iq__4", 2.5), hit("iq__1", 2.0), hit("iq__5", 0.1)],
+                    json!([{"key" : "clip", "doc_count" : 5}, {"key" : "movie", "doc_count" : 1}]),
+                ),
+            ],
+        );
+        assert_eq!(merged.total_hits, 14);
+        let uids: Vec<&str> = merged.hits.iter().filter_map(|h| h.text("uid")).collect();
+        assert_eq!(uids, vec!["iq__4", "iq__2"]);
+        assert_eq!(merged.hits[0].part.as_deref(), Some("hqp_b"));
+        assert!(merged.has_more());
+        let types: Vec<(String, u64)> = merged.aggregations["types"]
+            .buckets()
+            .iter()
+            .map(|b| (b.key.as_str().unwrap().to_string(), b.doc_count))
+            .collect();
+        assert_eq!(
+            types,
+            vec![("movie".to_string(), 7), ("clip".to_string(), 5)]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Ready to register search handlers <br>
+//! Each handler reads the [IndexerConfig] of the content from its metadata. [crawl] builds the index from
+//! scratch, [update] brings it up to date with the objects changed since, [search] answers queries
+//! against the current index part, [federated_search] against it and the index parts of other contents,
+//! [suggest] completes and corrects what a user typed, [vector_search] finds the documents with the closest
+//! embeddings and [rollback] returns to the previous part. They all reply
+//! `{"headers" : "application/json", "body" : "SUCCESS", "result" : ...}` with their result.
+//! ```ignore
+//! use elvwasm::search::handlers::{crawl, federated_search, rollback, search, suggest, update, vector_search};
+//! use elvwasm::{implement_bitcode_module, jpc, register_handler};
+//!
+//! implement_bitcode_module!("crawl", crawl, "update", update, "search", search,
+//!   "federated_search", federated_search, "suggest", suggest, "vector_search", vector_search,
+//!   "rollback", rollback);
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    keyword_filter, rebuild_index, rollback_index, suggested_fields, update_index, vector_fields,
+    FederatedSearcher, IndexPart, IndexState, Indexer, IndexerConfig, SearchRequest, Searcher,
+    Suggester, VectorIndex, DEFAULT_FILTER_LIMIT, DEFAULT_SUGGEST_LIMIT, DEFAULT_VECTOR_K,
+};
+use crate::{BitcodeContext, ErrorKinds};
+
+use guest::CallResult;
+use serde_json::{json, Value};
+
+fn success(bcc: &BitcodeContext, result: Value) -> CallResult {
+    bcc.make_success_json(&json!(
+    {
+        "headers" : "application/json",
+        "body" : "SUCCESS",
+        "result" : result,
+    }))
+}
+
+/// crawl crawls the root content of the config and indexes every document found into a new part
+pub fn crawl(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let result = rebuild_index(bcc, &config)?;
+    bcc.log_info(&format!("index crawl = {result}"))?;
+    success(bcc, result)
+}
+
+/// update reindexes the objects added, changed or removed since the current part was built
+pub fn update(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let result = update_index(bcc, &config)?;
+    bcc.log_info(&format!("index update = {result}"))?;
+    success(bcc, result)
+}
+
+// restores the current index part
+fn restore_current(
+    bcc: &BitcodeContext,
+    config: IndexerConfig,
+) -> Result<Indexer, Box<dyn std::error::Error + Send + Sync>> {
+    let current = IndexState::load(bcc)?
+        .current
+        .ok_or_else(|| ErrorKinds::NotExist("the content has not been indexed".to_string()))?;
+    Indexer::restore(
+        bcc,
+        &bcc.request.q_info.hash,
+        &current.part_hash,
+        config.fields,
+    )
+}
+
+/// search restores the current index part and runs the [SearchRequest] of the query parameters
+pub fn search(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let req = SearchRequest::from_query(&bcc.request.params.http.query)?;
+    let indexer = restore_current(bcc, config)?;
+    let results = Searcher::new(bcc, &indexer.schema)?.search(&req)?;
+    success(bcc, json!(results))
+}
+
+/// federated_search runs the [SearchRequest] of the query parameters against the current index part of the
+/// content, when it has one, and those of the `shards` query parameter, a comma separated list of
+/// `library:content_hash`, merging their hits into one page
+pub fn federated_search(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let qp = &bcc.request.params.http.query;
+    let req = SearchRequest::from_query(qp)?;
+    let mut parts: Vec<IndexPart> = IndexPart::current(bcc)?.into_iter().collect();
+    for shard in qp
+        .get("shards")
+        .into_iter()
+        .flatten()
+        .flat_map(|s| s.split(','))
+    {
+        let (qlibid, qhash) = match shard.split_once(':') {
+            Some(s) => s,
+            None => {
+                return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(format!(
+                    "shard {shard} is not library:content_hash"
+                )))
+            }
+        };
+        match IndexPart::current_external(bcc, qlibid, qhash)? {
+            Some(part) => parts.push(part),
+            None => {
+                return bcc.make_error_with_kind(ErrorKinds::NotExist(format!(
+                    "shard {qhash} has not been indexed"
+                )))
+            }
+        }
+    }
+    if parts.is_empty() {
+        return bcc
+            .make_error_with_kind(ErrorKinds::NotExist("no index part to search".to_string()));
+    }
+    let results = FederatedSearcher::new(bcc, config.fields, parts).search(&req)?;
+    success(bcc, json!(results))
+}
+
+/// suggest completes the `prefix` query parameter with up to `limit` values of the suggested `field`, the
+/// first suggested field of the config by default, and offers a spelling correction of it
+pub fn suggest(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let qp = &bcc.request.params.http.query;
+    let first = |k: &str| qp.get(k).and_then(|v| v.first()).cloned();
+    let prefix = match first("prefix").filter(|p| !p.trim().is_empty()) {
+        Some(p) => p,
+        None => {
+            return bcc
+                .make_error_with_kind(ErrorKinds::BadHttpParams("prefix is required".to_string()))
+        }
+    };
+    let field = match first("field").or_else(|| suggested_fields(&config.fields).first().cloned()) {
+        Some(f) => f,
+        None => {
+            return bcc.make_error_with_kind(ErrorKinds::Invalid(
+                "no field is configured for suggestions".to_string(),
+            ))
+        }
+    };
+    let limit = match first("limit") {
+        Some(l) => l
+            .parse()
+            .map_err(|_| ErrorKinds::BadHttpParams(format!("limit must be a number, got {l}")))?,
+        None => DEFAULT_SUGGEST_LIMIT,
+    };
+    let indexer = restore_current(bcc, config)?;
+    let suggester = Suggester::new(bcc, &indexer.schema)?;
+    let completions = suggester.complete(&field, &prefix, limit)?;
+    let did_you_mean = suggester.did_you_mean(&field, &prefix)?;
+    success(
+        bcc,
+        json!({
+            "field" : field,
+            "completions" : completions,
+            "did_you_mean" : did_you_mean,
+        }),
+    )
+}
+
+/// vector_search returns the `k` documents whose `field` embedding is closest to `vector`, a JSON array of
+/// numbers, the first vector field of the config by default. When a `query` (or `q`) is given, only the
+/// documents matching it are considered.
+pub fn vector_search(bcc: &mut BitcodeContext) -> CallResult {
+    let config = IndexerConfig::from_meta(bcc)?;
+    let qp = &bcc.request.params.http.query;
+    let first = |k: &str| qp.get(k).and_then(|v| v.first()).cloned();
+    let vector: Vec<f32> = match first("vector").map(|v| serde_json::from_str(&v)) {
+        Some(Ok(v)) => v,
+        _ => {
+            return bcc.make_error_with_kind(ErrorKinds::BadHttpParams(
+                "vector must be a JSON array of numbers".to_string(),
+            ))
+        }
+    };
+    let fields = vector_fields(&config.fields)?;
+    let field = match first("field") {
+        Some(f) => f,
+        None => match fields.first() {
+            Some((f, _)) => f.name.clone(),
+            None => {
+                return bcc.make_error_with_kind(ErrorKinds::Invalid(
+                    "no vector field is configured".to_string(),
+                ))
+            }
+        },
+    };
+    let k = match first("k") {
+        Some(k) => k
+            .parse()
+            .map_err(|_| ErrorKinds::BadHttpParams(format!("k must be a number, got {k}")))?,
+        None => DEFAULT_VECTOR_K,
+    };
+    let current = IndexState::load(bcc)?
+        .current
+        .ok_or_else(|| ErrorKinds::NotExist("the content has not been indexed".to_string()))?;
+    let part = match current.vector_parts.get(&field) {
+        Some(p) => p.clone(),
+        None => {
+            return bcc.make_error_with_kind(ErrorKinds::NotExist(format!(
+                "no vector index for field {field}"
+            )))
+        }
+    };
+    let filter = if qp.contains_key("query") || qp.contains_key("q") {
+        let req = SearchRequest::from_query(qp)?
+            .offset(0)
+            .limit(DEFAULT_FILTER_LIMIT);
+        let indexer = restore_current(bcc, config)?;
+        Some(keyword_filter(&Searcher::new(bcc, &indexer.schema)?, &req)?)
+    } else {
+        None
+    };
+    let index = VectorIndex::load(bcc, &bcc.request.q_info.hash, &part)?;
+    let hits = index.search(&vector, k, filter.as_ref())?;
+    success(
+        bcc,
+        json!({
+            "field" : field,
+            "hits" : hits,
+        }),
+    )
+}
+
+/// rollback makes the previous index part current again
+pub fn rollback(bcc: &mut BitcodeContext) -> CallResult {
+    let result = rollback_index(bcc)?;
+    success(bcc, result)
+}
@@ -0,0 +1,260 @@
+//! Indexes built from an index config <br>
+//! An [Indexer] holds the schema made of the fields of an [IndexerConfig](crate::search::IndexerConfig),
+//! plus the [UID_FIELD] and [DATA_FIELD] every document carries and the companion fields of the fields
+//! configured for [suggestions](crate::search::suggest), and the directory of the index on the node.
+//! Vector fields are left to the [vector indexes](crate::search::vector). It is either created empty or
+//! restored from the part an earlier index was archived to, and a [DocumentWriter] indexes the documents
+//! extracted from crawled objects, replacing their earlier version.
+//! ```rust
+//! use elvwasm::search::{document_fields, Indexer, IndexerConfig};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let indexer = Indexer::new(bcc, config.fields.clone())?;
+//!   let meta = serde_json::json!({"title" : "The Old Man and the Sea"});
+//!   let mut writer = indexer.writer(bcc)?;
+//!   writer.index("iq__abc", &meta, &document_fields(&meta, &config.fields))?;
+//!   writer.commit()?;
+//!   let part_hash = indexer.archive(bcc)?;
+//!   Ok(part_hash.into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    completion_field, extract_body, suggest_entries, suggest_field, suggested_fields, FieldConfig,
+    FieldEntry, FieldValue, IndexWriter, Schema, SchemaBuilder, TextOptions, VECTOR_FIELD_TYPE,
+};
+use crate::{BitcodeContext, ErrorKinds};
+
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Field holding the unique id of each indexed document
+pub const UID_FIELD: &str = "uid";
+/// Field holding the metadata a document was extracted from
+pub const DATA_FIELD: &str = "data";
+
+pub struct Indexer {
+    /// directory of the index on the node
+    pub dir: String,
+    pub fields: Vec<FieldConfig>,
+    pub schema: Schema,
+}
+
+impl Indexer {
+    /// new creates an empty index with the schema of the fields
+    pub fn new(
+        bcc: &BitcodeContext,
+        fields: Vec<FieldConfig>,
+    ) -> Result<Indexer, Box<dyn Error + Send + Sync>> {
+        let res = bcc.new_index_builder(json!({"directory" : "index"}))?;
+        let dir = index_dir(&res).ok_or_else(|| {
+            ErrorKinds::BadHttpParams("could not find dir in new_index_builder return".to_string())
+        })?;
+        Indexer::with_schema(bcc, dir, fields)
+    }
+
+    /// restore restores the index archived in a part of the content, to be updated or searched
+    pub fn restore(
+        bcc: &BitcodeContext,
+        content_hash: &str,
+        part_hash: &str,
+        fields: Vec<FieldConfig>,
+    ) -> Result<Indexer, Box<dyn Error + Send + Sync>> {
+        let res = bcc.restore_index_from_part(content_hash, part_hash)?;
+        let dir = index_dir(&res).ok_or_else(|| {
+            ErrorKinds::BadHttpParams(format!(
+                "could not find dir restoring index part {part_hash}"
+            ))
+        })?;
+        Indexer::with_schema(bcc, dir, fields)
+    }
+
+    fn with_schema(
+        bcc: &BitcodeContext,
+        dir: String,
+        fields: Vec<FieldConfig>,
+    ) -> Result<Indexer, Box<dyn Error + Send + Sync>> {
+        let mut builder = SchemaBuilder::new(bcc)
+            .text(UID_FIELD, TextOptions::string().stored())
+            .text(DATA_FIELD, TextOptions::text().unindexed().stored());
+        let suggested = suggested_fields(&fields);
+        for field_config in &fields {
+            if field_config.field_type == VECTOR_FIELD_TYPE {
+                continue;
+            }
+            builder = builder.field(FieldEntry::from_config(
+                &field_config.name,
+                &field_config.field_type,
+                &field_config.options,
+            )?);
+            if suggested.contains(&field_config.name) {
+                for entry in suggest_entries(field_config)? {
+                    builder = builder.field(entry);
+                }
+            }
+        }
+        let schema = builder.build()?;
+        Ok(Indexer {
+            dir,
+            fields,
+            schema,
+        })
+    }
+
+    /// writer returns a [DocumentWriter] adding to the index
+    pub fn writer<'a>(
+        &'a self,
+        bcc: &'a BitcodeContext,
+    ) -> Result<DocumentWriter<'a>, Box<dyn Error + Send + Sync>> {
+        Ok(DocumentWriter {
+            writer: IndexWriter::new(bcc, &self.schema)?,
+            suggested: suggested_fields(&self.fields),
+            vectors: self
+                .fields
+                .iter()
+                .filter(|f| f.field_type == VECTOR_FIELD_TYPE)
+                .map(|f| f.name.clone())
+                .collect(),
+        })
+    }
+
+    /// archive archives the index directory to a new part of the content, returning the part hash
+    pub fn archive(&self, bcc: &BitcodeContext) -> Result<String, Box<dyn Error + Send + Sync>> {
+        part_hash(&bcc.archive_index_to_part(&self.dir)?)
+    }
+}
+
+// the body of a search extension reply, or its result when it has no http body
+fn reply_result(res: &[u8]) -> Option<Value> {
+    let v: Value = serde_json::from_slice(res).ok()?;
+    extract_body(v.clone())
+        .or_else(|| v.get("result").cloned())
+        .or(Some(v))
+}
+
+// the index directory named in the reply of new_index_builder or restore_index_from_part
+fn index_dir(res: &[u8]) -> Option<String> {
+    let body = reply_result(res)?;
+    let dir = body
+        .get("dir")
+        .or_else(|| body.get("directory"))?
+        .as_str()?;
+    Some(dir.to_string())
+}
+
+// the part hash in the reply of archive_index_to_part
+fn part_hash(res: &[u8]) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let body = reply_result(res).unwrap_or_default();
+    let hash = match &body {
+        Value::String(s) => Some(s.as_str()),
+        _ => ["part_hash", "qphash", "hash"]
+            .iter()
+            .find_map(|k| body.get(*k).and_then(|h| h.as_str())),
+    };
+    match hash {
+        Some(h) => Ok(h.to_string()),
+        None => Err(Box::new(ErrorKinds::BadHttpParams(format!(
+            "could not find the part hash in {body}"
+        )))),
+    }
+}
+
+/// Writes the documents of crawled objects to an [Indexer]'s index, one per object id
+pub struct DocumentWriter<'a> {
+    writer: IndexWriter<'a>,
+    // fields whose values are copied to their suggestion companion fields
+    suggested: Vec<String>,
+    // vector fields, indexed apart
+    vectors: Vec<String>,
+}
+
+impl DocumentWriter<'_> {
+    /// index replaces the document of an object with one holding `data` and the extracted `fields`,
+    /// `{"field" : value or [values]}`, the values of suggested fields also going to their companion fields
+    pub fn index(
+        &mut self,
+        uid: &str,
+        data: &Value,
+        fields: &Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let fields = match fields.as_object() {
+            Some(f) => f,
+            None => {
+                return Err(Box::new(ErrorKinds::Invalid(format!(
+                    "fields for {uid} must be an object"
+                ))))
+            }
+        };
+        self.remove(uid)?;
+        let mut doc = self.writer.document()?;
+        doc.add_text(UID_FIELD, uid)?
+            .add_text(DATA_FIELD, &data.to_string())?;
+        for (name, value) in fields {
+            if self.vectors.contains(name) {
+                continue;
+            }
+            doc.add_json(name, value)?;
+            if self.suggested.contains(name) {
+                doc.add_json(&suggest_field(name), value)?
+                    .add_json(&completion_field(name), value)?;
+            }
+        }
+        self.writer.add_document(doc)
+    }
+
+    /// remove deletes the document of an object
+    pub fn remove(&mut self, uid: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.writer.delete_term(UID_FIELD, &FieldValue::text(uid))
+    }
+
+    pub fn commit(&mut self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        self.writer.commit()
+    }
+
+    pub fn rollback(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.writer.rollback()
+    }
+
+    /// transaction runs f and commits, or rolls back and returns f's error should it fail, like
+    /// [IndexWriter::transaction]
+    pub fn transaction<F>(&mut self, f: F) -> Result<usize, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Box<dyn Error + Send + Sync>>,
+    {
+        match f(self) {
+            Ok(()) => self.commit(),
+            Err(e) => Err(self.writer.rollback_after(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_parsing() {
+        let dir = br#"{"result" : {"http" : {"body" : {"dir" : "/tmp/idx"}}}}"#;
+        assert_eq!(Some("/tmp/idx".to_string()), index_dir(dir));
+        assert_eq!(
+            Some("/tmp/restored".to_string()),
+            index_dir(br#"{"result" : {"directory" : "/tmp/restored"}}"#)
+        );
+        assert_eq!(None, index_dir(br#"{"result" : {}}"#));
+        assert_eq!(
+            "hqp_abc",
+            part_hash(br#"{"http" : {"body" : "hqp_abc"}}"#).unwrap()
+        );
+        assert_eq!(
+            "hqp_def",
+            part_hash(br#"{"result" : {"part_hash" : "hqp_def"}}"#).unwrap()
+        );
+        assert!(part_hash(br#"{"result" : {}}"#).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Typed access to the fabric's Tantivy based search extension <br>
+//! The raw host calls live on the [BitcodeContext](crate::BitcodeContext) (see `bccontext_search.rs`); this
+//! module wraps them in typed builders and results, and builds indexes of content from an index config:
+//! crawling the content, extracting the documents' fields, archiving the index to a part and keeping it up
+//! to date. The [handlers] are ready to be registered by a bitcode module.
+
+pub mod aggregations;
+pub mod analyzer;
+pub mod config;
+pub mod crawler;
+pub mod extract;
+pub mod federated;
+pub mod handlers;
+pub mod indexer;
+pub mod paths;
+pub mod query;
+pub mod results;
+pub mod schema;
+pub mod suggest;
+pub mod update;
+pub mod vector;
+pub mod writer;
+
+pub use self::aggregations::*;
+pub use self::analyzer::*;
+pub use self::config::*;
+pub use self::crawler::*;
+pub use self::extract::*;
+pub use self::federated::*;
+pub use self::indexer::*;
+pub use self::paths::*;
+pub use self::query::*;
+pub use self::results::*;
+pub use self::schema::*;
+pub use self::suggest::*;
+pub use self::update::*;
+pub use self::vector::*;
+pub use self::writer::*;
+
+use crate::ErrorKinds;
+
+use serde_json::Value;
+use std::error::Error;
+
+/// extract_body digs the body out of a search extension reply, which arrives either as
+/// `{"result" : {"http" : {"body" : ...}}}` or as `{"http" : {"body" : ...}}`
+pub fn extract_body(v: Value) -> Option<Value> {
+    let obj = v.as_object()?;
+    match obj.get("result") {
+        Some(res) => res.get("http")?.get("body").cloned(),
+        None => obj.get("http")?.get("body").cloned(),
+    }
+}
+
+// decodes a search extension reply and extracts its body
+pub(crate) fn reply_body(res: &[u8]) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    let v: Value = serde_json::from_slice(res)?;
+    if let Some(err) = v.get("error") {
+        return Err(Box::new(ErrorKinds::Other(format!(
+            "search extension failed: {err}"
+        ))));
+    }
+    extract_body(v.clone()).ok_or_else(|| {
+        ErrorKinds::BadHttpParams(format!("no body in search extension reply {v}")).into()
+    })
+}
+
+// extracts an unsigned integer member of a search extension reply body
+pub(crate) fn reply_u64(res: &[u8], key: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let body = reply_body(res)?;
+    body.get(key).and_then(|v| v.as_u64()).ok_or_else(|| {
+        ErrorKinds::BadHttpParams(format!("could not find key {key} in {body}")).into()
+    })
+}
@@ -0,0 +1,244 @@
+//! Metadata paths <br>
+//! A [Prefix] parses the jpaths of index configs and policies, wildcards included, and expands them
+//! against metadata into the concrete paths of the values they match.
+//! ```rust
+//! use elvwasm::search::Prefix;
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let prefix = Prefix::from_jpath("/offerings/*/items[*]/title");
+//!   let meta: serde_json::Value = serde_json::from_slice(&bcc.sqmd_get_json(&prefix.literal_jpath())?)?;
+//!   let titles: Vec<String> = prefix.expand(&meta, 1).into_iter().map(|(path, _)| path).collect();
+//!   Ok(titles.join(",").into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use serde_json::Value;
+use std::cmp::min;
+
+/// field_jpath converts a field path written with dots, as in `site_map.searchables.*.title`, to the jpath
+/// [Prefix::from_jpath] parses; paths already starting with `/` are returned as they are
+pub fn field_jpath(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path.replace('.', "/"))
+    }
+}
+
+/// An element of a metadata path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrefixValue {
+    ObjectItemAny,
+    ArrayItemAny,
+    Key(String),
+}
+
+impl PrefixValue {
+    pub fn is_key(&self) -> bool {
+        matches!(self, PrefixValue::Key(..))
+    }
+
+    /// select returns the children of `v`, found at `path`, that the element matches along with their paths
+    pub fn select<'v>(&self, path: &str, v: &'v Value) -> Vec<(String, &'v Value)> {
+        let base = path.trim_end_matches('/');
+        match (self, v) {
+            (PrefixValue::Key(k), Value::Object(o)) => o
+                .get(k)
+                .map(|child| vec![(format!("{base}/{k}"), child)])
+                .unwrap_or_default(),
+            (PrefixValue::Key(k), Value::Array(a)) => k
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| a.get(i))
+                .map(|child| vec![(format!("{base}[{k}]"), child)])
+                .unwrap_or_default(),
+            (PrefixValue::ObjectItemAny, Value::Object(o)) => o
+                .iter()
+                .map(|(k, child)| (format!("{base}/{k}"), child))
+                .collect(),
+            (PrefixValue::ArrayItemAny, Value::Array(a)) => a
+                .iter()
+                .enumerate()
+                .map(|(i, child)| (format!("{base}[{i}]"), child))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A metadata path such as `/offerings/*/items[*]/title`, `*` matching any key of an object and `[*]`
+/// any item of an array
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Prefix {
+    pub(crate) prefix: Vec<PrefixValue>,
+}
+
+impl Prefix {
+    pub fn get(&self, i: usize) -> &PrefixValue {
+        &self.prefix[i]
+    }
+
+    pub fn values(&self) -> &[PrefixValue] {
+        &self.prefix
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefix.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty()
+    }
+
+    pub fn from_jpath(jpath: &str) -> Prefix {
+        let mut prefix: Vec<PrefixValue> = Vec::new();
+        let otokens: Vec<&str> = jpath.split('/').collect();
+        let mut start = 0;
+        if otokens.is_empty() {
+            start = 1;
+        }
+        for &otokens in &otokens[start..] {
+            let ltokens: Vec<&str> = otokens.split('[').collect();
+            if ltokens[0] == "*" {
+                prefix.push(PrefixValue::ObjectItemAny)
+            } else if !ltokens[0].is_empty() {
+                prefix.push(PrefixValue::Key(ltokens[0].to_string()));
+            }
+
+            for &ltoken in &ltokens[1..] {
+                let index = ltoken.strip_suffix(']').unwrap_or(ltoken);
+                if index != "*" {
+                    prefix.push(PrefixValue::Key(index.to_string()));
+                } else {
+                    prefix.push(PrefixValue::ArrayItemAny)
+                }
+            }
+        }
+        Prefix { prefix }
+    }
+
+    pub fn to_jpath(&self) -> String {
+        let mut str_elems: Vec<String> = Vec::new();
+        for elem in &self.prefix {
+            match elem {
+                PrefixValue::Key(value) => str_elems.push(format!("/{value}")),
+                PrefixValue::ObjectItemAny => str_elems.push("/*".to_string()),
+                PrefixValue::ArrayItemAny => str_elems.push("[*]".to_string()),
+            }
+        }
+        if str_elems.is_empty() {
+            return "/".to_string();
+        }
+        str_elems.join("")
+    }
+
+    pub fn common_ancestor(prefix_1: Prefix, prefix_2: Prefix) -> Prefix {
+        let mut common_prefix: Vec<PrefixValue> = Vec::new();
+        let min_length = min(prefix_1.prefix.len(), prefix_2.prefix.len());
+        for i in 0..min_length {
+            let mut is_match = false;
+            let mut elem: Option<PrefixValue> = None;
+
+            if *prefix_1.get(i) == *prefix_2.get(i) {
+                is_match = true;
+                elem = Some(prefix_1.get(i).clone());
+            } else if *prefix_1.get(i) == PrefixValue::ObjectItemAny && prefix_2.get(i).is_key() {
+                is_match = true;
+                elem = Some(prefix_2.get(i).clone());
+            } else if *prefix_2.get(i) == PrefixValue::ObjectItemAny && prefix_1.get(i).is_key() {
+                is_match = true;
+                elem = Some(prefix_1.get(i).clone());
+            }
+            if !is_match {
+                break;
+            }
+            if let Some(e) = elem {
+                common_prefix.push(e)
+            }
+        }
+        Prefix {
+            prefix: common_prefix,
+        }
+    }
+
+    /// literal_jpath returns the path up to the first wildcard, the part that can be fetched directly
+    pub fn literal_jpath(&self) -> String {
+        Prefix {
+            prefix: self
+                .prefix
+                .iter()
+                .take_while(|p| p.is_key())
+                .cloned()
+                .collect(),
+        }
+        .to_jpath()
+    }
+
+    /// expand returns every value of `meta` matching the prefix along with its concrete path, where `meta`
+    /// is the metadata found at the first `skip` elements of the prefix
+    pub fn expand<'v>(&self, meta: &'v Value, skip: usize) -> Vec<(String, &'v Value)> {
+        let mut found = vec![(
+            Prefix {
+                prefix: self.prefix[..skip.min(self.prefix.len())].to_vec(),
+            }
+            .to_jpath(),
+            meta,
+        )];
+        for elem in self.prefix.iter().skip(skip) {
+            found = found
+                .into_iter()
+                .flat_map(|(path, v)| elem.select(&path, v))
+                .collect();
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_prefix_expand() {
+        let meta = json!({
+            "offerings" : {
+                "default" : {"items" : [{"t" : 1}, {"t" : 2}]},
+                "clear" : {"items" : []},
+            }
+        });
+        let prefix = Prefix::from_jpath("/offerings/*/items[*]/t");
+        assert_eq!("/offerings", prefix.literal_jpath());
+        assert_eq!("/offerings/*/items[*]/t", prefix.to_jpath());
+        let found: Vec<(String, Value)> = prefix
+            .expand(&meta["offerings"], 1)
+            .into_iter()
+            .map(|(p, v)| (p, v.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("/offerings/default/items[0]/t".to_string(), json!(1)),
+                ("/offerings/default/items[1]/t".to_string(), json!(2)),
+            ],
+            found
+        );
+        assert_eq!(
+            Prefix::from_jpath("/offerings/*/items[*]/t"),
+            Prefix::from_jpath(&field_jpath("offerings.*.items[*].t"))
+        );
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let common = Prefix::common_ancestor(
+            Prefix::from_jpath("/offerings/*/items"),
+            Prefix::from_jpath("/offerings/default/playout"),
+        );
+        assert_eq!("/offerings/default", common.to_jpath());
+    }
+}
@@ -0,0 +1,476 @@
+//! Typed search queries <br>
+//! A [Query] is built from term, phrase, prefix, fuzzy and range clauses combined with boolean
+//! must/should/must_not and boosts, and compiles either to the Tantivy query parser syntax, user input being
+//! escaped, or to a structured JSON query for the host when it uses clauses the parser syntax lacks.
+//! ```rust
+//! use elvwasm::search::{Query, SearchRequest};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let q = Query::boolean()
+//!     .must(Query::phrase("title", "old man"))
+//!     .should(Query::term("body", "sea").boost(2.0))
+//!     .must_not(Query::range("year").lt(1950));
+//!   assert_eq!(q.to_query_string(), r#"(+title:"old man" (body:sea)^2 -year:[* TO 1950})"#);
+//!   let req = SearchRequest::with_query(&q).limit(10);
+//!   Ok(req.query.into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+// characters with a meaning in the query parser syntax
+const SPECIAL_CHARS: &[char] = &[
+    '+', '-', '&', '|', '!', '(', ')', '{', '}', '[', ']', '^', '"', '~', '*', '?', ':', '\\', '/',
+    '<', '>', '=',
+];
+
+// words the query parser reads as operators
+const RESERVED_WORDS: &[&str] = &["AND", "OR", "NOT", "TO", "IN"];
+
+/// escape backslash escapes the characters of `text` the query parser would read as syntax, whitespace
+/// included, so that it is searched as a single term
+/// ```rust
+/// assert_eq!(elvwasm::search::escape("c++ (2023)"), r"c\+\+\ \(2023\)");
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL_CHARS.contains(&c) || c.is_whitespace() {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// a double quoted string of the query parser syntax
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// One end of a [Query::Range]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RangeBound {
+    Unbounded,
+    Included(Value),
+    Excluded(Value),
+}
+
+impl Default for RangeBound {
+    fn default() -> Self {
+        RangeBound::Unbounded
+    }
+}
+
+impl RangeBound {
+    fn value(&self) -> String {
+        match self {
+            RangeBound::Unbounded => "*".to_string(),
+            RangeBound::Included(v) | RangeBound::Excluded(v) => match v {
+                Value::String(s) => quote(s),
+                Value::Number(_) | Value::Bool(_) => v.to_string(),
+                _ => quote(&v.to_string()),
+            },
+        }
+    }
+}
+
+/// A search query. Clauses with no field (built with an empty field name) search the default fields of
+/// the request. The JSON form, as serialized, is the structured query sent to the host.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Query {
+    /// matches every document
+    All,
+    Term {
+        field: Option<String>,
+        text: String,
+    },
+    /// the terms in order, at most `slop` positions apart
+    Phrase {
+        field: Option<String>,
+        terms: Vec<String>,
+        #[serde(default)]
+        slop: u32,
+    },
+    Prefix {
+        field: Option<String>,
+        prefix: String,
+    },
+    /// terms within `distance` edits of `text`
+    Fuzzy {
+        field: Option<String>,
+        text: String,
+        distance: u8,
+        #[serde(default)]
+        transpositions: bool,
+    },
+    Range {
+        field: String,
+        #[serde(default)]
+        lower: RangeBound,
+        #[serde(default)]
+        upper: RangeBound,
+    },
+    Boolean {
+        #[serde(default)]
+        must: Vec<Query>,
+        #[serde(default)]
+        should: Vec<Query>,
+        #[serde(default)]
+        must_not: Vec<Query>,
+    },
+    Boost {
+        query: Box<Query>,
+        boost: f32,
+    },
+}
+
+// an empty field name stands for the default fields
+fn field_opt(field: &str) -> Option<String> {
+    if field.is_empty() {
+        None
+    } else {
+        Some(field.to_string())
+    }
+}
+
+impl Query {
+    pub fn all() -> Query {
+        Query::All
+    }
+
+    pub fn term(field: &str, text: &str) -> Query {
+        Query::Term {
+            field: field_opt(field),
+            text: text.to_string(),
+        }
+    }
+
+    /// phrase searches the whitespace separated words of `text` in order
+    pub fn phrase(field: &str, text: &str) -> Query {
+        Query::Phrase {
+            field: field_opt(field),
+            terms: text.split_whitespace().map(|t| t.to_string()).collect(),
+            slop: 0,
+        }
+    }
+
+    pub fn prefix(field: &str, prefix: &str) -> Query {
+        Query::Prefix {
+            field: field_opt(field),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    pub fn fuzzy(field: &str, text: &str, distance: u8) -> Query {
+        Query::Fuzzy {
+            field: field_opt(field),
+            text: text.to_string(),
+            distance,
+            transpositions: true,
+        }
+    }
+
+    /// range starts an unbounded range on a field, to be narrowed with [gt](Query::gt), [gte](Query::gte),
+    /// [lt](Query::lt) and [lte](Query::lte)
+    pub fn range(field: &str) -> Query {
+        Query::Range {
+            field: field.to_string(),
+            lower: RangeBound::Unbounded,
+            upper: RangeBound::Unbounded,
+        }
+    }
+
+    /// boolean starts an empty boolean query, to be filled with [must](Query::must),
+    /// [should](Query::should) and [must_not](Query::must_not)
+    pub fn boolean() -> Query {
+        Query::Boolean {
+            must: Vec::new(),
+            should: Vec::new(),
+            must_not: Vec::new(),
+        }
+    }
+
+    /// slop sets how far apart the terms of a phrase may be
+    pub fn slop(mut self, n: u32) -> Query {
+        if let Query::Phrase { slop, .. } = &mut self {
+            *slop = n;
+        }
+        self
+    }
+
+    fn bound(mut self, lower_bound: Option<RangeBound>, upper_bound: Option<RangeBound>) -> Query {
+        if let Query::Range { lower, upper, .. } = &mut self {
+            if let Some(b) = lower_bound {
+                *lower = b;
+            }
+            if let Some(b) = upper_bound {
+                *upper = b;
+            }
+        }
+        self
+    }
+
+    pub fn gt<V: Into<Value>>(self, v: V) -> Query {
+        self.bound(Some(RangeBound::Excluded(v.into())), None)
+    }
+
+    pub fn gte<V: Into<Value>>(self, v: V) -> Query {
+        self.bound(Some(RangeBound::Included(v.into())), None)
+    }
+
+    pub fn lt<V: Into<Value>>(self, v: V) -> Query {
+        self.bound(None, Some(RangeBound::Excluded(v.into())))
+    }
+
+    pub fn lte<V: Into<Value>>(self, v: V) -> Query {
+        self.bound(None, Some(RangeBound::Included(v.into())))
+    }
+
+    // the boolean query clauses are added to, the query itself becoming its first must clause when it is
+    // not a boolean query
+    fn into_boolean(self) -> Query {
+        match self {
+            Query::Boolean { .. } => self,
+            q => Query::Boolean {
+                must: vec![q],
+                should: Vec::new(),
+                must_not: Vec::new(),
+            },
+        }
+    }
+
+    /// must adds a clause every hit matches
+    pub fn must(self, q: Query) -> Query {
+        let mut b = self.into_boolean();
+        if let Query::Boolean { must, .. } = &mut b {
+            must.push(q);
+        }
+        b
+    }
+
+    /// should adds a clause that raises the score of the hits matching it
+    pub fn should(self, q: Query) -> Query {
+        let mut b = self.into_boolean();
+        if let Query::Boolean { should, .. } = &mut b {
+            should.push(q);
+        }
+        b
+    }
+
+    /// must_not adds a clause no hit matches
+    pub fn must_not(self, q: Query) -> Query {
+        let mut b = self.into_boolean();
+        if let Query::Boolean { must_not, .. } = &mut b {
+            must_not.push(q);
+        }
+        b
+    }
+
+    /// boost multiplies the score of the hits matching the query
+    pub fn boost(self, boost: f32) -> Query {
+        Query::Boost {
+            query: Box::new(self),
+            boost,
+        }
+    }
+
+    /// requires_json tells whether the query uses clauses the query parser syntax cannot express, fuzzy
+    /// terms, and has to be sent as a structured query
+    pub fn requires_json(&self) -> bool {
+        match self {
+            Query::Fuzzy { .. } => true,
+            Query::Boolean {
+                must,
+                should,
+                must_not,
+            } => must
+                .iter()
+                .chain(should)
+                .chain(must_not)
+                .any(|q| q.requires_json()),
+            Query::Boost { query, .. } => query.requires_json(),
+            _ => false,
+        }
+    }
+
+    /// to_json returns the structured form of the query
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// terms returns the lowercased words the hits are expected to contain, those of must_not clauses aside
+    pub fn terms(&self) -> Vec<String> {
+        let words = |s: &str| -> Vec<String> {
+            s.split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_lowercase())
+                .collect()
+        };
+        match self {
+            Query::Term { text, .. } | Query::Fuzzy { text, .. } => words(text),
+            Query::Prefix { prefix, .. } => words(prefix),
+            Query::Phrase { terms, .. } => terms.iter().flat_map(|t| words(t)).collect(),
+            Query::Boolean { must, should, .. } => {
+                must.iter().chain(should).flat_map(|q| q.terms()).collect()
+            }
+            Query::Boost { query, .. } => query.terms(),
+            Query::All | Query::Range { .. } => Vec::new(),
+        }
+    }
+
+    /// to_query_string compiles the query to the Tantivy query parser syntax, fuzzy terms being written
+    /// `term~distance`
+    pub fn to_query_string(&self) -> String {
+        let field = |f: &Option<String>| match f {
+            Some(f) => format!("{}:", escape(f)),
+            None => String::new(),
+        };
+        match self {
+            Query::All => "*".to_string(),
+            Query::Term { field: f, text } => {
+                if text.is_empty() || RESERVED_WORDS.contains(&text.as_str()) {
+                    format!("{}{}", field(f), quote(text))
+                } else {
+                    format!("{}{}", field(f), escape(text))
+                }
+            }
+            Query::Phrase {
+                field: f,
+                terms,
+                slop,
+            } => {
+                let phrase = quote(&terms.join(" "));
+                match slop {
+                    0 => format!("{}{phrase}", field(f)),
+                    n => format!("{}{phrase}~{n}", field(f)),
+                }
+            }
+            Query::Prefix { field: f, prefix } => format!("{}{}*", field(f), quote(prefix)),
+            Query::Fuzzy {
+                field: f,
+                text,
+                distance,
+                ..
+            } => format!("{}{}~{distance}", field(f), escape(text)),
+            Query::Range {
+                field: f,
+                lower,
+                upper,
+            } => {
+                let open = match lower {
+                    RangeBound::Excluded(_) => '{',
+                    _ => '[',
+                };
+                let close = match upper {
+                    RangeBound::Excluded(_) => '}',
+                    _ => ']',
+                };
+                format!(
+                    "{}:{open}{} TO {}{close}",
+                    escape(f),
+                    lower.value(),
+                    upper.value()
+                )
+            }
+            Query::Boolean {
+                must,
+                should,
+                must_not,
+            } => {
+                let mut clauses: Vec<String> = Vec::new();
+                clauses.extend(must.iter().map(|q| format!("+{}", q.to_query_string())));
+                clauses.extend(should.iter().map(|q| q.to_query_string()));
+                if clauses.is_empty() {
+                    // only negative clauses match nothing on their own
+                    clauses.push("*".to_string());
+                }
+                clauses.extend(must_not.iter().map(|q| format!("-{}", q.to_query_string())));
+                format!("({})", clauses.join(" "))
+            }
+            Query::Boost { query, boost } => format!("({})^{boost}", query.to_query_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_query_string() {
+        assert_eq!("title:sea", Query::term("title", "sea").to_query_string());
+        assert_eq!(
+            r#"sea\:\ \"man\""#,
+            Query::term("", r#"sea: "man""#).to_query_string()
+        );
+        assert_eq!(
+            r#"title:"AND""#,
+            Query::term("title", "AND").to_query_string()
+        );
+        assert_eq!(
+            r#"body:"old \"man\""~2"#,
+            Query::phrase("body", r#"old  "man""#)
+                .slop(2)
+                .to_query_string()
+        );
+        assert_eq!(
+            r#"title:"se"*"#,
+            Query::prefix("title", "se").to_query_string()
+        );
+        assert_eq!(
+            "title:sae~1",
+            Query::fuzzy("title", "sae", 1).to_query_string()
+        );
+        assert_eq!(
+            r#"date:{"2020-01-01" TO *]"#,
+            Query::range("date").gt("2020-01-01").to_query_string()
+        );
+        assert_eq!(
+            "year:[1950 TO 1960}",
+            Query::range("year").gte(1950).lt(1960).to_query_string()
+        );
+        assert_eq!(
+            "(* -title:sea)",
+            Query::boolean()
+                .must_not(Query::term("title", "sea"))
+                .to_query_string()
+        );
+        assert_eq!(
+            "(+title:old +(body:sea)^1.5)",
+            Query::term("title", "old")
+                .must(Query::term("body", "sea").boost(1.5))
+                .to_query_string()
+        );
+    }
+
+    #[test]
+    fn test_query_json_and_terms() {
+        let q = Query::boolean()
+            .should(Query::fuzzy("title", "Sae", 1))
+            .must_not(Query::term("body", "shark"));
+        assert!(q.requires_json());
+        assert!(!Query::term("title", "sea").boost(2.0).requires_json());
+        assert_eq!(
+            json!({"boolean" : {
+                "must" : [],
+                "should" : [{"fuzzy" : {"field" : "title", "text" : "Sae", "distance" : 1, "transpositions" : true}}],
+                "must_not" : [{"term" : {"field" : "body", "text" : "shark"}}],
+            }}),
+            q.to_json()
+        );
+        let back: Query = serde_json::from_value(q.to_json()).unwrap();
+        assert_eq!(q, back);
+        assert_eq!(vec!["sae".to_string()], q.terms());
+        assert_eq!(json!("all"), Query::all().to_json());
+    }
+}
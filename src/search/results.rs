@@ -0,0 +1,656 @@
+//! Typed search requests and results <br>
+//! A [Searcher] opens the index whose [Schema] has been built (typically right after
+//! [BitcodeContext::restore_index_from_part]) and runs [SearchRequest]s, returning [SearchResults] with the
+//! total hit count and, per hit, its score, document address, stored fields and highlighted snippets.
+//! ```rust
+//! use elvwasm::search::{SchemaBuilder, SearchRequest, Searcher, SortOrder, TextOptions};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   bcc.restore_index_from_part("hq__abc", "hqp_def")?;
+//!   let schema = SchemaBuilder::new(bcc)
+//!     .text("title", TextOptions::text().stored())
+//!     .text("body", TextOptions::text().stored())
+//!     .build()?;
+//!   let results = Searcher::new(bcc, &schema)?.search(
+//!     &SearchRequest::new("old man")
+//!       .limit(10)
+//!       .offset(20)
+//!       .snippets(&["body"]),
+//!   )?;
+//!   Ok(serde_json::to_vec(&results)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    aggregations_from_host, aggregations_to_host, field_stats_aggregations, reply_body,
+    Aggregation, AggregationResult, FieldType, Query, Schema, SUGGEST_ANALYZER,
+};
+use crate::{BitcodeContext, ErrorKinds};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Number of hits returned when a [SearchRequest] sets no limit
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Length, in characters, of snippets highlighted by the client
+pub const DEFAULT_SNIPPET_CHARS: usize = 150;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Desc
+    }
+}
+
+/// Orders hits by the value of a fast field instead of by score
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SortBy {
+    pub field: String,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// A query along with how its results are to be paged, ordered and highlighted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SearchRequest {
+    /// the query in the query parser syntax
+    pub query: String,
+    /// the query it was compiled from, when built with [SearchRequest::with_query]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured: Option<Query>,
+    /// the fields searched by terms lacking a `field:` prefix; empty means every indexed text field
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub sort: Option<SortBy>,
+    /// text fields for which highlighted snippets are returned
+    #[serde(default)]
+    pub snippets: Vec<String>,
+    /// aggregations returned along with the hits, by name
+    #[serde(default)]
+    pub aggregations: BTreeMap<String, Aggregation>,
+}
+
+fn default_limit() -> usize {
+    DEFAULT_SEARCH_LIMIT
+}
+
+impl SearchRequest {
+    pub fn new(query: &str) -> SearchRequest {
+        SearchRequest {
+            query: query.to_string(),
+            structured: None,
+            fields: Vec::new(),
+            limit: DEFAULT_SEARCH_LIMIT,
+            offset: 0,
+            sort: None,
+            snippets: Vec::new(),
+            aggregations: BTreeMap::new(),
+        }
+    }
+
+    /// with_query builds a request from a typed [Query]
+    pub fn with_query(q: &Query) -> SearchRequest {
+        SearchRequest {
+            structured: Some(q.clone()),
+            ..SearchRequest::new(&q.to_query_string())
+        }
+    }
+
+    /// from_query builds a request from the `query` (or `q`, a JSON [Query]), `fields`, `limit`, `offset`, `sort` (a field name, prefixed
+    /// by `-` for descending order), `snippets` and `aggs` query parameters, `fields` and `snippets` being comma
+    /// separated lists and `aggs` a JSON object of named [Aggregation]s
+    pub fn from_query(
+        qp: &std::collections::HashMap<String, Vec<String>>,
+    ) -> Result<SearchRequest, Box<dyn Error + Send + Sync>> {
+        let first = |k: &str| qp.get(k).and_then(|v| v.first()).map(|s| s.as_str());
+        let list = |k: &str| -> Vec<String> {
+            qp.get(k)
+                .into_iter()
+                .flatten()
+                .flat_map(|s| s.split(','))
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        };
+        let number = |k: &str, default: usize| -> Result<usize, Box<dyn Error + Send + Sync>> {
+            match first(k) {
+                Some(s) => s.parse().map_err(|_| {
+                    ErrorKinds::BadHttpParams(format!("{k} must be a number, got {s}")).into()
+                }),
+                None => Ok(default),
+            }
+        };
+        let mut req = match first("q") {
+            Some(q) => SearchRequest::with_query(&serde_json::from_str(q).map_err(|e| {
+                ErrorKinds::BadHttpParams(format!("q is not a structured query: {e}"))
+            })?),
+            None => SearchRequest::new(first("query").unwrap_or_default()),
+        };
+        req.fields = list("fields");
+        req.limit = number("limit", DEFAULT_SEARCH_LIMIT)?;
+        req.offset = number("offset", 0)?;
+        req.snippets = list("snippets");
+        if let Some(a) = first("aggs") {
+            req.aggregations = serde_json::from_str(a).map_err(|e| {
+                ErrorKinds::BadHttpParams(format!("aggs is not a set of aggregations: {e}"))
+            })?;
+        }
+        if let Some(s) = first("sort").filter(|s| !s.is_empty()) {
+            req = match s.strip_prefix('-') {
+                Some(f) => req.sort_by(f, SortOrder::Desc),
+                None => req.sort_by(s, SortOrder::Asc),
+            };
+        }
+        Ok(req)
+    }
+
+    pub fn fields(mut self, fields: &[&str]) -> SearchRequest {
+        self.fields = fields.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> SearchRequest {
+        self.limit = limit;
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> SearchRequest {
+        self.offset = offset;
+        self
+    }
+
+    pub fn sort_by(mut self, field: &str, order: SortOrder) -> SearchRequest {
+        self.sort = Some(SortBy {
+            field: field.to_string(),
+            order,
+        });
+        self
+    }
+
+    pub fn snippets(mut self, fields: &[&str]) -> SearchRequest {
+        self.snippets = fields.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    pub fn aggregate(mut self, name: &str, agg: Aggregation) -> SearchRequest {
+        self.aggregations.insert(name.to_string(), agg);
+        self
+    }
+
+    /// with_field_stats adds the aggregations asked for by the `stats` of the schema's fields, see
+    /// [Aggregation::for_field]
+    pub fn with_field_stats(mut self, schema: &Schema) -> SearchRequest {
+        self.aggregations.extend(field_stats_aggregations(schema));
+        self
+    }
+}
+
+/// The segment local address of a document
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DocAddress {
+    pub segment_ord: u32,
+    pub doc_id: u32,
+}
+
+/// A matching document
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub score: f64,
+    #[serde(default)]
+    pub address: Option<DocAddress>,
+    /// the index part the hit was found in, set by a [FederatedSearcher](crate::search::FederatedSearcher)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part: Option<String>,
+    /// stored field values, each field holding an array of its values
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+    /// highlighted snippets keyed by field, matches being wrapped in `<b></b>`
+    #[serde(default)]
+    pub snippets: BTreeMap<String, String>,
+}
+
+impl Hit {
+    // decodes a hit of the reply, {"score" : s, "address" : a, "doc" : {..}, "snippets" : {..}}, wrapping single
+    // stored values in an array
+    fn from_reply(h: ReplyHit) -> Hit {
+        let fields = h
+            .doc
+            .into_iter()
+            .map(|(k, v)| match v {
+                Value::Array(_) => (k, v),
+                _ => (k, json!([v])),
+            })
+            .collect();
+        Hit {
+            score: h.score,
+            address: h.address,
+            part: None,
+            fields,
+            snippets: h.snippets,
+        }
+    }
+
+    /// value returns the first stored value of a field
+    pub fn value(&self, field: &str) -> Option<&Value> {
+        match self.fields.get(field)? {
+            Value::Array(a) => a.first(),
+            v => Some(v),
+        }
+    }
+
+    /// text returns the first stored value of a field as a string
+    pub fn text(&self, field: &str) -> Option<&str> {
+        self.value(field)?.as_str()
+    }
+}
+
+/// One page of the hits of a search
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SearchResults {
+    /// the number of documents matching the query, across all pages
+    pub total_hits: u64,
+    pub offset: usize,
+    pub limit: usize,
+    pub hits: Vec<Hit>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+}
+
+// the body of a query_parser_search reply
+#[derive(Deserialize)]
+struct SearchReply {
+    total_hits: u64,
+    hits: Vec<ReplyHit>,
+    #[serde(default)]
+    aggregations: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ReplyHit {
+    score: f64,
+    #[serde(default)]
+    address: Option<DocAddress>,
+    #[serde(default)]
+    doc: Map<String, Value>,
+    #[serde(default)]
+    snippets: BTreeMap<String, String>,
+}
+
+impl SearchResults {
+    /// from_reply decodes the body of a `query_parser_search` reply for a request, an object holding the
+    /// `total_hits` matching the query, the `hits` and the requested `aggregations`.  The host returns the top
+    /// `offset + limit` hits, the page being cut here.  Aggregations missing from the reply are computed from
+    /// the hits returned.
+    pub fn from_reply(
+        body: &Value,
+        req: &SearchRequest,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let reply: SearchReply = serde_json::from_value(body.clone()).map_err(|e| {
+            ErrorKinds::BadHttpParams(format!("unexpected search reply {body}: {e}"))
+        })?;
+        let hits: Vec<Hit> = reply.hits.into_iter().map(Hit::from_reply).collect();
+        let aggregations =
+            aggregations_from_host(&req.aggregations, reply.aggregations.as_ref(), &hits)?;
+        let hits = hits.into_iter().skip(req.offset).take(req.limit).collect();
+        Ok(SearchResults {
+            total_hits: reply.total_hits,
+            offset: req.offset,
+            limit: req.limit,
+            hits,
+            aggregations,
+        })
+    }
+
+    /// has_more reports whether hits remain past this page
+    pub fn has_more(&self) -> bool {
+        ((self.offset + self.hits.len()) as u64) < self.total_hits
+    }
+
+    /// sort_by_field orders the hits of this page by the first stored value of a field, hits lacking it coming
+    /// last.  Only the page is sorted, the order across pages comes from the host, which [Searcher::search]
+    /// sends the request's sort to.
+    pub fn sort_by_field(&mut self, sort: &SortBy) {
+        self.hits
+            .sort_by(|a, b| match (a.value(&sort.field), b.value(&sort.field)) {
+                (Some(x), Some(y)) => {
+                    let o = compare_values(x, y);
+                    match sort.order {
+                        SortOrder::Asc => o,
+                        SortOrder::Desc => o.reverse(),
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            });
+    }
+
+    /// highlight fills in the snippets the host did not provide from the stored text of the fields
+    pub fn highlight(&mut self, fields: &[String], query: &str) {
+        let terms = query_terms(query);
+        for hit in &mut self.hits {
+            for f in fields {
+                if hit.snippets.contains_key(f) {
+                    continue;
+                }
+                if let Some(text) = hit.text(f) {
+                    let s = highlight(text, &terms, DEFAULT_SNIPPET_CHARS);
+                    hit.snippets.insert(f.clone(), s);
+                }
+            }
+        }
+    }
+}
+
+// orders numbers numerically and everything else by its string form
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        _ => match (a.as_str(), b.as_str()) {
+            (Some(x), Some(y)) => x.cmp(y),
+            _ => a.to_string().cmp(&b.to_string()),
+        },
+    }
+}
+
+// the lowercased words of a query, leaving out operators and field prefixes
+fn query_terms(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .map(|w| w.rsplit(':').next().unwrap_or(w))
+        .filter(|w| !matches!(*w, "AND" | "OR" | "NOT" | "TO"))
+        .flat_map(|w| w.split(|c: char| !c.is_alphanumeric()))
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+// escapes the characters with a meaning in HTML text and attribute values
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// highlight cuts a snippet of at most `max_chars` characters around the first occurrence of any of `terms` in
+/// `text`, wrapping every whole word matching a term (case insensitively) in `<b></b>`.  The text is HTML
+/// escaped, so the snippet can be inserted into a page as is.
+/// ```rust
+/// let s = elvwasm::search::highlight("The Old Man and the Sea", &["sea".to_string()], 12);
+/// assert_eq!(s, "…and the <b>Sea</b>");
+/// ```
+pub fn highlight(text: &str, terms: &[String], max_chars: usize) -> String {
+    let mut words: Vec<(usize, &str)> = Vec::new();
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), word_start) {
+            (true, None) => word_start = Some(i),
+            (false, Some(s)) => {
+                words.push((s, &text[s..i]));
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = word_start {
+        words.push((s, &text[s..]));
+    }
+    let is_match = |w: &str| terms.iter().any(|t| w.to_lowercase() == *t);
+    let first = words
+        .iter()
+        .find(|(_, w)| is_match(w))
+        .map(|(p, _)| *p)
+        .unwrap_or(0);
+    // center the window on the first match, snapping to character boundaries
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let first_char = chars.iter().position(|(b, _)| *b >= first).unwrap_or(0);
+    let start_char = first_char.saturating_sub(max_chars / 2);
+    let end_char = (start_char + max_chars).min(chars.len());
+    let start_char = end_char.saturating_sub(max_chars).min(start_char);
+    let start = chars.get(start_char).map(|(b, _)| *b).unwrap_or(0);
+    let end = chars.get(end_char).map(|(b, _)| *b).unwrap_or(text.len());
+    let start = end - text[start..end].trim_start().len();
+    let end = start + text[start..end].trim_end().len();
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    let mut last = start;
+    for (p, w) in words
+        .iter()
+        .filter(|(p, w)| *p >= start && p + w.len() <= end)
+    {
+        if is_match(w) {
+            out.push_str(&escape_html(&text[last..*p]));
+            out.push_str("<b>");
+            out.push_str(&escape_html(w));
+            out.push_str("</b>");
+            last = p + w.len();
+        }
+    }
+    out.push_str(&escape_html(&text[last..end]));
+    if end < text.len() {
+        out.push('…');
+    }
+    out
+}
+
+/// Searcher runs queries against an index, see the [module documentation](self)
+pub struct Searcher<'a> {
+    bcc: &'a BitcodeContext,
+    schema: &'a Schema,
+}
+
+impl<'a> Searcher<'a> {
+    /// new opens the index and a reader on it
+    pub fn new(
+        bcc: &'a BitcodeContext,
+        schema: &'a Schema,
+    ) -> Result<Searcher<'a>, Box<dyn Error + Send + Sync>> {
+        bcc.builder_create_index(None)?;
+        bcc.index_reader_builder_create(None)?;
+        bcc.index_reader_searcher(None)?;
+        Ok(Searcher { bcc, schema })
+    }
+
+    pub fn schema(&self) -> &'a Schema {
+        self.schema
+    }
+
+    // the fields searched by default when the request names none, suggestion prefix indexes aside
+    fn default_fields(&self) -> Vec<String> {
+        self.schema
+            .fields()
+            .filter(|(fe, _)| {
+                matches!(&fe.field_type, FieldType::Text(o) | FieldType::Json(o)
+                    if o.tokenizer.is_some() && o.tokenizer.as_deref() != Some(SUGGEST_ANALYZER))
+            })
+            .map(|(fe, _)| fe.name.clone())
+            .collect()
+    }
+
+    // the tokenizer each searched text field was indexed with, which the query parser analyzes its
+    // query text with
+    fn tokenizers(&self, fields: &[String]) -> BTreeMap<String, String> {
+        fields
+            .iter()
+            .filter_map(|f| match &self.schema.entry(f)?.field_type {
+                FieldType::Text(o) | FieldType::Json(o) => Some((f.clone(), o.tokenizer.clone()?)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// search runs the request, returning one page of results
+    pub fn search(
+        &self,
+        req: &SearchRequest,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let fields = if req.fields.is_empty() {
+            self.default_fields()
+        } else {
+            req.fields.clone()
+        };
+        for f in fields.iter().chain(&req.snippets) {
+            if self.schema.field(f).is_none() {
+                return Err(Box::new(ErrorKinds::NotExist(format!(
+                    "field {f} is not in the schema"
+                ))));
+            }
+        }
+        if let Some(sort) = &req.sort {
+            match self.schema.entry(&sort.field) {
+                Some(fe) if fe.field_type.is_fast() => {}
+                _ => {
+                    return Err(Box::new(ErrorKinds::Invalid(format!(
+                        "cannot sort by {}, it is not a fast field",
+                        sort.field
+                    ))))
+                }
+            }
+        }
+        self.bcc.query_parser_for_index(Some(json!({
+            "fields" : fields,
+            "tokenizers" : self.tokenizers(&fields),
+        })))?;
+        match &req.structured {
+            Some(q) if q.requires_json() => self.bcc.query_parser_parse_json_query(&q.to_json())?,
+            _ => self.bcc.query_parser_parse_query(&req.query)?,
+        };
+        let res = self.bcc.query_parser_search(Some(json!({
+            "top_limit" : req.offset + req.limit,
+            "order_by" : req.sort,
+            "snippets" : req.snippets,
+            "aggregations" : aggregations_to_host(&req.aggregations),
+        })))?;
+        let mut results = SearchResults::from_reply(&reply_body(&res)?, req)?;
+        if let Some(sort) = &req.sort {
+            results.sort_by_field(sort);
+        }
+        match &req.structured {
+            Some(q) => results.highlight(&req.snippets, &q.terms().join(" ")),
+            None => results.highlight(&req.snippets, &req.query),
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_from_reply() {
+        let req = SearchRequest::new("sea").limit(2).offset(1);
+        let body = json!({"total_hits" : 7, "hits" : [
+            {"score" : 3.0, "doc" : {"title" : ["a"]}, "address" : {"segment_ord" : 0, "doc_id" : 1}},
+            {"score" : 2.0, "doc" : {"title" : ["b"]}, "address" : {"segment_ord" : 0, "doc_id" : 2}},
+            {"score" : 1.0, "doc" : {"title" : "c"}},
+        ]});
+        // the top offset + limit hits, the page is cut here
+        let res = SearchResults::from_reply(&body, &req).unwrap();
+        assert_eq!(res.total_hits, 7);
+        assert_eq!(res.hits.len(), 2);
+        assert_eq!(res.hits[0].text("title"), Some("b"));
+        assert_eq!(
+            res.hits[0].address,
+            Some(DocAddress {
+                segment_ord: 0,
+                doc_id: 2
+            })
+        );
+        assert_eq!(res.hits[1].fields["title"], json!(["c"]));
+        assert!(res.has_more());
+
+        // a last, partial page: both matches are returned, the first belongs to the previous page
+        let body = json!({"total_hits" : 2, "hits" : [
+            {"score" : 2.5, "doc" : {"year" : [1998]}},
+            {"score" : 1.5, "doc" : {"year" : [1999]}, "snippets" : {"body" : "<b>x</b>"}},
+        ]});
+        let mut res = SearchResults::from_reply(&body, &req).unwrap();
+        assert_eq!(res.total_hits, 2);
+        assert_eq!(res.hits.len(), 1);
+        assert_eq!(res.hits[0].snippets["body"], "<b>x</b>");
+        assert!(!res.has_more());
+        res.hits.push(res.hits[0].clone());
+        res.hits[1].fields.insert("year".to_string(), json!([2001]));
+        res.sort_by_field(&SortBy {
+            field: "year".to_string(),
+            order: SortOrder::Desc,
+        });
+        assert_eq!(res.hits[0].value("year"), Some(&json!(2001)));
+
+        let req = SearchRequest::new("sea").limit(10).offset(5);
+        let hits: Vec<Value> = (0..8)
+            .map(|i| json!({"score" : 8 - i, "doc" : {"n" : [i]}}))
+            .collect();
+        let res =
+            SearchResults::from_reply(&json!({"total_hits" : 8, "hits" : hits}), &req).unwrap();
+        assert_eq!(res.hits.len(), 3);
+        assert_eq!(res.hits[0].value("n"), Some(&json!(5)));
+
+        assert!(SearchResults::from_reply(&json!([{"score" : 1.0, "doc" : {}}]), &req).is_err());
+        assert!(SearchResults::from_reply(&json!({"hits" : []}), &req).is_err());
+    }
+
+    #[test]
+    fn test_request_from_query() {
+        let qp = std::collections::HashMap::from([
+            (
+                "q".to_string(),
+                vec![r#"{"prefix" : {"field" : "title", "prefix" : "se"}}"#.to_string()],
+            ),
+            ("sort".to_string(), vec!["-year".to_string()]),
+        ]);
+        let req = SearchRequest::from_query(&qp).unwrap();
+        assert_eq!(req.query, r#"title:"se"*"#);
+        assert_eq!(req.structured, Some(Query::prefix("title", "se")));
+        assert_eq!(req.sort.unwrap().order, SortOrder::Desc);
+        let qp = std::collections::HashMap::from([("q".to_string(), vec!["sea".to_string()])]);
+        assert!(SearchRequest::from_query(&qp).is_err());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let terms = query_terms("title:old AND \"the sea\"");
+        assert_eq!(terms, vec!["old", "the", "sea"]);
+        assert_eq!(
+            highlight("The Old Man", &terms, 100),
+            "<b>The</b> <b>Old</b> Man"
+        );
+        assert_eq!(highlight("no match here", &terms, 5), "no ma…");
+        assert_eq!(
+            highlight("<script>sea</script> & \"old\"", &terms, 100),
+            "&lt;script&gt;<b>sea</b>&lt;/script&gt; &amp; &quot;<b>old</b>&quot;"
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Typed Tantivy schema construction <br>
+//! A [SchemaBuilder] collects [FieldEntry]s, each a name and a [FieldType] carrying its indexing options, and
+//! registers them with the index builder opened by [BitcodeContext::new_index_builder] or
+//! [BitcodeContext::restore_index_from_part].  Field entries can be built directly or from the
+//! `type`/`options` of an indexer configuration with [FieldEntry::from_config].
+//! ```rust
+//! use elvwasm::search::{NumericOptions, SchemaBuilder, TextOptions};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   bcc.new_index_builder(serde_json::json!({}))?;
+//!   let schema = SchemaBuilder::new(bcc)
+//!     .text("title", TextOptions::text().stored())
+//!     .text("asset_type", TextOptions::string().stored().fast())
+//!     .u64("year", NumericOptions::indexed().stored().fast())
+//!     .build()?;
+//!   Ok(schema.field("title").unwrap_or_default().to_string().into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{reply_u64, Analyzer};
+use crate::{BitcodeContext, ErrorKinds};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Text option type for untokenized strings, indexed as a single term
+pub const TEXT_OPTION_STRING: u8 = 1;
+
+/// Text option type for tokenized full text
+pub const TEXT_OPTION_TEXT: u8 = 2;
+
+/// What is recorded in the postings of an indexed text field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexRecordOption {
+    /// document ids only
+    Basic,
+    /// document ids and term frequencies
+    WithFreqs,
+    /// document ids, term frequencies and positions, needed for phrase queries
+    WithFreqsAndPositions,
+}
+
+/// Indexing options of a text or JSON field
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TextOptions {
+    /// [TEXT_OPTION_TEXT] or [TEXT_OPTION_STRING]
+    #[serde(rename = "type")]
+    pub text_type: u8,
+    /// None leaves the field unindexed
+    pub tokenizer: Option<String>,
+    /// the custom analyzer the tokenizer names, registered with the host along with the schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analyzer: Option<Analyzer>,
+    pub record: IndexRecordOption,
+    pub stored: bool,
+    pub fast: bool,
+}
+
+impl TextOptions {
+    /// text creates options for full text, tokenized by the `default` tokenizer with positions recorded
+    pub fn text() -> TextOptions {
+        TextOptions {
+            text_type: TEXT_OPTION_TEXT,
+            tokenizer: Some("default".to_string()),
+            analyzer: None,
+            record: IndexRecordOption::WithFreqsAndPositions,
+            stored: false,
+            fast: false,
+        }
+    }
+
+    /// string creates options for an untokenized string indexed as a single term
+    pub fn string() -> TextOptions {
+        TextOptions {
+            text_type: TEXT_OPTION_STRING,
+            tokenizer: Some("raw".to_string()),
+            analyzer: None,
+            record: IndexRecordOption::Basic,
+            stored: false,
+            fast: false,
+        }
+    }
+
+    pub fn stored(mut self) -> TextOptions {
+        self.stored = true;
+        self
+    }
+
+    /// fast keeps a columnar copy of the values for sorting and aggregations
+    pub fn fast(mut self) -> TextOptions {
+        self.fast = true;
+        self
+    }
+
+    /// tokenizer analyzes the field with a tokenizer of the host, see [BUILTIN_TOKENIZERS](crate::search::BUILTIN_TOKENIZERS)
+    pub fn tokenizer(mut self, tokenizer: &str) -> TextOptions {
+        self.tokenizer = Some(tokenizer.to_string());
+        self.analyzer = None;
+        self
+    }
+
+    /// analyzer analyzes the field, and the query text searching it, with a custom [Analyzer]
+    pub fn analyzer(mut self, analyzer: Analyzer) -> TextOptions {
+        self.tokenizer = Some(analyzer.name.clone());
+        self.analyzer = Some(analyzer);
+        self
+    }
+
+    /// positions toggles recording of positions (and frequencies) in the postings
+    pub fn positions(mut self, positions: bool) -> TextOptions {
+        self.record = if positions {
+            IndexRecordOption::WithFreqsAndPositions
+        } else {
+            IndexRecordOption::Basic
+        };
+        self
+    }
+
+    /// unindexed keeps the field out of the inverted index, e.g. for stored only values
+    pub fn unindexed(mut self) -> TextOptions {
+        self.tokenizer = None;
+        self.analyzer = None;
+        self
+    }
+}
+
+/// Indexing options of a numeric, date, boolean or bytes field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NumericOptions {
+    pub indexed: bool,
+    pub stored: bool,
+    pub fast: bool,
+}
+
+impl NumericOptions {
+    pub fn indexed() -> NumericOptions {
+        NumericOptions {
+            indexed: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn stored(mut self) -> NumericOptions {
+        self.stored = true;
+        self
+    }
+
+    /// fast keeps a columnar copy of the values for sorting, ranges and aggregations
+    pub fn fast(mut self) -> NumericOptions {
+        self.fast = true;
+        self
+    }
+}
+
+/// The kind of a field along with its options
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldType {
+    Text(TextOptions),
+    U64(NumericOptions),
+    I64(NumericOptions),
+    F64(NumericOptions),
+    Date(NumericOptions),
+    Bool(NumericOptions),
+    Facet { stored: bool },
+    Bytes(NumericOptions),
+    Json(TextOptions),
+}
+
+impl FieldType {
+    /// name returns the configuration name of the field kind, as accepted by [FieldEntry::from_config]
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Text(o) if o.text_type == TEXT_OPTION_STRING => "string",
+            FieldType::Text(_) => "text",
+            FieldType::U64(_) => "u64",
+            FieldType::I64(_) => "i64",
+            FieldType::F64(_) => "f64",
+            FieldType::Date(_) => "date",
+            FieldType::Bool(_) => "bool",
+            FieldType::Facet { .. } => "facet",
+            FieldType::Bytes(_) => "bytes",
+            FieldType::Json(_) => "json",
+        }
+    }
+
+    pub fn is_stored(&self) -> bool {
+        match self {
+            FieldType::Text(o) | FieldType::Json(o) => o.stored,
+            FieldType::U64(o)
+            | FieldType::I64(o)
+            | FieldType::F64(o)
+            | FieldType::Date(o)
+            | FieldType::Bool(o)
+            | FieldType::Bytes(o) => o.stored,
+            FieldType::Facet { stored } => *stored,
+        }
+    }
+
+    pub fn is_fast(&self) -> bool {
+        match self {
+            FieldType::Text(o) | FieldType::Json(o) => o.fast,
+            FieldType::U64(o)
+            | FieldType::I64(o)
+            | FieldType::F64(o)
+            | FieldType::Date(o)
+            | FieldType::Bool(o)
+            | FieldType::Bytes(o) => o.fast,
+            FieldType::Facet { .. } => true,
+        }
+    }
+}
+
+/// The statistics gathered on a field, as configured by its `stats` option, see [crate::search::Aggregation::for_field]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FieldStats {
+    /// min, max, average and sum of numeric and date fields
+    #[serde(default)]
+    pub simple: bool,
+    /// value counts of string and facet fields, buckets of numeric and date fields
+    #[serde(default)]
+    pub histogram: bool,
+    /// bucket width of a numeric histogram, or seconds of a date histogram
+    #[serde(default)]
+    pub interval: Option<f64>,
+    /// number of string values counted
+    #[serde(default)]
+    pub size: Option<usize>,
+}
+
+impl FieldStats {
+    pub fn is_enabled(&self) -> bool {
+        self.simple || self.histogram
+    }
+}
+
+/// A named field of a schema
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldEntry {
+    pub name: String,
+    pub field_type: FieldType,
+    pub stats: Option<FieldStats>,
+}
+
+impl FieldEntry {
+    pub fn new(name: &str, field_type: FieldType) -> FieldEntry {
+        FieldEntry {
+            name: name.to_string(),
+            field_type,
+            stats: None,
+        }
+    }
+
+    /// with_stats asks for statistics to be gathered on the field, which makes it fast
+    pub fn with_stats(mut self, stats: FieldStats) -> FieldEntry {
+        if stats.is_enabled() {
+            match &mut self.field_type {
+                FieldType::Text(o) | FieldType::Json(o) => o.fast = true,
+                FieldType::U64(o)
+                | FieldType::I64(o)
+                | FieldType::F64(o)
+                | FieldType::Date(o)
+                | FieldType::Bool(o)
+                | FieldType::Bytes(o) => o.fast = true,
+                FieldType::Facet { .. } => {}
+            }
+            self.stats = Some(stats);
+        }
+        self
+    }
+
+    /// from_config maps an indexer configuration field onto a schema field
+    /// # Arguments
+    /// * `name` : the field name
+    /// * `field_type` : one of `text`, `string`, `u64`, `i64`, `f64`, `date`, `bool`, `facet`, `bytes` or `json`
+    /// * `options` : the field's `options`, where `stored` (default true), `fast`, `indexed` (default true),
+    ///   `tokenizer` and `positions` are honoured, `analyzer` is parsed as an [Analyzer] named after the
+    ///   field by default and `stats` is parsed as [FieldStats]
+    /// ```rust
+    /// use elvwasm::search::{FieldEntry, FieldType, TextOptions};
+    /// let fe = FieldEntry::from_config("asset_type", "string", &serde_json::json!({"stats" : {"histogram" : true}})).unwrap();
+    /// assert_eq!(fe.field_type, FieldType::Text(TextOptions::string().stored().fast()));
+    /// ```
+    pub fn from_config(
+        name: &str,
+        field_type: &str,
+        options: &Value,
+    ) -> Result<FieldEntry, Box<dyn Error + Send + Sync>> {
+        let flag = |key: &str, default: bool| options[key].as_bool().unwrap_or(default);
+        let stored = flag("stored", true);
+        let fast = flag("fast", false);
+        let stats: FieldStats = match options.get("stats") {
+            Some(s) if !s.is_null() => serde_json::from_value(s.clone())?,
+            _ => FieldStats::default(),
+        };
+        let indexed = flag("indexed", true);
+        let analyzer = match options.get("analyzer") {
+            Some(a) if !a.is_null() => Some(Analyzer::from_config(name, a)?),
+            _ => None,
+        };
+        let text = |mut o: TextOptions| {
+            if let Some(t) = options["tokenizer"].as_str() {
+                o = o.tokenizer(t);
+            }
+            if let Some(a) = &analyzer {
+                o = o.analyzer(a.clone());
+            }
+            if let Some(p) = options["positions"].as_bool() {
+                o = o.positions(p);
+            }
+            if !indexed {
+                o = o.unindexed();
+            }
+            o.stored = stored;
+            o.fast = fast;
+            o
+        };
+        let numeric = NumericOptions {
+            indexed,
+            stored,
+            fast,
+        };
+        let ft = match field_type {
+            "text" => FieldType::Text(text(TextOptions::text())),
+            "string" => FieldType::Text(text(TextOptions::string())),
+            "json" => FieldType::Json(text(TextOptions::text())),
+            "u64" => FieldType::U64(numeric),
+            "i64" => FieldType::I64(numeric),
+            "f64" => FieldType::F64(numeric),
+            "date" => FieldType::Date(numeric),
+            "bool" => FieldType::Bool(numeric),
+            "bytes" => FieldType::Bytes(numeric),
+            "facet" => FieldType::Facet { stored },
+            _ => {
+                return Err(Box::new(ErrorKinds::Invalid(format!(
+                    "unknown type {field_type} for field {name}"
+                ))))
+            }
+        };
+        Ok(FieldEntry::new(name, ft).with_stats(stats))
+    }
+
+    fn text_params(&self, o: &TextOptions) -> Value {
+        json!({
+            "name" : self.name,
+            "type" : o.text_type,
+            "stored" : o.stored,
+            "fast" : o.fast,
+            "indexed" : o.tokenizer.is_some(),
+            "tokenizer" : o.tokenizer,
+            "record" : o.record,
+        })
+    }
+
+    fn numeric_params(&self, o: &NumericOptions) -> Value {
+        json!({
+            "name" : self.name,
+            "indexed" : o.indexed,
+            "stored" : o.stored,
+            "fast" : o.fast,
+        })
+    }
+
+    // registers the field with the host's schema builder, returning the field id
+    fn register(&self, bcc: &BitcodeContext) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let res = match &self.field_type {
+            FieldType::Text(o) => bcc.builder_add_text_field(Some(self.text_params(o)))?,
+            FieldType::Json(o) => bcc.builder_add_json_field(Some(self.text_params(o)))?,
+            FieldType::U64(o) => bcc.builder_add_u64_field(Some(self.numeric_params(o)))?,
+            FieldType::I64(o) => bcc.builder_add_i64_field(Some(self.numeric_params(o)))?,
+            FieldType::F64(o) => bcc.builder_add_f64_field(Some(self.numeric_params(o)))?,
+            FieldType::Date(o) => bcc.builder_add_date_field(Some(self.numeric_params(o)))?,
+            FieldType::Bool(o) => bcc.builder_add_bool_field(Some(self.numeric_params(o)))?,
+            FieldType::Bytes(o) => bcc.builder_add_bytes_field(Some(self.numeric_params(o)))?,
+            FieldType::Facet { stored } => bcc.builder_add_facet_field(Some(json!({
+                "name" : self.name,
+                "stored" : stored,
+            })))?,
+        };
+        reply_u64(&res, "field")
+    }
+}
+
+/// A built schema: its fields along with the ids the host assigned them
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    fields: Vec<(FieldEntry, u64)>,
+}
+
+impl Schema {
+    /// field looks up the id of a field by name
+    pub fn field(&self, name: &str) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|(fe, _)| fe.name == name)
+            .map(|(_, id)| *id)
+    }
+
+    /// entry looks up a field's definition by name
+    pub fn entry(&self, name: &str) -> Option<&FieldEntry> {
+        self.fields
+            .iter()
+            .find(|(fe, _)| fe.name == name)
+            .map(|(fe, _)| fe)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&FieldEntry, u64)> {
+        self.fields.iter().map(|(fe, id)| (fe, *id))
+    }
+
+    /// stored_fields lists the names of the fields whose values are stored
+    pub fn stored_fields(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .filter(|(fe, _)| fe.field_type.is_stored())
+            .map(|(fe, _)| fe.name.clone())
+            .collect()
+    }
+}
+
+/// SchemaBuilder declares the fields of an index, see the [module documentation](self)
+pub struct SchemaBuilder<'a> {
+    bcc: &'a BitcodeContext,
+    entries: Vec<FieldEntry>,
+}
+
+impl<'a> SchemaBuilder<'a> {
+    pub fn new(bcc: &'a BitcodeContext) -> SchemaBuilder<'a> {
+        SchemaBuilder {
+            bcc,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn field(mut self, entry: FieldEntry) -> SchemaBuilder<'a> {
+        self.entries.push(entry);
+        self
+    }
+
+    pub fn text(self, name: &str, opts: TextOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Text(opts)))
+    }
+
+    pub fn json(self, name: &str, opts: TextOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Json(opts)))
+    }
+
+    pub fn u64(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::U64(opts)))
+    }
+
+    pub fn i64(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::I64(opts)))
+    }
+
+    pub fn f64(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::F64(opts)))
+    }
+
+    pub fn date(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Date(opts)))
+    }
+
+    pub fn bool(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Bool(opts)))
+    }
+
+    pub fn facet(self, name: &str, stored: bool) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Facet { stored }))
+    }
+
+    pub fn bytes(self, name: &str, opts: NumericOptions) -> SchemaBuilder<'a> {
+        self.field(FieldEntry::new(name, FieldType::Bytes(opts)))
+    }
+
+    /// build registers the custom analyzers and every field with the host and builds the schema
+    pub fn build(self) -> Result<Schema, Box<dyn Error + Send + Sync>> {
+        for analyzer in analyzers(&self.entries)? {
+            self.bcc.builder_register_tokenizer(Some(
+                json!({ "name": analyzer.name, "analyzer": analyzer }),
+            ))?;
+        }
+        let mut fields = Vec::with_capacity(self.entries.len());
+        for entry in self.entries {
+            if fields
+                .iter()
+                .any(|(fe, _): &(FieldEntry, u64)| fe.name == entry.name)
+            {
+                return Err(Box::new(ErrorKinds::Exist(format!(
+                    "field {} declared twice",
+                    entry.name
+                ))));
+            }
+            let id = entry.register(self.bcc)?;
+            fields.push((entry, id));
+        }
+        self.bcc.builder_build(None)?;
+        Ok(Schema { fields })
+    }
+}
+
+// the distinct custom analyzers of the entries, failing when two share a name but differ
+fn analyzers(entries: &[FieldEntry]) -> Result<Vec<&Analyzer>, Box<dyn Error + Send + Sync>> {
+    let mut found: Vec<&Analyzer> = Vec::new();
+    for entry in entries {
+        let analyzer = match &entry.field_type {
+            FieldType::Text(o) | FieldType::Json(o) => match &o.analyzer {
+                Some(a) => a,
+                None => continue,
+            },
+            _ => continue,
+        };
+        analyzer.validate()?;
+        match found.iter().find(|a| a.name == analyzer.name) {
+            Some(a) if *a != analyzer => {
+                return Err(Box::new(ErrorKinds::Exist(format!(
+                    "analyzer {} of field {} is defined differently elsewhere",
+                    analyzer.name, entry.name
+                ))))
+            }
+            Some(_) => {}
+            None => found.push(analyzer),
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{Language, Tokenizer};
+
+    #[test]
+    fn test_field_entry_from_config() {
+        let fe = FieldEntry::from_config(
+            "display_title",
+            "text",
+            &json!({"tokenizer" : "en_stem", "positions" : false}),
+        )
+        .unwrap();
+        assert_eq!(
+            fe.field_type,
+            FieldType::Text(
+                TextOptions::text()
+                    .tokenizer("en_stem")
+                    .positions(false)
+                    .stored()
+            )
+        );
+        let fe = FieldEntry::from_config("year", "u64", &json!({"stored" : false, "fast" : true}))
+            .unwrap();
+        assert_eq!(
+            fe.field_type,
+            FieldType::U64(NumericOptions::indexed().fast())
+        );
+        let fe = FieldEntry::from_config("genre", "facet", &Value::Null).unwrap();
+        assert_eq!(fe.field_type.name(), "facet");
+        assert!(fe.field_type.is_stored());
+        assert!(FieldEntry::from_config("x", "geo", &json!({})).is_err());
+        let fe = FieldEntry::from_config(
+            "id",
+            "string",
+            &json!({"builder" : {}, "stats" : {"simple" : false, "histogram" : false}}),
+        )
+        .unwrap();
+        assert_eq!(fe.stats, None);
+        assert!(!fe.field_type.is_fast());
+    }
+
+    #[test]
+    fn test_field_analyzers() {
+        let fe = FieldEntry::from_config(
+            "title",
+            "text",
+            &json!({"analyzer" : {"stemmer" : "english", "ascii_folding" : true}}),
+        )
+        .unwrap();
+        let title = Analyzer::new("title", Tokenizer::Simple)
+            .stemmer(Language::English)
+            .ascii_folding();
+        assert_eq!(
+            fe.field_type,
+            FieldType::Text(TextOptions::text().analyzer(title.clone()).stored())
+        );
+        let body = FieldEntry::new(
+            "body",
+            FieldType::Text(TextOptions::text().analyzer(title.clone())),
+        );
+        let plain = FieldEntry::new("plain", FieldType::Text(TextOptions::text()));
+        let entries = vec![fe.clone(), body, plain];
+        assert_eq!(analyzers(&entries).unwrap(), vec![&title]);
+        let clash = FieldEntry::new(
+            "other",
+            FieldType::Json(TextOptions::text().analyzer(title.lowercase(false))),
+        );
+        assert!(analyzers(&[fe, clash]).is_err());
+        let unindexed = TextOptions::text()
+            .analyzer(Analyzer::new("edge", Tokenizer::Raw))
+            .unindexed();
+        assert_eq!(unindexed.analyzer, None);
+    }
+}
@@ -0,0 +1,337 @@
+//! Autocomplete and spelling suggestions <br>
+//! A text field configured with the `suggest` option gets two companion fields at index time: a prefix index
+//! of its whole values, lowercased and ASCII folded, and a fast copy of the values as written. A [Suggester]
+//! completes what a user has typed so far with the values starting with it, the most frequent first, and
+//! corrects misspelled words with the closest terms of a field's term dictionary ("did you mean").
+//! ```rust
+//! use elvwasm::search::{Indexer, IndexerConfig, Suggester};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let indexer = Indexer::new(bcc, config.fields)?;
+//!   let suggester = Suggester::new(bcc, &indexer.schema)?;
+//!   let completions = suggester.complete("title", "the old m", 10)?;
+//!   let did_you_mean = suggester.did_you_mean("title", "old mna")?;
+//!   Ok(serde_json::to_vec(&(completions, did_you_mean))?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    reply_body, Aggregation, Analyzer, FieldConfig, FieldEntry, FieldType, Query, Schema,
+    SearchRequest, Searcher, TextOptions, Tokenizer,
+};
+use crate::{BitcodeContext, ErrorKinds};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Suffix of the companion field holding the prefix index of a suggested field
+pub const SUGGEST_SUFFIX: &str = "_suggest";
+/// Suffix of the companion field holding the values of a suggested field as written
+pub const COMPLETION_SUFFIX: &str = "_completion";
+/// Name of the analyzer of the prefix index fields
+pub const SUGGEST_ANALYZER: &str = "suggest";
+pub const DEFAULT_SUGGEST_LIMIT: usize = 10;
+/// Largest edit distance of a spelling correction by default
+pub const DEFAULT_CORRECTION_DISTANCE: u8 = 2;
+
+// documents scanned to count completions when the host does not aggregate them
+const SUGGEST_SCAN_LIMIT: usize = 100;
+
+pub fn suggest_field(field: &str) -> String {
+    format!("{field}{SUGGEST_SUFFIX}")
+}
+
+pub fn completion_field(field: &str) -> String {
+    format!("{field}{COMPLETION_SUFFIX}")
+}
+
+/// suggest_analyzer analyzes the values of a prefix index field, and the prefixes searching it
+pub fn suggest_analyzer() -> Analyzer {
+    Analyzer::new(SUGGEST_ANALYZER, Tokenizer::Raw).ascii_folding()
+}
+
+/// suggested_fields lists the configured fields with the `suggest` option set
+pub fn suggested_fields(fields: &[FieldConfig]) -> Vec<String> {
+    fields
+        .iter()
+        .filter(|f| f.options["suggest"].as_bool().unwrap_or(false))
+        .map(|f| f.name.clone())
+        .collect()
+}
+
+/// suggest_entries returns the companion schema fields of a suggested field, which must be a text or string
+/// field
+pub fn suggest_entries(
+    field: &FieldConfig,
+) -> Result<Vec<FieldEntry>, Box<dyn Error + Send + Sync>> {
+    if field.field_type != "text" && field.field_type != "string" {
+        return Err(Box::new(ErrorKinds::Invalid(format!(
+            "cannot suggest {} field {}",
+            field.field_type, field.name
+        ))));
+    }
+    Ok(vec![
+        FieldEntry::new(
+            &suggest_field(&field.name),
+            FieldType::Text(
+                TextOptions::string()
+                    .analyzer(suggest_analyzer())
+                    .positions(false),
+            ),
+        ),
+        FieldEntry::new(
+            &completion_field(&field.name),
+            FieldType::Text(TextOptions::string().unindexed().stored().fast()),
+        ),
+    ])
+}
+
+/// A value completing a prefix, along with the number of documents holding it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Completion {
+    pub text: String,
+    pub count: u64,
+}
+
+/// A term of the dictionary close to a misspelled word
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Correction {
+    pub term: String,
+    /// the edit distance to the word
+    pub distance: usize,
+    /// the number of documents holding the term
+    pub doc_freq: u64,
+}
+
+/// edit_distance counts the insertions, deletions, substitutions and transpositions of adjacent characters
+/// turning `a` into `b`
+/// ```rust
+/// assert_eq!(elvwasm::search::edit_distance("sea", "sae"), 1);
+/// assert_eq!(elvwasm::search::edit_distance("old", "bold"), 1);
+/// ```
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// rank_corrections keeps the candidate terms within `max_distance` of the word, closest first and most
+/// frequent among equally close ones
+pub fn rank_corrections(
+    word: &str,
+    candidates: Vec<(String, u64)>,
+    max_distance: usize,
+) -> Vec<Correction> {
+    let mut corrections: Vec<Correction> = candidates
+        .into_iter()
+        .map(|(term, doc_freq)| Correction {
+            distance: edit_distance(word, &term),
+            term,
+            doc_freq,
+        })
+        .filter(|c| c.distance <= max_distance)
+        .collect();
+    corrections.sort_by(|a, b| {
+        a.distance
+            .cmp(&b.distance)
+            .then(b.doc_freq.cmp(&a.doc_freq))
+            .then(a.term.cmp(&b.term))
+    });
+    corrections.dedup_by(|a, b| a.term == b.term);
+    corrections
+}
+
+// decodes the terms of an index_reader_terms reply body, either an array or under `terms`, each term being
+// `{"term" : t, "doc_freq" : n}` or `[t, n]`
+fn parse_terms(body: &Value) -> Result<Vec<(String, u64)>, Box<dyn Error + Send + Sync>> {
+    let terms = match body {
+        Value::Array(a) => a,
+        _ => body["terms"].as_array().ok_or_else(|| {
+            ErrorKinds::BadHttpParams(format!("no terms in term dictionary reply {body}"))
+        })?,
+    };
+    terms
+        .iter()
+        .map(|t| {
+            let (term, freq) = match t {
+                Value::Array(a) if a.len() == 2 => (&a[0], &a[1]),
+                _ => (&t["term"], &t["doc_freq"]),
+            };
+            match (term.as_str(), freq.as_u64()) {
+                (Some(term), Some(freq)) => Ok((term.to_string(), freq)),
+                _ => Err(ErrorKinds::BadHttpParams(format!("bad dictionary term {t}")).into()),
+            }
+        })
+        .collect()
+}
+
+/// Suggester completes and corrects user input, see the [module documentation](self)
+pub struct Suggester<'a> {
+    bcc: &'a BitcodeContext,
+    searcher: Searcher<'a>,
+}
+
+impl<'a> Suggester<'a> {
+    /// new opens the index and a reader on it
+    pub fn new(
+        bcc: &'a BitcodeContext,
+        schema: &'a Schema,
+    ) -> Result<Suggester<'a>, Box<dyn Error + Send + Sync>> {
+        Ok(Suggester {
+            bcc,
+            searcher: Searcher::new(bcc, schema)?,
+        })
+    }
+
+    /// complete returns up to `limit` values of a suggested field starting with `prefix`, compared ignoring
+    /// case and accents, the most frequent first
+    pub fn complete(
+        &self,
+        field: &str,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<Completion>, Box<dyn Error + Send + Sync>> {
+        let (suggest, completion) = (suggest_field(field), completion_field(field));
+        if self.searcher.schema().field(&suggest).is_none() {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "field {field} is not configured for suggestions"
+            ))));
+        }
+        if prefix.trim().is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+        let req = SearchRequest::with_query(&Query::prefix(&suggest, prefix.trim_start()))
+            .fields(&[&suggest])
+            .limit(SUGGEST_SCAN_LIMIT)
+            .aggregate("completions", Aggregation::terms(&completion, limit));
+        let results = self.searcher.search(&req)?;
+        let mut completions: Vec<Completion> = results
+            .aggregations
+            .get("completions")
+            .map(|a| a.buckets())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|b| {
+                Some(Completion {
+                    text: b.key.as_str()?.to_string(),
+                    count: b.doc_count,
+                })
+            })
+            .collect();
+        completions.sort_by(|a, b| b.count.cmp(&a.count).then(a.text.cmp(&b.text)));
+        completions.truncate(limit);
+        Ok(completions)
+    }
+
+    /// correct returns up to `limit` terms of the field's dictionary within `distance` edits of `word`,
+    /// closest and then most frequent first; a word spelled right comes first with a distance of 0
+    pub fn correct(
+        &self,
+        field: &str,
+        word: &str,
+        distance: u8,
+        limit: usize,
+    ) -> Result<Vec<Correction>, Box<dyn Error + Send + Sync>> {
+        let word = word.to_lowercase();
+        let res = self.bcc.index_reader_terms(Some(json!({
+            "field" : field,
+            "fuzzy" : {"term" : word, "distance" : distance, "transpositions" : true},
+            "limit" : limit.max(DEFAULT_SUGGEST_LIMIT),
+        })))?;
+        let mut corrections =
+            rank_corrections(&word, parse_terms(&reply_body(&res)?)?, distance.into());
+        corrections.truncate(limit);
+        Ok(corrections)
+    }
+
+    /// did_you_mean replaces each word of `text` missing from the field's dictionary by its best correction,
+    /// returning None when every word is spelled right or none could be corrected
+    pub fn did_you_mean(
+        &self,
+        field: &str,
+        text: &str,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let mut changed = false;
+        let mut words = Vec::new();
+        for word in text.split_whitespace() {
+            match self
+                .correct(field, word, DEFAULT_CORRECTION_DISTANCE, 1)?
+                .first()
+            {
+                Some(c) if c.distance > 0 => {
+                    changed = true;
+                    words.push(c.term.clone());
+                }
+                _ => words.push(word.to_lowercase()),
+            }
+        }
+        Ok(if changed { Some(words.join(" ")) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_corrections() {
+        assert_eq!(edit_distance("", "sea"), 3);
+        assert_eq!(edit_distance("man", "man"), 0);
+        assert_eq!(edit_distance("mna", "man"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        let body = json!({"terms" : [
+            {"term" : "man", "doc_freq" : 3},
+            {"term" : "men", "doc_freq" : 7},
+            ["mean", 9],
+            ["moon", 1],
+        ]});
+        let ranked = rank_corrections("mna", parse_terms(&body).unwrap(), 2);
+        let terms: Vec<&str> = ranked.iter().map(|c| c.term.as_str()).collect();
+        assert_eq!(terms, vec!["man", "mean", "men"]);
+        assert_eq!(ranked[0].distance, 1);
+        assert!(parse_terms(&json!({"terms" : [{"term" : 1}]})).is_err());
+        assert!(parse_terms(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_suggest_entries() {
+        let title: FieldConfig = serde_json::from_value(json!({
+            "name" : "title", "type" : "text", "options" : {"suggest" : true}, "paths" : [],
+        }))
+        .unwrap();
+        let year: FieldConfig = serde_json::from_value(json!({
+            "name" : "year", "type" : "u64", "options" : {}, "paths" : [],
+        }))
+        .unwrap();
+        assert_eq!(
+            suggested_fields(&[title.clone(), year.clone()]),
+            vec!["title"]
+        );
+        let entries = suggest_entries(&title).unwrap();
+        assert_eq!(entries[0].name, "title_suggest");
+        assert_eq!(entries[1].name, "title_completion");
+        assert!(entries[1].field_type.is_fast());
+        assert!(suggest_entries(&year).is_err());
+    }
+}
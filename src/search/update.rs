@@ -0,0 +1,409 @@
+//! Incremental index updates <br>
+//! The state of the index of a content lives in its metadata at [INDEX_STATE_PATH]: the part the current
+//! index is archived to along with those of its [vector indexes](crate::search::vector), the version of
+//! the root content and the hash of every object it was built from, and the parts it replaced.
+//! [update_index] uses it to rewrite only the documents of the objects that were added, changed or
+//! removed since, and [rollback_index] to return to an earlier part.
+//! ```rust
+//! use elvwasm::search::{update_index, IndexerConfig};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let config = IndexerConfig::from_meta(bcc)?;
+//!   let update = update_index(bcc, &config)?;
+//!   bcc.make_success_json(&update)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{
+    document_fields, document_vectors, vector_fields, Crawler, Indexer, IndexerConfig, VectorIndex,
+};
+use crate::{BitcodeContext, ErrorKinds, SystemTimeResult};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Metadata path of the [IndexState]
+pub const INDEX_STATE_PATH: &str = "/indexer/state";
+
+/// Number of superseded index parts kept recorded for rollback
+pub const MAX_PREVIOUS_PARTS: usize = 5;
+
+/// An archived index part and the object versions it was built from
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PartRecord {
+    pub part_hash: String,
+    pub root_hash: String,
+    /// sorted hashes of the indexed objects
+    pub sorted_hashes: Vec<String>,
+    /// object id to the hash last indexed
+    pub objects: BTreeMap<String, String>,
+    pub created: u64,
+    /// vector field to the part its [VectorIndex] was archived to
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vector_parts: BTreeMap<String, String>,
+}
+
+/// The current index part along with the ones it superseded, newest first
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IndexState {
+    pub current: Option<PartRecord>,
+    #[serde(default)]
+    pub previous: Vec<PartRecord>,
+}
+
+impl IndexState {
+    pub fn load(bcc: &BitcodeContext) -> Result<IndexState, Box<dyn Error + Send + Sync>> {
+        IndexState::decode(&bcc.sqmd_get_json(INDEX_STATE_PATH)?)
+    }
+
+    /// load_external loads the index state of another content, such as a shard of a larger index
+    pub fn load_external(
+        bcc: &BitcodeContext,
+        qlibid: &str,
+        qhash: &str,
+    ) -> Result<IndexState, Box<dyn Error + Send + Sync>> {
+        IndexState::decode(&bcc.sqmd_get_json_external(qlibid, qhash, INDEX_STATE_PATH)?)
+    }
+
+    // a missing state decodes to the default, an unindexed content
+    fn decode(res: &[u8]) -> Result<IndexState, Box<dyn Error + Send + Sync>> {
+        match serde_json::from_slice::<Value>(res)? {
+            Value::Object(o) if !o.contains_key("error") => {
+                Ok(serde_json::from_value(Value::Object(o))?)
+            }
+            _ => Ok(IndexState::default()),
+        }
+    }
+
+    pub fn save(&self, bcc: &BitcodeContext) -> Result<(), Box<dyn Error + Send + Sync>> {
+        bcc.sqmd_set_json(INDEX_STATE_PATH, &serde_json::to_value(self)?)?;
+        Ok(())
+    }
+
+    /// push makes the record current, keeping the one it replaces for rollback
+    pub fn push(&mut self, record: PartRecord) {
+        if let Some(cur) = self.current.replace(record) {
+            self.previous.insert(0, cur);
+            self.previous.truncate(MAX_PREVIOUS_PARTS);
+        }
+    }
+
+    /// rollback makes the most recent previous part current again, returning the part dropped
+    pub fn rollback(&mut self) -> Option<PartRecord> {
+        if self.previous.is_empty() {
+            return None;
+        }
+        let prev = self.previous.remove(0);
+        self.current.replace(prev)
+    }
+}
+
+/// The documents an update adds, replaces and deletes
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UpdatePlan {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl UpdatePlan {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// plan_update compares the sorted hashes of the objects last indexed with the ones just crawled, objects
+/// whose hash appears on one side only being added, changed or removed depending on whether their id
+/// was indexed before and is still found
+pub fn plan_update(
+    old_sorted: &[String],
+    old_objects: &BTreeMap<String, String>,
+    new_sorted: &[String],
+    new_objects: &BTreeMap<String, String>,
+) -> UpdatePlan {
+    let (mut i, mut j) = (0, 0);
+    let mut gone = Vec::new();
+    let mut fresh = Vec::new();
+    let mut plan = UpdatePlan::default();
+    while i < old_sorted.len() || j < new_sorted.len() {
+        match (old_sorted.get(i), new_sorted.get(j)) {
+            (Some(o), Some(n)) if o == n => {
+                plan.unchanged += 1;
+                i += 1;
+                j += 1;
+            }
+            (Some(o), Some(n)) if o < n => {
+                gone.push(o);
+                i += 1;
+            }
+            (Some(o), None) => {
+                gone.push(o);
+                i += 1;
+            }
+            (_, Some(n)) => {
+                fresh.push(n);
+                j += 1;
+            }
+            (None, None) => break,
+        }
+    }
+    let id_of = |objects: &BTreeMap<String, String>, hash: &String| {
+        objects
+            .iter()
+            .find(|(_, h)| *h == hash)
+            .map(|(id, _)| id.clone())
+    };
+    for h in fresh {
+        if let Some(id) = id_of(new_objects, h) {
+            if old_objects.contains_key(&id) {
+                plan.changed.push(id);
+            } else {
+                plan.added.push(id);
+            }
+        }
+    }
+    for h in gone {
+        if let Some(id) = id_of(old_objects, h) {
+            if !new_objects.contains_key(&id) {
+                plan.removed.push(id);
+            }
+        }
+    }
+    plan
+}
+
+/**
+ * Brings the index of the content up to date: the root content's latest version is checked against
+ * the one last indexed, and if it moved the root is crawled again and only the documents of added,
+ * changed and removed objects are written to the restored index, which is archived to a new part.
+ * The part it replaces stays recorded in the state for rollback.
+ */
+pub fn update_index(
+    bcc: &BitcodeContext,
+    config: &IndexerConfig,
+) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    index_content(bcc, config, false)
+}
+
+/// rebuild_index crawls the root content and indexes every document found into a new, empty index,
+/// archived to a new part recorded like the ones of [update_index]
+pub fn rebuild_index(
+    bcc: &BitcodeContext,
+    config: &IndexerConfig,
+) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    index_content(bcc, config, true)
+}
+
+fn index_content(
+    bcc: &BitcodeContext,
+    config: &IndexerConfig,
+    rebuild: bool,
+) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    let mut state = IndexState::load(bcc)?;
+    let crawler = Crawler::new(bcc, "");
+    let latest = crawler.latest_hash(&config.fabric.root.content)?;
+    let current = if rebuild { None } else { state.current.clone() };
+    if let Some(cur) = &current {
+        if cur.root_hash == latest {
+            return Ok(json!({"status" : "unchanged", "part_hash" : cur.part_hash}));
+        }
+    }
+    let crawl = crawler.crawl(config)?;
+    let root_hash = crawl.stats["root"].as_str().unwrap_or(&latest).to_string();
+
+    // the objects linked from the root make up the documents
+    let docs: BTreeMap<String, (String, Value)> = crawl
+        .mdregistry
+        .values()
+        .filter(|e| e["depth"].as_u64().unwrap_or(0) > 0)
+        .filter_map(|e| {
+            Some((
+                e["id"].as_str()?.to_string(),
+                (e["hash"].as_str()?.to_string(), e["meta"].clone()),
+            ))
+        })
+        .collect();
+    let new_objects: BTreeMap<String, String> = docs
+        .iter()
+        .map(|(id, (hash, _))| (id.clone(), hash.clone()))
+        .collect();
+    let new_sorted: Vec<String> = crawl
+        .sorted_hashes
+        .iter()
+        .filter(|h| **h != root_hash)
+        .cloned()
+        .collect();
+    let (old_sorted, old_objects) = match &current {
+        Some(cur) => (cur.sorted_hashes.clone(), cur.objects.clone()),
+        None => (Vec::new(), BTreeMap::new()),
+    };
+    let plan = plan_update(&old_sorted, &old_objects, &new_sorted, &new_objects);
+    bcc.log_info(&format!(
+        "index update: added={} changed={} removed={} unchanged={}",
+        plan.added.len(),
+        plan.changed.len(),
+        plan.removed.len(),
+        plan.unchanged
+    ))?;
+
+    let fields = config.fields.clone();
+    let indexer = match &current {
+        Some(cur) => Indexer::restore(bcc, &bcc.request.q_info.hash, &cur.part_hash, fields)?,
+        None => Indexer::new(bcc, fields)?,
+    };
+    let mut writer = indexer.writer(bcc)?;
+    writer.transaction(|writer| {
+        for id in &plan.removed {
+            writer.remove(id)?;
+        }
+        for id in plan.added.iter().chain(&plan.changed) {
+            let (_, meta) = &docs[id];
+            writer.index(id, meta, &document_fields(meta, &indexer.fields))?;
+        }
+        Ok(())
+    })?;
+
+    let new_part = indexer.archive(bcc)?;
+    let vector_parts = index_vectors(bcc, config, current.as_ref(), &docs, &plan)?;
+    let now: SystemTimeResult = bcc.q_system_time().try_into()?;
+    state.push(PartRecord {
+        part_hash: new_part.clone(),
+        root_hash,
+        sorted_hashes: new_sorted,
+        objects: new_objects,
+        created: now.time,
+        vector_parts,
+    });
+    state.save(bcc)?;
+    Ok(json!({
+        "status" : "updated",
+        "part_hash" : new_part,
+        "plan" : plan,
+        "stats" : crawl.stats,
+    }))
+}
+
+// archives the vector index of each vector field to a part, updating the one of the current part by the
+// plan when its options are unchanged and indexing every document otherwise
+fn index_vectors(
+    bcc: &BitcodeContext,
+    config: &IndexerConfig,
+    current: Option<&PartRecord>,
+    docs: &BTreeMap<String, (String, Value)>,
+    plan: &UpdatePlan,
+) -> Result<BTreeMap<String, String>, Box<dyn Error + Send + Sync>> {
+    let mut parts = BTreeMap::new();
+    for (field, opts) in vector_fields(&config.fields)? {
+        let previous = match current.and_then(|c| c.vector_parts.get(&field.name)) {
+            Some(part) => Some(VectorIndex::load(bcc, &bcc.request.q_info.hash, part)?)
+                .filter(|index| *index.options() == opts),
+            None => None,
+        };
+        let (mut index, ids): (VectorIndex, Vec<&String>) = match previous {
+            Some(mut index) => {
+                for id in &plan.removed {
+                    index.remove(id);
+                }
+                (index, plan.added.iter().chain(&plan.changed).collect())
+            }
+            None => (VectorIndex::new(opts)?, docs.keys().collect()),
+        };
+        for id in ids {
+            let (_, meta) = &docs[id];
+            match document_vectors(meta, std::slice::from_ref(field)).get(&field.name) {
+                Some(v) => {
+                    if let Err(e) = index.insert(id, v) {
+                        bcc.log_warn(&format!("skipping {} vector of {id}: {e}", field.name))?;
+                        index.remove(id);
+                    }
+                }
+                None => {
+                    index.remove(id);
+                }
+            }
+        }
+        parts.insert(field.name.clone(), index.archive(bcc)?);
+    }
+    Ok(parts)
+}
+
+/// rollback_index makes the previous index part current again
+pub fn rollback_index(bcc: &BitcodeContext) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    let mut state = IndexState::load(bcc)?;
+    let dropped = match state.rollback() {
+        Some(d) => d,
+        None => {
+            return Err(Box::new(ErrorKinds::NotExist(
+                "no previous index part to roll back to".to_string(),
+            )))
+        }
+    };
+    state.save(bcc)?;
+    Ok(json!({
+        "status" : "rolled back",
+        "dropped" : dropped.part_hash,
+        "part_hash" : state.current.map(|c| c.part_hash),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn objects(pairs: &[(&str, &str)]) -> (Vec<String>, BTreeMap<String, String>) {
+        let map: BTreeMap<String, String> = pairs
+            .iter()
+            .map(|(id, h)| (id.to_string(), h.to_string()))
+            .collect();
+        let mut sorted: Vec<String> = map.values().cloned().collect();
+        sorted.sort();
+        (sorted, map)
+    }
+
+    #[test]
+    fn test_plan_update() {
+        let (old_sorted, old) = objects(&[
+            ("iq__a", "hq__a1"),
+            ("iq__b", "hq__b1"),
+            ("iq__c", "hq__c1"),
+        ]);
+        let (new_sorted, new) = objects(&[
+            ("iq__a", "hq__a1"),
+            ("iq__b", "hq__b2"),
+            ("iq__d", "hq__d1"),
+        ]);
+        let plan = plan_update(&old_sorted, &old, &new_sorted, &new);
+        assert_eq!(vec!["iq__d".to_string()], plan.added);
+        assert_eq!(vec!["iq__b".to_string()], plan.changed);
+        assert_eq!(vec!["iq__c".to_string()], plan.removed);
+        assert_eq!(1, plan.unchanged);
+        assert!(plan_update(&new_sorted, &new, &new_sorted, &new).is_empty());
+    }
+
+    #[test]
+    fn test_state_push_and_rollback() {
+        let mut state = IndexState::default();
+        assert_eq!(None, state.rollback());
+        for i in 0..MAX_PREVIOUS_PARTS + 2 {
+            state.push(PartRecord {
+                part_hash: format!("hqp_{i}"),
+                ..Default::default()
+            });
+        }
+        assert_eq!(MAX_PREVIOUS_PARTS, state.previous.len());
+        let dropped = state.rollback().unwrap();
+        assert_eq!(format!("hqp_{}", MAX_PREVIOUS_PARTS + 1), dropped.part_hash);
+        assert_eq!(
+            format!("hqp_{MAX_PREVIOUS_PARTS}"),
+            state.current.unwrap().part_hash
+        );
+    }
+}
@@ -0,0 +1,897 @@
+//! Vector similarity search over embeddings <br>
+//! Fields of type `vector` in an indexer configuration hold precomputed embeddings found at their paths, and
+//! stay out of the Tantivy schema. Each gets a [VectorIndex]: every vector with the id of its document, plus,
+//! for the `hnsw` index, a navigable small world graph for approximate search, `flat` indexes being searched
+//! exhaustively. The index is serialized to a compact binary form and archived to a part of the content,
+//! next to the Tantivy part, answering top-k cosine or dot product queries optionally restricted to the
+//! documents matching a keyword query.
+//! ```rust
+//! use elvwasm::search::{Metric, VectorIndex, VectorOptions};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   let mut index = VectorIndex::new(VectorOptions::hnsw(3, Metric::Cosine))?;
+//!   index.insert("iq__a", &[0.1, 0.9, 0.0])?;
+//!   index.insert("iq__b", &[0.8, 0.1, 0.1])?;
+//!   let part_hash = index.archive(bcc)?;
+//!   let index = VectorIndex::load(bcc, &bcc.request.q_info.hash, &part_hash)?;
+//!   let hits = index.search(&[0.0, 1.0, 0.0], 1, None)?;
+//!   Ok(serde_json::to_vec(&hits)?)
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{Extractor, FieldConfig, SearchRequest, Searcher, UID_FIELD};
+use crate::{BitcodeContext, CreatePartResult, ErrorKinds, NewStreamResult, WriteResult};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::io::Read;
+
+/// Type of the indexer configuration fields holding embeddings
+pub const VECTOR_FIELD_TYPE: &str = "vector";
+pub const DEFAULT_HNSW_M: usize = 16;
+pub const DEFAULT_EF_CONSTRUCTION: usize = 100;
+pub const DEFAULT_EF_SEARCH: usize = 64;
+/// Number of documents a vector search returns by default
+pub const DEFAULT_VECTOR_K: usize = 10;
+/// Most documents a keyword filter of a vector search may match
+pub const DEFAULT_FILTER_LIMIT: usize = 10000;
+
+const VECTOR_MAGIC: &[u8; 4] = b"ELVV";
+const VECTOR_FORMAT_VERSION: u8 = 1;
+// bytes written to a stream at once when archiving
+const ARCHIVE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How vectors are compared, the greater the closer
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// the cosine of the angle, vectors being normalized when indexed
+    Cosine,
+    /// the dot product of the vectors as given
+    Dot,
+}
+
+impl Default for Metric {
+    fn default() -> Self {
+        Metric::Cosine
+    }
+}
+
+/// The structure of a [VectorIndex]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexKind {
+    /// every vector is compared with the query, exact
+    Flat,
+    /// hierarchical navigable small world graph, approximate
+    Hnsw,
+}
+
+impl Default for VectorIndexKind {
+    fn default() -> Self {
+        VectorIndexKind::Flat
+    }
+}
+
+fn default_m() -> usize {
+    DEFAULT_HNSW_M
+}
+
+fn default_ef_construction() -> usize {
+    DEFAULT_EF_CONSTRUCTION
+}
+
+fn default_ef_search() -> usize {
+    DEFAULT_EF_SEARCH
+}
+
+/// The `options` of a vector field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorOptions {
+    pub dimensions: usize,
+    #[serde(default)]
+    pub metric: Metric,
+    #[serde(default)]
+    pub index: VectorIndexKind,
+    /// neighbors kept per node and graph layer, twice as many on the bottom layer
+    #[serde(default = "default_m")]
+    pub m: usize,
+    /// candidates considered when linking a new node
+    #[serde(default = "default_ef_construction")]
+    pub ef_construction: usize,
+    /// candidates considered when searching, at least the number of results asked for
+    #[serde(default = "default_ef_search")]
+    pub ef_search: usize,
+}
+
+impl VectorOptions {
+    pub fn flat(dimensions: usize, metric: Metric) -> VectorOptions {
+        VectorOptions {
+            dimensions,
+            metric,
+            index: VectorIndexKind::Flat,
+            m: DEFAULT_HNSW_M,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+            ef_search: DEFAULT_EF_SEARCH,
+        }
+    }
+
+    pub fn hnsw(dimensions: usize, metric: Metric) -> VectorOptions {
+        VectorOptions {
+            index: VectorIndexKind::Hnsw,
+            ..VectorOptions::flat(dimensions, metric)
+        }
+    }
+
+    /// from_config parses the options of a `vector` field
+    /// ```rust
+    /// use elvwasm::search::{FieldConfig, Metric, VectorIndexKind, VectorOptions};
+    /// let field: FieldConfig = serde_json::from_value(serde_json::json!({
+    ///   "name" : "embedding", "type" : "vector", "paths" : ["embeddings.clip"],
+    ///   "options" : {"dimensions" : 512, "metric" : "dot", "index" : "hnsw"},
+    /// })).unwrap();
+    /// let opts = VectorOptions::from_config(&field).unwrap();
+    /// assert_eq!((opts.dimensions, opts.metric, opts.index), (512, Metric::Dot, VectorIndexKind::Hnsw));
+    /// ```
+    pub fn from_config(field: &FieldConfig) -> Result<VectorOptions, Box<dyn Error + Send + Sync>> {
+        let opts: VectorOptions = serde_json::from_value(field.options.clone()).map_err(|e| {
+            ErrorKinds::Invalid(format!("bad options for vector field {}: {e}", field.name))
+        })?;
+        if opts.validate().is_err() {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector field {} needs dimensions, m of 2 or more and ef_construction",
+                field.name
+            ))));
+        }
+        Ok(opts)
+    }
+
+    /// validate checks the options can build an index: some dimensions, an `m` of 2 or more for the layers
+    /// of the graph to thin out, and some `ef_construction`
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.dimensions == 0 || self.m < 2 || self.ef_construction == 0 {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector index needs dimensions, m of 2 or more and ef_construction, got {}, {} and {}",
+                self.dimensions, self.m, self.ef_construction
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// vector_fields returns the configured `vector` fields along with their options
+pub fn vector_fields(
+    fields: &[FieldConfig],
+) -> Result<Vec<(&FieldConfig, VectorOptions)>, Box<dyn Error + Send + Sync>> {
+    fields
+        .iter()
+        .filter(|f| f.field_type == VECTOR_FIELD_TYPE)
+        .map(|f| Ok((f, VectorOptions::from_config(f)?)))
+        .collect()
+}
+
+/// document_vectors extracts the first array of numbers found at the paths of each vector field
+pub fn document_vectors(meta: &Value, fields: &[FieldConfig]) -> BTreeMap<String, Vec<f32>> {
+    let fields: Vec<FieldConfig> = fields
+        .iter()
+        .filter(|f| f.field_type == VECTOR_FIELD_TYPE)
+        .cloned()
+        .collect();
+    let mut out = BTreeMap::new();
+    for e in Extractor::new(&fields).extract(meta) {
+        if out.contains_key(&e.field) {
+            continue;
+        }
+        if let Some(v) = e.value.as_array().and_then(|a| {
+            a.iter()
+                .map(|x| x.as_f64().map(|x| x as f32))
+                .collect::<Option<Vec<f32>>>()
+        }) {
+            if !v.is_empty() {
+                out.insert(e.field, v);
+            }
+        }
+    }
+    out
+}
+
+/// A document found by a vector search
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VectorHit {
+    pub id: String,
+    pub score: f32,
+}
+
+// a node of the graph scored against a query, ordered by score
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Scored(f32, u32);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .partial_cmp(&other.0)
+            .unwrap_or(Ordering::Equal)
+            .then(other.1.cmp(&self.1))
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalize(v: &mut [f32]) {
+    let norm = dot(v, v).sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+// the layers of a hierarchical navigable small world graph, neighbors[node][layer]
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Hnsw {
+    entry: u32,
+    max_layer: usize,
+    neighbors: Vec<Vec<Vec<u32>>>,
+}
+
+impl Hnsw {
+    // the layer of a node, drawn from an exponential distribution seeded by the node so builds repeat
+    fn random_layer(node: u32, m: usize) -> usize {
+        let mut x = (node as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        let u = ((x >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-u.ln() / (m as f64).ln()).floor() as usize
+    }
+
+    fn build(vectors: &[f32], dimensions: usize, opts: &VectorOptions) -> Hnsw {
+        let mut graph = Hnsw::default();
+        let vector = |n: u32| &vectors[n as usize * dimensions..(n as usize + 1) * dimensions];
+        for node in 0..(vectors.len() / dimensions) as u32 {
+            graph.insert(node, &vector, opts);
+        }
+        graph
+    }
+
+    fn insert<'v, F>(&mut self, node: u32, vector: &F, opts: &VectorOptions)
+    where
+        F: Fn(u32) -> &'v [f32],
+    {
+        let layer = Hnsw::random_layer(node, opts.m);
+        self.neighbors.push(vec![Vec::new(); layer + 1]);
+        if node == 0 {
+            self.entry = 0;
+            self.max_layer = layer;
+            return;
+        }
+        let q = vector(node);
+        let mut entry = vec![Scored(dot(q, vector(self.entry)), self.entry)];
+        for l in (layer + 1..=self.max_layer).rev() {
+            entry = self.search_layer(q, entry, 1, l, vector);
+        }
+        for l in (0..=layer.min(self.max_layer)).rev() {
+            let found = self.search_layer(q, entry.clone(), opts.ef_construction, l, vector);
+            let max = if l == 0 { 2 * opts.m } else { opts.m };
+            let chosen: Vec<u32> = found.iter().take(opts.m).map(|s| s.1).collect();
+            for &n in &chosen {
+                let links = &mut self.neighbors[n as usize][l];
+                links.push(node);
+                if links.len() > max {
+                    let nv = vector(n);
+                    let mut scored: Vec<Scored> = links
+                        .iter()
+                        .map(|&o| Scored(dot(nv, vector(o)), o))
+                        .collect();
+                    scored.sort_by(|a, b| b.cmp(a));
+                    *links = scored.into_iter().take(max).map(|s| s.1).collect();
+                }
+            }
+            self.neighbors[node as usize][l] = chosen;
+            entry = found;
+        }
+        if layer > self.max_layer {
+            self.max_layer = layer;
+            self.entry = node;
+        }
+    }
+
+    // the `ef` nodes of a layer closest to q reachable from the entry points, closest first
+    fn search_layer<'v, F>(
+        &self,
+        q: &[f32],
+        entry: Vec<Scored>,
+        ef: usize,
+        layer: usize,
+        vector: &F,
+    ) -> Vec<Scored>
+    where
+        F: Fn(u32) -> &'v [f32],
+    {
+        let mut visited: HashSet<u32> = entry.iter().map(|s| s.1).collect();
+        let mut candidates: BinaryHeap<Scored> = entry.iter().copied().collect();
+        // the best found so far, the worst on top
+        let mut found: BinaryHeap<std::cmp::Reverse<Scored>> =
+            entry.into_iter().map(std::cmp::Reverse).collect();
+        while let Some(c) = candidates.pop() {
+            if let Some(worst) = found.peek() {
+                if found.len() >= ef && c.0 < worst.0 .0 {
+                    break;
+                }
+            }
+            let links = match self.neighbors[c.1 as usize].get(layer) {
+                Some(l) => l,
+                None => continue,
+            };
+            for &n in links {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let s = Scored(dot(q, vector(n)), n);
+                if found.len() < ef || s > found.peek().map(|w| w.0).unwrap_or(s) {
+                    candidates.push(s);
+                    found.push(std::cmp::Reverse(s));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut out: Vec<Scored> = found.into_iter().map(|r| r.0).collect();
+        out.sort_by(|a, b| b.cmp(a));
+        out
+    }
+}
+
+/// An index of the vectors of one field, see the [module documentation](self)
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorIndex {
+    options: VectorOptions,
+    ids: Vec<String>,
+    // the node of each id
+    positions: HashMap<String, usize>,
+    // the vectors one after the other, normalized for the cosine metric
+    vectors: Vec<f32>,
+    // built on demand for hnsw indexes, dropped when the vectors change
+    graph: Option<Hnsw>,
+}
+
+impl VectorIndex {
+    /// new creates an empty index, failing on options [VectorOptions::validate] rejects
+    pub fn new(options: VectorOptions) -> Result<VectorIndex, Box<dyn Error + Send + Sync>> {
+        options.validate()?;
+        Ok(VectorIndex {
+            options,
+            ids: Vec::new(),
+            positions: HashMap::new(),
+            vectors: Vec::new(),
+            graph: None,
+        })
+    }
+
+    pub fn options(&self) -> &VectorOptions {
+        &self.options
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.positions.contains_key(id)
+    }
+
+    fn vector(&self, node: usize) -> &[f32] {
+        let d = self.options.dimensions;
+        &self.vectors[node * d..(node + 1) * d]
+    }
+
+    // checks the dimensions of a vector and normalizes it for the cosine metric
+    fn prepare(&self, v: &[f32]) -> Result<Vec<f32>, Box<dyn Error + Send + Sync>> {
+        if v.len() != self.options.dimensions {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector has {} dimensions, the index {}",
+                v.len(),
+                self.options.dimensions
+            ))));
+        }
+        let mut v = v.to_vec();
+        if self.options.metric == Metric::Cosine {
+            normalize(&mut v);
+        }
+        Ok(v)
+    }
+
+    /// insert adds the vector of a document, replacing any it had
+    pub fn insert(&mut self, id: &str, v: &[f32]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let v = self.prepare(v)?;
+        let d = self.options.dimensions;
+        match self.positions.get(id) {
+            Some(&node) => self.vectors[node * d..(node + 1) * d].copy_from_slice(&v),
+            None => {
+                self.positions.insert(id.to_string(), self.ids.len());
+                self.ids.push(id.to_string());
+                self.vectors.extend(v);
+            }
+        }
+        self.graph = None;
+        Ok(())
+    }
+
+    /// remove drops the vector of a document, returning whether it had one. The last vector takes its place.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let node = match self.positions.remove(id) {
+            Some(n) => n,
+            None => return false,
+        };
+        let d = self.options.dimensions;
+        let last = self.ids.len() - 1;
+        self.ids.swap_remove(node);
+        if node != last {
+            self.vectors.copy_within(last * d..(last + 1) * d, node * d);
+            self.positions.insert(self.ids[node].clone(), node);
+        }
+        self.vectors.truncate(last * d);
+        self.graph = None;
+        true
+    }
+
+    /// build builds the graph of an hnsw index, done by [VectorIndex::to_bytes] when needed
+    pub fn build(&mut self) {
+        if self.options.index == VectorIndexKind::Hnsw && self.graph.is_none() && !self.is_empty() {
+            self.graph = Some(Hnsw::build(
+                &self.vectors,
+                self.options.dimensions,
+                &self.options,
+            ));
+        }
+    }
+
+    /// search returns the `k` documents closest to the query vector, only considering the ids of the filter
+    /// when given. The graph of an hnsw index is searched when built, falling back to an exact search when
+    /// the filter leaves fewer than `k` of the candidates found.
+    pub fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: Option<&HashSet<String>>,
+    ) -> Result<Vec<VectorHit>, Box<dyn Error + Send + Sync>> {
+        let q = self.prepare(query)?;
+        let allowed = |node: usize| match filter {
+            Some(f) => f.contains(&self.ids[node]),
+            None => true,
+        };
+        if k == 0 || self.is_empty() {
+            return Ok(Vec::new());
+        }
+        if let Some(graph) = &self.graph {
+            let vector = |n: u32| self.vector(n as usize);
+            let mut entry = vec![Scored(dot(&q, vector(graph.entry)), graph.entry)];
+            for l in (1..=graph.max_layer).rev() {
+                entry = graph.search_layer(&q, entry, 1, l, &vector);
+            }
+            let found = graph.search_layer(&q, entry, self.options.ef_search.max(k), 0, &vector);
+            let hits: Vec<VectorHit> = found
+                .into_iter()
+                .filter(|s| allowed(s.1 as usize))
+                .take(k)
+                .map(|s| VectorHit {
+                    id: self.ids[s.1 as usize].clone(),
+                    score: s.0,
+                })
+                .collect();
+            let wanted = filter.map_or(k, |f| k.min(f.len()));
+            if hits.len() >= wanted.min(self.len()) {
+                return Ok(hits);
+            }
+        }
+        let mut scored: Vec<Scored> = (0..self.len())
+            .filter(|&n| allowed(n))
+            .map(|n| Scored(dot(&q, self.vector(n)), n as u32))
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        Ok(scored
+            .into_iter()
+            .take(k)
+            .map(|s| VectorHit {
+                id: self.ids[s.1 as usize].clone(),
+                score: s.0,
+            })
+            .collect())
+    }
+
+    /// to_bytes serializes the index, building the graph of an hnsw index first
+    pub fn to_bytes(&mut self) -> Vec<u8> {
+        self.build();
+        let o = &self.options;
+        let mut out = Vec::with_capacity(32 + self.vectors.len() * 4);
+        out.extend_from_slice(VECTOR_MAGIC);
+        out.push(VECTOR_FORMAT_VERSION);
+        out.push(match o.metric {
+            Metric::Cosine => 0,
+            Metric::Dot => 1,
+        });
+        out.push(match o.index {
+            VectorIndexKind::Flat => 0,
+            VectorIndexKind::Hnsw => 1,
+        });
+        for n in [
+            o.dimensions,
+            o.m,
+            o.ef_construction,
+            o.ef_search,
+            self.ids.len(),
+        ] {
+            out.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        for id in &self.ids {
+            out.extend_from_slice(&(id.len() as u32).to_le_bytes());
+            out.extend_from_slice(id.as_bytes());
+        }
+        for x in &self.vectors {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        match &self.graph {
+            Some(g) => {
+                out.push(1);
+                out.extend_from_slice(&g.entry.to_le_bytes());
+                out.extend_from_slice(&(g.max_layer as u32).to_le_bytes());
+                for layers in &g.neighbors {
+                    out.push(layers.len() as u8);
+                    for links in layers {
+                        out.extend_from_slice(&(links.len() as u32).to_le_bytes());
+                        for l in links {
+                            out.extend_from_slice(&l.to_le_bytes());
+                        }
+                    }
+                }
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// from_bytes decodes an index serialized by [VectorIndex::to_bytes], checking the counts and node ids
+    /// it holds against its length
+    pub fn from_bytes(b: &[u8]) -> Result<VectorIndex, Box<dyn Error + Send + Sync>> {
+        let mut r = ByteReader { b, pos: 0 };
+        if r.take(4)? != VECTOR_MAGIC {
+            return Err(Box::new(ErrorKinds::Invalid(
+                "not a vector index".to_string(),
+            )));
+        }
+        let version = r.u8()?;
+        if version != VECTOR_FORMAT_VERSION {
+            return Err(Box::new(ErrorKinds::NotImplemented(format!(
+                "vector index format version {version}"
+            ))));
+        }
+        let metric = match r.u8()? {
+            0 => Metric::Cosine,
+            _ => Metric::Dot,
+        };
+        let index = match r.u8()? {
+            0 => VectorIndexKind::Flat,
+            _ => VectorIndexKind::Hnsw,
+        };
+        let options = VectorOptions {
+            dimensions: r.u32()? as usize,
+            metric,
+            index,
+            m: r.u32()? as usize,
+            ef_construction: r.u32()? as usize,
+            ef_search: r.u32()? as usize,
+        };
+        options.validate()?;
+        // every id takes at least its 4 byte length
+        let count = r.u32()? as usize;
+        if count > r.remaining() / 4 {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector index of {count} ids is longer than its {} bytes",
+                b.len()
+            ))));
+        }
+        let mut ids = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = r.u32()? as usize;
+            ids.push(String::from_utf8(r.take(len)?.to_vec())?);
+        }
+        let floats = count
+            .checked_mul(options.dimensions)
+            .filter(|n| *n <= r.remaining() / 4)
+            .ok_or_else(|| {
+                ErrorKinds::Invalid(format!(
+                    "vector index of {count} vectors of {} dimensions is longer than its {} bytes",
+                    options.dimensions,
+                    b.len()
+                ))
+            })?;
+        let mut vectors = Vec::with_capacity(floats);
+        for _ in 0..floats {
+            vectors.push(f32::from_le_bytes(r.take(4)?.try_into()?));
+        }
+        let graph = match r.u8()? {
+            0 => None,
+            _ => {
+                let entry = r.node(count)?;
+                // the layers of a node are counted in a byte
+                let max_layer = r.u32()? as usize;
+                if max_layer > u8::MAX as usize {
+                    return Err(Box::new(ErrorKinds::Invalid(format!(
+                        "vector index graph has {max_layer} layers"
+                    ))));
+                }
+                let mut neighbors = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let layers = r.u8()? as usize;
+                    let mut node = Vec::with_capacity(layers);
+                    for _ in 0..layers {
+                        let n = r.u32()? as usize;
+                        let mut links = Vec::with_capacity(n.min(r.remaining() / 4));
+                        for _ in 0..n {
+                            links.push(r.node(count)?);
+                        }
+                        node.push(links);
+                    }
+                    neighbors.push(node);
+                }
+                Some(Hnsw {
+                    entry,
+                    max_layer,
+                    neighbors,
+                })
+            }
+        };
+        Ok(VectorIndex {
+            options,
+            positions: ids
+                .iter()
+                .enumerate()
+                .map(|(n, id)| (id.clone(), n))
+                .collect(),
+            ids,
+            vectors,
+            graph,
+        })
+    }
+
+    /// archive writes the serialized index to a new part of the content being written, returning the part
+    /// hash
+    pub fn archive(
+        &mut self,
+        bcc: &BitcodeContext,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let bytes = self.to_bytes();
+        let stream: NewStreamResult = bcc.new_stream().try_into()?;
+        let sid = stream.stream_id.clone();
+        defer! {
+          let _ = bcc.close_stream(sid.clone());
+        }
+        for chunk in bytes.chunks(ARCHIVE_CHUNK_SIZE) {
+            let wr: WriteResult = bcc.write_stream(&stream.stream_id, chunk).try_into()?;
+            if wr.written != chunk.len() {
+                return Err(Box::new(ErrorKinds::IO(format!(
+                    "short write archiving vector index, wrote {} of {}",
+                    wr.written,
+                    chunk.len()
+                ))));
+            }
+        }
+        let part: CreatePartResult = bcc
+            .q_create_part_from_stream(&bcc.request.q_info.write_token, &stream.stream_id)
+            .try_into()?;
+        Ok(part.qphash)
+    }
+
+    /// load reads back an index archived to a part of a content
+    pub fn load(
+        bcc: &BitcodeContext,
+        content_hash: &str,
+        part_hash: &str,
+    ) -> Result<VectorIndex, Box<dyn Error + Send + Sync>> {
+        let stream: NewStreamResult = bcc.new_stream().try_into()?;
+        let sid = stream.stream_id.clone();
+        defer! {
+          let _ = bcc.close_stream(sid.clone());
+        }
+        bcc.write_part_to_stream(
+            stream.stream_id.clone(),
+            part_hash.to_string(),
+            content_hash.to_string(),
+            0,
+            -1,
+            false,
+        )?;
+        let mut bytes = Vec::new();
+        bcc.stream_reader(&stream.stream_id)
+            .read_to_end(&mut bytes)?;
+        VectorIndex::from_bytes(&bytes)
+    }
+}
+
+// reads the fields of a serialized vector index
+struct ByteReader<'a> {
+    b: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn remaining(&self) -> usize {
+        self.b.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error + Send + Sync>> {
+        if n > self.remaining() {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector index truncated at byte {}",
+                self.pos
+            ))));
+        }
+        let s = &self.b[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error + Send + Sync>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    // a node of a graph over `count` vectors
+    fn node(&mut self, count: usize) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let pos = self.pos;
+        let n = self.u32()?;
+        if n as usize >= count {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "vector index links node {n} of {count} at byte {pos}"
+            ))));
+        }
+        Ok(n)
+    }
+}
+
+/// keyword_filter returns the ids of the documents matching a keyword search, to restrict a vector search
+/// to, up to the request's limit
+pub fn keyword_filter(
+    searcher: &Searcher,
+    req: &SearchRequest,
+) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    Ok(searcher
+        .search(req)?
+        .hits
+        .iter()
+        .filter_map(|h| h.text(UID_FIELD).map(|s| s.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // points spread around the unit circle in two dimensions, with a third constant one
+    fn circle(index: &mut VectorIndex, n: usize) {
+        for i in 0..n {
+            let a = i as f32 * std::f32::consts::TAU / n as f32;
+            index
+                .insert(&format!("iq__{i}"), &[a.cos(), a.sin(), 0.5])
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_vector_search() {
+        for opts in [
+            VectorOptions::flat(3, Metric::Cosine),
+            VectorOptions::hnsw(3, Metric::Cosine),
+        ] {
+            let mut index = VectorIndex::new(VectorOptions { m: 4, ..opts }).unwrap();
+            circle(&mut index, 200);
+            let decoded = VectorIndex::from_bytes(&index.to_bytes()).unwrap();
+            assert_eq!(decoded, index);
+            assert_eq!(decoded.graph.is_some(), opts.index == VectorIndexKind::Hnsw);
+            let hits = decoded.search(&[1.0, 0.0, 0.5], 3, None).unwrap();
+            let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+            assert_eq!(ids[0], "iq__0");
+            assert!(ids[1..].contains(&"iq__1") && ids[1..].contains(&"iq__199"));
+            assert!((hits[0].score - 1.0).abs() < 1e-5);
+            let filter: HashSet<String> = ["iq__100", "iq__50"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let hits = decoded.search(&[1.0, 0.0, 0.5], 5, Some(&filter)).unwrap();
+            let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+            assert_eq!(ids, vec!["iq__50", "iq__100"]);
+        }
+    }
+
+    #[test]
+    fn test_vector_index_updates() {
+        let mut index = VectorIndex::new(VectorOptions::flat(2, Metric::Dot)).unwrap();
+        index.insert("a", &[1.0, 0.0]).unwrap();
+        index.insert("b", &[2.0, 0.0]).unwrap();
+        index.insert("a", &[3.0, 0.0]).unwrap();
+        assert_eq!(index.len(), 2);
+        let hits = index.search(&[1.0, 0.0], 1, None).unwrap();
+        assert_eq!(
+            hits,
+            vec![VectorHit {
+                id: "a".to_string(),
+                score: 3.0
+            }]
+        );
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+        assert!(index.insert("c", &[1.0]).is_err());
+        assert!(VectorIndex::from_bytes(b"ELVV").is_err());
+        assert!(VectorIndex::from_bytes(&index.to_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    fn test_vector_index_rejects_bad_options_and_bytes() {
+        let opts = VectorOptions {
+            m: 1,
+            ..VectorOptions::hnsw(3, Metric::Cosine)
+        };
+        assert!(VectorIndex::new(opts).is_err());
+        assert!(VectorIndex::new(VectorOptions::flat(0, Metric::Dot)).is_err());
+
+        let mut index = VectorIndex::new(VectorOptions::hnsw(3, Metric::Cosine)).unwrap();
+        circle(&mut index, 20);
+        let bytes = index.to_bytes();
+        let ids: usize = index.ids.iter().map(|id| 4 + id.len()).sum();
+        let entry = 27 + ids + index.vectors.len() * 4 + 1;
+        let corrupt = |at: usize, v: u32| {
+            let mut b = bytes.clone();
+            b[at..at + 4].copy_from_slice(&v.to_le_bytes());
+            VectorIndex::from_bytes(&b)
+        };
+        assert!(VectorIndex::from_bytes(&bytes).is_ok());
+        assert!(corrupt(23, u32::MAX).is_err());
+        assert!(corrupt(7, u32::MAX).is_err());
+        assert!(corrupt(11, 1).is_err());
+        assert!(corrupt(entry, 20).is_err());
+        assert!(corrupt(entry + 4, 1000).is_err());
+        assert!(corrupt(bytes.len() - 4, 1000).is_err());
+    }
+
+    #[test]
+    fn test_document_vectors() {
+        let fields: Vec<FieldConfig> = serde_json::from_value(json!([
+            {"name" : "embedding", "type" : "vector", "paths" : ["embeddings.clip"],
+             "options" : {"dimensions" : 3}},
+            {"name" : "title", "type" : "text", "paths" : ["title"], "options" : {}},
+        ]))
+        .unwrap();
+        let meta = json!({"title" : "Sea", "embeddings" : {"clip" : [0.5, 1, -2]}});
+        let vectors = document_vectors(&meta, &fields);
+        assert_eq!(vectors.len(), 1);
+        assert_eq!(vectors["embedding"], vec![0.5, 1.0, -2.0]);
+        let opts = vector_fields(&fields).unwrap();
+        assert_eq!(opts.len(), 1);
+        assert_eq!(opts[0].1, VectorOptions::flat(3, Metric::Cosine));
+    }
+}
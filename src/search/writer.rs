@@ -0,0 +1,548 @@
+//! Typed documents and index writer <br>
+//! An [IndexWriter] owns the host side writer of an index whose [Schema] has been built, hands out
+//! [Document]s that accept values typed by the schema's fields and commits them in batches.
+//! ```rust
+//! use elvwasm::search::{FieldValue, IndexWriter, NumericOptions, SchemaBuilder, TextOptions};
+//! fn do_something<'s>(bcc: &'s mut elvwasm::BitcodeContext) -> wapc_guest::CallResult {
+//!   bcc.new_index_builder(serde_json::json!({}))?;
+//!   let schema = SchemaBuilder::new(bcc)
+//!     .text("id", TextOptions::string().stored())
+//!     .text("title", TextOptions::text().stored())
+//!     .u64("year", NumericOptions::indexed().stored().fast())
+//!     .build()?;
+//!   let mut writer = IndexWriter::new(bcc, &schema)?;
+//!   let added = writer.transaction(|w| {
+//!     w.delete_term("id", &FieldValue::text("iq__abc"))?;
+//!     let mut doc = w.document()?;
+//!     doc.add_text("id", "iq__abc")?
+//!       .add_text("title", "The Old Man and the Sea")?
+//!       .add_u64("year", 1952)?;
+//!     w.add_document(doc)
+//!   })?;
+//!   Ok(added.to_string().into_bytes())
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate thiserror;
+extern crate wapc_guest as guest;
+
+use crate::search::{reply_u64, FieldType, Schema};
+use crate::{BitcodeContext, ErrorKinds};
+
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Number of documents an [IndexWriter] adds before committing on its own
+pub const DEFAULT_WRITER_BATCH_SIZE: usize = 1000;
+
+/// A value for one field of a [Document]
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    /// seconds since the epoch
+    Date(i64),
+    Bool(bool),
+    /// a facet path such as `/genre/drama`
+    Facet(String),
+    Bytes(Vec<u8>),
+    Json(Value),
+}
+
+impl FieldValue {
+    pub fn text(s: &str) -> FieldValue {
+        FieldValue::Text(s.to_string())
+    }
+
+    /// from_json converts a metadata value into the kind of value the field accepts
+    /// # Arguments
+    /// * `field_type` : the type of the target field
+    /// * `v` : the metadata value; strings are parsed for numeric, boolean and date fields, and dates may also
+    ///   be given as seconds since the epoch
+    /// ```rust
+    /// use elvwasm::search::{FieldType, FieldValue, NumericOptions};
+    /// let v = FieldValue::from_json(&FieldType::Date(NumericOptions::indexed()), &serde_json::json!("2023-01-01")).unwrap();
+    /// assert_eq!(v, FieldValue::Date(1672531200));
+    /// ```
+    pub fn from_json(
+        field_type: &FieldType,
+        v: &Value,
+    ) -> Result<FieldValue, Box<dyn Error + Send + Sync>> {
+        let invalid = || -> Box<dyn Error + Send + Sync> {
+            Box::new(ErrorKinds::Invalid(format!(
+                "value {v} does not fit a {} field",
+                field_type.name()
+            )))
+        };
+        let parsed = |v: &Value| v.as_str().and_then(|s| s.trim().parse::<f64>().ok());
+        let fv = match field_type {
+            FieldType::Text(_) => match v {
+                Value::String(s) => FieldValue::Text(s.clone()),
+                Value::Null | Value::Array(_) | Value::Object(_) => return Err(invalid()),
+                _ => FieldValue::Text(v.to_string()),
+            },
+            FieldType::Json(_) => match v {
+                Value::Object(_) => FieldValue::Json(v.clone()),
+                _ => return Err(invalid()),
+            },
+            FieldType::U64(_) => FieldValue::U64(
+                v.as_u64()
+                    .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+                    .ok_or_else(invalid)?,
+            ),
+            FieldType::I64(_) => FieldValue::I64(
+                v.as_i64()
+                    .or_else(|| v.as_str().and_then(|s| s.trim().parse().ok()))
+                    .ok_or_else(invalid)?,
+            ),
+            FieldType::F64(_) => {
+                FieldValue::F64(v.as_f64().or_else(|| parsed(v)).ok_or_else(invalid)?)
+            }
+            FieldType::Date(_) => FieldValue::Date(
+                v.as_i64()
+                    .or_else(|| v.as_str().and_then(parse_date))
+                    .ok_or_else(invalid)?,
+            ),
+            FieldType::Bool(_) => FieldValue::Bool(match v {
+                Value::Bool(b) => *b,
+                Value::String(s) if s == "true" => true,
+                Value::String(s) if s == "false" => false,
+                _ => return Err(invalid()),
+            }),
+            FieldType::Facet { .. } => match v.as_str() {
+                Some(s) if s.starts_with('/') => FieldValue::Facet(s.to_string()),
+                Some(s) => FieldValue::Facet(format!("/{s}")),
+                None => return Err(invalid()),
+            },
+            FieldType::Bytes(_) => match v.as_str() {
+                Some(s) => FieldValue::Bytes(general_purpose::STANDARD.decode(s)?),
+                None => return Err(invalid()),
+            },
+        };
+        Ok(fv)
+    }
+
+    /// fits reports whether the value can be added to a field of the given type
+    pub fn fits(&self, field_type: &FieldType) -> bool {
+        matches!(
+            (self, field_type),
+            (FieldValue::Text(_), FieldType::Text(_))
+                | (FieldValue::U64(_), FieldType::U64(_))
+                | (FieldValue::I64(_), FieldType::I64(_))
+                | (FieldValue::F64(_), FieldType::F64(_))
+                | (FieldValue::Date(_), FieldType::Date(_))
+                | (FieldValue::Bool(_), FieldType::Bool(_))
+                | (FieldValue::Facet(_), FieldType::Facet { .. })
+                | (FieldValue::Bytes(_), FieldType::Bytes(_))
+                | (FieldValue::Json(_), FieldType::Json(_))
+        )
+    }
+
+    /// to_json renders the value as the host expects it
+    pub fn to_json(&self) -> Value {
+        match self {
+            FieldValue::Text(s) | FieldValue::Facet(s) => json!(s),
+            FieldValue::U64(n) => json!(n),
+            FieldValue::I64(n) | FieldValue::Date(n) => json!(n),
+            FieldValue::F64(n) => json!(n),
+            FieldValue::Bool(b) => json!(b),
+            FieldValue::Bytes(b) => json!(general_purpose::STANDARD.encode(b)),
+            FieldValue::Json(v) => v.clone(),
+        }
+    }
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// parse_date parses an RFC 3339 timestamp, or a bare `YYYY-MM-DD` date, into seconds since the epoch
+/// ```rust
+/// assert_eq!(elvwasm::search::parse_date("2023-01-01T01:00:00+01:00"), Some(1672531200));
+/// assert_eq!(elvwasm::search::parse_date("2023-13-01"), None);
+/// ```
+pub fn parse_date(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let num = |r: std::ops::Range<usize>| -> Option<i64> {
+        let part = s.get(r)?;
+        if part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (y, m, d) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    if s.get(4..5)? != "-"
+        || s.get(7..8)? != "-"
+        || !(1..=12).contains(&m)
+        || !(1..=31).contains(&d)
+    {
+        return None;
+    }
+    let mut secs = days_from_civil(y, m, d) * 86400;
+    if s.len() == 10 {
+        return Some(secs);
+    }
+    if !matches!(s.get(10..11)?, "T" | "t" | " ") || s.get(13..14)? != ":" {
+        return None;
+    }
+    let (hh, mm) = (num(11..13)?, num(14..16)?);
+    let mut rest = 16;
+    let mut ss = 0;
+    if s.get(16..17) == Some(":") {
+        ss = num(17..19)?;
+        rest = 19;
+        if s.get(rest..rest + 1) == Some(".") {
+            rest += 1;
+            while s
+                .get(rest..rest + 1)
+                .map_or(false, |c| c.as_bytes()[0].is_ascii_digit())
+            {
+                rest += 1;
+            }
+        }
+    }
+    secs += hh * 3600 + mm * 60 + ss;
+    match s.get(rest..)? {
+        "" | "Z" | "z" => Some(secs),
+        tz if tz.len() == 6 && tz.get(3..4) == Some(":") => {
+            let sign = match &tz[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let oh: i64 = tz.get(1..3)?.parse().ok()?;
+            let om: i64 = tz.get(4..6)?.parse().ok()?;
+            Some(secs - sign * (oh * 3600 + om * 60))
+        }
+        _ => None,
+    }
+}
+
+/// A document being assembled on the host, see [IndexWriter::document]
+pub struct Document<'a> {
+    bcc: &'a BitcodeContext,
+    schema: &'a Schema,
+    doc_id: u64,
+    values: usize,
+}
+
+impl<'a> Document<'a> {
+    /// id returns the host side handle of the document
+    pub fn id(&self) -> u64 {
+        self.doc_id
+    }
+
+    /// is_empty reports whether no value has been added yet
+    pub fn is_empty(&self) -> bool {
+        self.values == 0
+    }
+
+    /// add adds a value to the named field, which must exist in the schema with a matching type
+    pub fn add(
+        &mut self,
+        name: &str,
+        value: FieldValue,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        let (entry, field) = match (self.schema.entry(name), self.schema.field(name)) {
+            (Some(e), Some(f)) => (e, f),
+            _ => {
+                return Err(Box::new(ErrorKinds::NotExist(format!(
+                    "field {name} is not in the schema"
+                ))))
+            }
+        };
+        if !value.fits(&entry.field_type) {
+            return Err(Box::new(ErrorKinds::Invalid(format!(
+                "value {value:?} does not fit {} field {name}",
+                entry.field_type.name()
+            ))));
+        }
+        let params = Some(json!({
+            "field" : field,
+            "value" : value.to_json(),
+            "doc_id" : self.doc_id,
+        }));
+        match value {
+            FieldValue::Text(_) => self.bcc.document_add_text(params)?,
+            FieldValue::U64(_) => self.bcc.document_add_u64(params)?,
+            FieldValue::I64(_) => self.bcc.document_add_i64(params)?,
+            FieldValue::F64(_) => self.bcc.document_add_f64(params)?,
+            FieldValue::Date(_) => self.bcc.document_add_date(params)?,
+            FieldValue::Bool(_) => self.bcc.document_add_bool(params)?,
+            FieldValue::Facet(_) => self.bcc.document_add_facet(params)?,
+            FieldValue::Bytes(_) => self.bcc.document_add_bytes(params)?,
+            FieldValue::Json(_) => self.bcc.document_add_json(params)?,
+        };
+        self.values += 1;
+        Ok(self)
+    }
+
+    /// add_json converts a metadata value for the named field with [FieldValue::from_json] and adds it; the
+    /// elements of an array (other than for a JSON field) are added as separate values
+    pub fn add_json(
+        &mut self,
+        name: &str,
+        v: &Value,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        let field_type = match self.schema.entry(name) {
+            Some(e) => e.field_type.clone(),
+            None => {
+                return Err(Box::new(ErrorKinds::NotExist(format!(
+                    "field {name} is not in the schema"
+                ))))
+            }
+        };
+        match v {
+            Value::Array(a) if !matches!(field_type, FieldType::Json(_)) => {
+                for elem in a {
+                    self.add(name, FieldValue::from_json(&field_type, elem)?)?;
+                }
+                Ok(self)
+            }
+            _ => self.add(name, FieldValue::from_json(&field_type, v)?),
+        }
+    }
+
+    pub fn add_text(
+        &mut self,
+        name: &str,
+        s: &str,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::text(s))
+    }
+
+    pub fn add_u64(
+        &mut self,
+        name: &str,
+        n: u64,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::U64(n))
+    }
+
+    pub fn add_i64(
+        &mut self,
+        name: &str,
+        n: i64,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::I64(n))
+    }
+
+    pub fn add_f64(
+        &mut self,
+        name: &str,
+        n: f64,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::F64(n))
+    }
+
+    /// add_date adds a date given in seconds since the epoch
+    pub fn add_date(
+        &mut self,
+        name: &str,
+        secs: i64,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::Date(secs))
+    }
+
+    pub fn add_bool(
+        &mut self,
+        name: &str,
+        b: bool,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::Bool(b))
+    }
+
+    pub fn add_facet(
+        &mut self,
+        name: &str,
+        path: &str,
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::Facet(path.to_string()))
+    }
+
+    pub fn add_bytes(
+        &mut self,
+        name: &str,
+        b: &[u8],
+    ) -> Result<&mut Document<'a>, Box<dyn Error + Send + Sync>> {
+        self.add(name, FieldValue::Bytes(b.to_vec()))
+    }
+}
+
+/// IndexWriter adds, deletes and commits documents, see the [module documentation](self)
+pub struct IndexWriter<'a> {
+    bcc: &'a BitcodeContext,
+    schema: &'a Schema,
+    batch_size: usize,
+    pending: usize,
+    committed: usize,
+}
+
+impl<'a> IndexWriter<'a> {
+    /// new creates the index from the built schema and opens its writer
+    pub fn new(
+        bcc: &'a BitcodeContext,
+        schema: &'a Schema,
+    ) -> Result<IndexWriter<'a>, Box<dyn Error + Send + Sync>> {
+        bcc.document_create_index(None)?;
+        bcc.index_create_writer(None)?;
+        Ok(IndexWriter {
+            bcc,
+            schema,
+            batch_size: DEFAULT_WRITER_BATCH_SIZE,
+            pending: 0,
+            committed: 0,
+        })
+    }
+
+    /// batch_size sets how many documents are added between automatic commits
+    pub fn batch_size(mut self, n: usize) -> IndexWriter<'a> {
+        self.batch_size = n.max(1);
+        self
+    }
+
+    pub fn schema(&self) -> &'a Schema {
+        self.schema
+    }
+
+    /// pending returns the number of documents added since the last commit
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// committed returns the number of documents committed by this writer
+    pub fn committed(&self) -> usize {
+        self.committed
+    }
+
+    /// document creates an empty document on the host
+    pub fn document(&self) -> Result<Document<'a>, Box<dyn Error + Send + Sync>> {
+        let res = self.bcc.document_create(None)?;
+        Ok(Document {
+            bcc: self.bcc,
+            schema: self.schema,
+            doc_id: reply_u64(&res, "document-create-id")?,
+            values: 0,
+        })
+    }
+
+    /// add_document hands the document to the writer, committing once a batch is full
+    pub fn add_document(&mut self, doc: Document<'a>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.bcc
+            .index_add_document(Some(json!({ "document_id": doc.doc_id })))?;
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// delete_term deletes every document holding the value in the named field, typically a unique id
+    pub fn delete_term(
+        &mut self,
+        name: &str,
+        value: &FieldValue,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let field = self
+            .schema
+            .field(name)
+            .ok_or_else(|| ErrorKinds::NotExist(format!("field {name} is not in the schema")))?;
+        self.bcc.index_writer_delete_term(Some(json!({
+            "field" : field,
+            "value" : value.to_json(),
+        })))?;
+        Ok(())
+    }
+
+    /// commit makes the pending changes durable, returning the number of documents committed
+    pub fn commit(&mut self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        self.bcc.index_writer_commit(None)?;
+        let n = self.pending;
+        self.committed += n;
+        self.pending = 0;
+        Ok(n)
+    }
+
+    /// rollback discards the changes made since the last commit
+    pub fn rollback(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.bcc.index_writer_rollback(None)?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// transaction runs f and commits, or rolls back and returns f's error should it fail.  Batches
+    /// committed by f before the failure are not rolled back.
+    /// # Returns
+    /// the number of documents committed by the final commit
+    pub fn transaction<F>(&mut self, f: F) -> Result<usize, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&mut IndexWriter<'a>) -> Result<(), Box<dyn Error + Send + Sync>>,
+    {
+        match f(self) {
+            Ok(()) => self.commit(),
+            Err(e) => Err(self.rollback_after(e)),
+        }
+    }
+
+    // rolls back after a failed transaction, returning its error along with the rollback's should that fail
+    // too
+    pub(crate) fn rollback_after(
+        &mut self,
+        e: Box<dyn Error + Send + Sync>,
+    ) -> Box<dyn Error + Send + Sync> {
+        match self.rollback() {
+            Ok(()) => e,
+            Err(re) => Box::new(ErrorKinds::Other(format!("{e}; rollback failed: {re}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{NumericOptions, TextOptions};
+
+    #[test]
+    fn test_field_value_from_json() {
+        let u = FieldType::U64(NumericOptions::indexed());
+        assert_eq!(
+            FieldValue::from_json(&u, &json!("42")).unwrap(),
+            FieldValue::U64(42)
+        );
+        assert!(FieldValue::from_json(&u, &json!(-1)).is_err());
+        let t = FieldType::Text(TextOptions::string());
+        assert_eq!(
+            FieldValue::from_json(&t, &json!(7)).unwrap(),
+            FieldValue::text("7")
+        );
+        let f = FieldType::Facet { stored: true };
+        assert_eq!(
+            FieldValue::from_json(&f, &json!("genre/drama")).unwrap(),
+            FieldValue::Facet("/genre/drama".to_string())
+        );
+        assert!(FieldValue::U64(1).fits(&u) && !FieldValue::I64(1).fits(&u));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-02-29T12:30:15Z"), Some(951827415));
+        assert_eq!(parse_date("2000-02-29T12:30:15.250-02:00"), Some(951834615));
+        assert_eq!(parse_date("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse_date("2000-02-29T12"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+}